@@ -1,4 +1,4 @@
-use criterion::{Criterion, criterion_group, criterion_main};
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
 use signal_auditor::proto::transparency;
 use signal_auditor::transparency::TransparencyLog;
 use std::fs;
@@ -48,13 +48,207 @@ mod updates {
         group.finish();
     }
 
+    /// Compare computing the log root after every update against
+    /// computing it once at the end of a batch, for varying batch sizes.
+    fn benchmark_batch_application(c: &mut Criterion) {
+        let test_vectors = load_test_vectors();
+        let updates: Vec<transparency::AuditorUpdate> = test_vectors
+            .should_succeed
+            .expect("No should_succeed test vectors found")
+            .updates
+            .into_iter()
+            .map(|x| x.update.unwrap())
+            .collect();
+
+        let mut group = c.benchmark_group("batch_application");
+
+        let mut batch_sizes = vec![1, 10, 100];
+        batch_sizes.retain(|&size| size < updates.len());
+        batch_sizes.push(updates.len());
+
+        for batch_size in batch_sizes {
+            let batch = &updates[..batch_size];
+            group.throughput(Throughput::Elements(batch_size as u64));
+
+            group.bench_with_input(
+                BenchmarkId::new("per_update_root", batch_size),
+                batch,
+                |b, batch| {
+                    b.iter_batched(
+                        || batch.to_vec(),
+                        |batch| {
+                            let mut log = TransparencyLog::new();
+                            for update in batch {
+                                log.apply_update(update).unwrap();
+                                black_box(log.log_root().unwrap());
+                            }
+                        },
+                        BatchSize::SmallInput,
+                    );
+                },
+            );
+
+            group.bench_with_input(
+                BenchmarkId::new("batch_root", batch_size),
+                batch,
+                |b, batch| {
+                    b.iter_batched(
+                        || batch.to_vec(),
+                        |batch| {
+                            let mut log = TransparencyLog::new();
+                            for update in batch {
+                                log.apply_update(update).unwrap();
+                            }
+                            black_box(log.log_root().unwrap());
+                        },
+                        BatchSize::SmallInput,
+                    );
+                },
+            );
+
+            group.bench_with_input(
+                BenchmarkId::new("parallel_batch_root", batch_size),
+                batch,
+                |b, batch| {
+                    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+                    let log = TransparencyLog::new();
+                    b.iter(|| {
+                        let verified = log.verify_batch_parallel(batch, threads).unwrap();
+                        black_box(verified.log_root().unwrap());
+                    });
+                },
+            );
+        }
+
+        group.finish();
+    }
+
+    criterion_group!(
+        name = benches;
+        config = Criterion::default()
+            .sample_size(100)
+            .measurement_time(std::time::Duration::from_secs(10))
+            .warm_up_time(std::time::Duration::from_secs(3));
+        targets = benchmark_sequential_log_updates, benchmark_batch_application
+    );
+}
+
+/// Worst-case prefix tree updates, where the non-inclusion proof
+/// terminates close to the bottom of the tree.
+mod copaths {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use transparency::auditor_proof::{DifferentKey, NewTree, Proof};
+    use transparency::{AuditorProof, AuditorUpdate};
+
+    const SEED: [u8; 16] = [7u8; 16];
+
+    fn leaf_hash(index: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update([0x00]);
+        hasher.update(index);
+        hasher.update(0u32.to_be_bytes());
+        hasher.update(0u64.to_be_bytes());
+        hasher.finalize().into()
+    }
+
+    fn stand_in_hash(seed: &[u8; 16], level: u8) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update([0x02]);
+        hasher.update(seed);
+        hasher.update([level]);
+        hasher.finalize().into()
+    }
+
+    /// A log containing a single leaf at the zero index.
+    fn single_leaf_log() -> TransparencyLog {
+        let mut log = TransparencyLog::new();
+        log.apply_update(AuditorUpdate {
+            real: true,
+            index: vec![0u8; 32],
+            seed: SEED.to_vec(),
+            commitment: vec![0u8; 32],
+            proof: Some(AuditorProof {
+                proof: Some(Proof::NewTree(NewTree {})),
+            }),
+        })
+        .unwrap();
+        log
+    }
+
+    /// A DifferentKey update for an index that shares its first `depth - 1`
+    /// bits with the zero index, giving a copath of length `depth`.
+    fn deep_update(depth: usize, real: bool) -> AuditorUpdate {
+        let bit = depth - 1;
+        let mut index = [0u8; 32];
+        index[bit / 8] |= 0x80 >> (bit % 8);
+
+        // Above the divergence point the copath is the stand-in hashes from the
+        // initial insertion; at the divergence point it is the subtree containing
+        // the existing leaf.
+        let mut copath: Vec<Vec<u8>> = (0..bit)
+            .map(|level| stand_in_hash(&SEED, level as u8).to_vec())
+            .collect();
+        copath.push(subtree_root(depth));
+
+        AuditorUpdate {
+            real,
+            index: index.to_vec(),
+            seed: [9u8; 16].to_vec(),
+            commitment: vec![0u8; 32],
+            proof: Some(AuditorProof {
+                proof: Some(Proof::DifferentKey(DifferentKey {
+                    copath,
+                    old_seed: SEED.to_vec(),
+                })),
+            }),
+        }
+    }
+
+    /// The root of the subtree at `depth` along the path to the zero-index leaf.
+    /// At depth 256 this is the leaf itself.
+    fn subtree_root(depth: usize) -> Vec<u8> {
+        let mut node = leaf_hash(&[0u8; 32]);
+        for level in (depth..256).rev() {
+            let mut hasher = Sha256::new();
+            hasher.update([0x01]);
+            hasher.update(node);
+            hasher.update(stand_in_hash(&SEED, level as u8));
+            node = hasher.finalize().into();
+        }
+        node.to_vec()
+    }
+
+    fn benchmark_deep_copaths(c: &mut Criterion) {
+        let mut group = c.benchmark_group("deep_copaths");
+
+        for depth in [8, 128, 250, 256] {
+            for real in [true, false] {
+                let update = deep_update(depth, real);
+                let name = if real { "real" } else { "fake" };
+                group.bench_with_input(BenchmarkId::new(name, depth), &update, |b, update| {
+                    b.iter_batched(
+                        || (single_leaf_log(), update.clone()),
+                        |(mut log, update)| {
+                            log.apply_update(update).unwrap();
+                            black_box(log.log_root().unwrap());
+                        },
+                        BatchSize::SmallInput,
+                    );
+                });
+            }
+        }
+
+        group.finish();
+    }
+
     criterion_group!(
         name = benches;
         config = Criterion::default()
             .sample_size(100)
             .measurement_time(std::time::Duration::from_secs(10))
             .warm_up_time(std::time::Duration::from_secs(3));
-        targets = benchmark_sequential_log_updates
+        targets = benchmark_deep_copaths
     );
 }
 #[cfg(not(feature = "gcloud-kms"))]
@@ -124,7 +318,7 @@ mod signing {
 }
 
 #[cfg(not(feature = "gcloud-kms"))]
-criterion_main!(updates::benches, signing::benches);
+criterion_main!(updates::benches, copaths::benches, signing::benches);
 
 #[cfg(feature = "gcloud-kms")]
-criterion_main!(updates::benches);
+criterion_main!(updates::benches, copaths::benches);
@@ -0,0 +1,322 @@
+//! End-to-end sync benchmark.
+//!
+//! Runs the auditor binary against an in-process mock key transparency service,
+//! serving a synthetic log over a unix socket, and measures the time until the
+//! auditor submits a head covering the whole log. Unlike the micro-benchmarks in
+//! `audit.rs`, this includes fetching, decoding, verification, storage commits
+//! and signing, so configuration and code changes can be compared on end-to-end
+//! entries per second.
+//!
+//! The auditor is configured from a generated file, which `AUDIT_*` environment
+//! variables override as usual, e.g.
+//! `AUDIT_APPLY_CHUNK_SIZE=1000 cargo bench --bench sync -- --save-baseline chunk-1000`.
+#[cfg(not(feature = "gcloud-kms"))]
+mod e2e {
+    use criterion::{BenchmarkId, Criterion, SamplingMode, Throughput, criterion_group};
+    use ed25519_dalek::SigningKey;
+    use ed25519_dalek::pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding};
+    use sha2::{Digest, Sha256};
+    use std::path::{Path, PathBuf};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{Duration, Instant};
+    use tokio::net::UnixListener;
+    use tokio::runtime::Runtime;
+    use tokio::sync::watch;
+    use tokio_stream::wrappers::UnixListenerStream;
+    use tonic::{Request, Response, Status};
+
+    use signal_auditor::proto::kt::key_transparency_auditor_service_server::{
+        KeyTransparencyAuditorService, KeyTransparencyAuditorServiceServer,
+    };
+    use signal_auditor::proto::kt::{AuditRequest, AuditResponse, TreeSizeResponse};
+    use signal_auditor::proto::transparency::auditor_proof::{NewTree, Proof, SameKey};
+    use signal_auditor::proto::transparency::{AuditorProof, AuditorTreeHead, AuditorUpdate};
+
+    const INDEX: [u8; 32] = [0x5a; 32];
+    const SEED: [u8; 16] = [0x3c; 16];
+
+    /// Longest a single run may take before the benchmark gives up.
+    const RUN_TIMEOUT: Duration = Duration::from_secs(600);
+
+    /// A synthetic log of `size` entries: a new tree followed by updates to the
+    /// same key. Every update has a full-height proof, as updates from the real
+    /// log do, so the verification cost per entry is representative.
+    fn synthetic_log(size: u64) -> Vec<AuditorUpdate> {
+        (0..size)
+            .map(|position| {
+                let proof = match position {
+                    0 => Proof::NewTree(NewTree {}),
+                    _ => Proof::SameKey(SameKey {
+                        copath: vec![],
+                        counter: position as u32 - 1,
+                        position: 0,
+                    }),
+                };
+                AuditorUpdate {
+                    real: true,
+                    index: INDEX.to_vec(),
+                    seed: SEED.to_vec(),
+                    commitment: Sha256::digest(position.to_be_bytes()).to_vec(),
+                    proof: Some(AuditorProof { proof: Some(proof) }),
+                }
+            })
+            .collect()
+    }
+
+    /// A key transparency service serving a prefix of a fixed log.
+    ///
+    /// The log grows by `step` entries every time a head is submitted, or is
+    /// fully visible from the start if `step` is zero.
+    struct MockService {
+        log: Arc<Vec<AuditorUpdate>>,
+        visible: AtomicU64,
+        step: u64,
+        /// The size of the last submitted head
+        submitted: watch::Sender<u64>,
+    }
+
+    #[tonic::async_trait]
+    impl KeyTransparencyAuditorService for MockService {
+        async fn tree_size(
+            &self,
+            _request: Request<()>,
+        ) -> Result<Response<TreeSizeResponse>, Status> {
+            Ok(Response::new(TreeSizeResponse {
+                tree_size: self.visible.load(Ordering::SeqCst),
+            }))
+        }
+
+        async fn audit(
+            &self,
+            request: Request<AuditRequest>,
+        ) -> Result<Response<AuditResponse>, Status> {
+            let AuditRequest { start, limit } = request.into_inner();
+            let visible = self.visible.load(Ordering::SeqCst);
+            if start > visible {
+                return Err(Status::invalid_argument("Start is past the end of the log"));
+            }
+            let end = start.saturating_add(limit).min(visible);
+            Ok(Response::new(AuditResponse {
+                updates: self.log[start as usize..end as usize].to_vec(),
+                more: end < visible,
+            }))
+        }
+
+        async fn set_auditor_head(
+            &self,
+            request: Request<AuditorTreeHead>,
+        ) -> Result<Response<()>, Status> {
+            let size = request.into_inner().tree_size;
+            if self.step > 0 {
+                let grown = (size + self.step).min(self.log.len() as u64);
+                self.visible.fetch_max(grown, Ordering::SeqCst);
+            }
+            self.submitted.send_replace(size);
+            Ok(Response::new(()))
+        }
+    }
+
+    /// Keys and a configuration file for the auditor under test.
+    struct Fixture {
+        dir: PathBuf,
+        config: PathBuf,
+        socket: PathBuf,
+        storage: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("sync-bench-{name}"));
+            std::fs::create_dir_all(&dir).unwrap();
+
+            let log_key = SigningKey::from_bytes(&[1u8; 32]).verifying_key();
+            let vrf_key = SigningKey::from_bytes(&[2u8; 32]).verifying_key();
+            let auditor_key = SigningKey::from_bytes(&[3u8; 32]);
+            let write = |file: &str, contents: &str| {
+                let path = dir.join(file);
+                std::fs::write(&path, contents).unwrap();
+                path
+            };
+            let signal_public_key = write(
+                "signal.pub",
+                &log_key.to_public_key_pem(LineEnding::LF).unwrap(),
+            );
+            let vrf_public_key = write(
+                "vrf.pub",
+                &vrf_key.to_public_key_pem(LineEnding::LF).unwrap(),
+            );
+            let auditor_signing_key = write(
+                "auditor.key",
+                &auditor_key.to_pkcs8_pem(LineEnding::LF).unwrap(),
+            );
+
+            let socket = dir.join("kt.sock");
+            let storage = dir.join("head.bin");
+            // The client certificate and key are not read for unix:// endpoints
+            let config = write(
+                "config.yaml",
+                &format!(
+                    r#"server_endpoint: "unix://{socket}"
+client_cert_path: "unused.crt"
+client_key_path: "unused.key"
+default_batch_size: 1000
+max_retries: 3
+request_timeout_seconds: 30
+signal_public_key: "{signal_public_key}"
+vrf_public_key: "{vrf_public_key}"
+auditor_signing_key: "{auditor_signing_key}"
+poll_interval_seconds: 0
+max_concurrent_requests: 8
+sync_progress_interval: 3600
+storage:
+  kind: "file"
+storage_path: "{storage}"
+"#,
+                    socket = socket.display(),
+                    signal_public_key = signal_public_key.display(),
+                    vrf_public_key = vrf_public_key.display(),
+                    auditor_signing_key = auditor_signing_key.display(),
+                    storage = storage.display(),
+                ),
+            );
+
+            Self {
+                dir,
+                config,
+                socket,
+                storage,
+            }
+        }
+
+        /// Serve `log` from a fresh mock service, and time an auditor starting from
+        /// an empty head until it submits a head covering all of it.
+        fn run(&self, runtime: &Runtime, log: &Arc<Vec<AuditorUpdate>>, step: u64) -> Duration {
+            let _ = std::fs::remove_file(&self.storage);
+            let _ = std::fs::remove_file(&self.socket);
+
+            let (submitted, mut heads) = watch::channel(0);
+            let service = MockService {
+                log: log.clone(),
+                visible: AtomicU64::new(if step > 0 { step } else { log.len() as u64 }),
+                step,
+                submitted,
+            };
+            let listener = {
+                let _runtime = runtime.enter();
+                UnixListener::bind(&self.socket).unwrap()
+            };
+            let server = runtime.spawn(
+                tonic::transport::Server::builder()
+                    .add_service(KeyTransparencyAuditorServiceServer::new(service))
+                    .serve_with_incoming(UnixListenerStream::new(listener)),
+            );
+
+            let start = Instant::now();
+            let mut auditor = Auditor::spawn(&self.config);
+            let target = log.len() as u64;
+            runtime.block_on(async {
+                loop {
+                    let waited = tokio::time::timeout(
+                        Duration::from_millis(100),
+                        heads.wait_for(|size| *size >= target),
+                    );
+                    if waited.await.is_ok() {
+                        break;
+                    }
+                    if let Some(status) = auditor.0.try_wait().unwrap() {
+                        panic!(
+                            "Auditor exited with {status} before covering the log, run it with {} to see why",
+                            self.config.display()
+                        );
+                    }
+                    assert!(start.elapsed() < RUN_TIMEOUT, "Auditor did not cover the log");
+                }
+            });
+            let elapsed = start.elapsed();
+
+            drop(auditor);
+            server.abort();
+            elapsed
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// The auditor binary, killed when dropped.
+    struct Auditor(Child);
+
+    impl Auditor {
+        fn spawn(config: &Path) -> Self {
+            let child = Command::new(env!("CARGO_BIN_EXE_signal-auditor"))
+                .arg("--config")
+                .arg(config)
+                .arg("run")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("Failed to start the auditor");
+            Self(child)
+        }
+    }
+
+    impl Drop for Auditor {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn benchmark_end_to_end(c: &mut Criterion) {
+        let runtime = Runtime::new().unwrap();
+        let mut group = c.benchmark_group("end_to_end");
+        group.sampling_mode(SamplingMode::Flat);
+
+        // Initial sync of a log that is fully available, with prefetching and a
+        // single commit at the end
+        for size in [10_000u64, 50_000] {
+            let log = Arc::new(synthetic_log(size));
+            let fixture = Fixture::new(&format!("sync-{size}"));
+            group.throughput(Throughput::Elements(size));
+            group.bench_with_input(BenchmarkId::new("sync", size), &log, |b, log| {
+                b.iter_custom(|iters| (0..iters).map(|_| fixture.run(&runtime, log, 0)).sum());
+            });
+        }
+
+        // Steady state, where the log grows by one batch after each submitted head,
+        // so that every batch is committed, signed and submitted
+        for step in [10u64, 100] {
+            let size = 2_000;
+            let log = Arc::new(synthetic_log(size));
+            let fixture = Fixture::new(&format!("tail-{step}"));
+            group.throughput(Throughput::Elements(size));
+            group.bench_with_input(BenchmarkId::new("tail", step), &log, |b, log| {
+                b.iter_custom(|iters| (0..iters).map(|_| fixture.run(&runtime, log, step)).sum());
+            });
+        }
+
+        group.finish();
+    }
+
+    criterion_group!(
+        name = benches;
+        config = Criterion::default()
+            .sample_size(10)
+            .measurement_time(Duration::from_secs(60))
+            .warm_up_time(Duration::from_secs(5));
+        targets = benchmark_end_to_end
+    );
+}
+
+#[cfg(not(feature = "gcloud-kms"))]
+criterion::criterion_main!(e2e::benches);
+
+// The auditor signs with a KMS key in this configuration, which the benchmark
+// cannot provide
+#[cfg(feature = "gcloud-kms")]
+fn main() {}
@@ -1,4 +1,8 @@
 use std::io::Result;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 fn main() -> Result<()> {
     tonic_build::configure()
         .build_server(false)
@@ -6,10 +10,65 @@ fn main() -> Result<()> {
             &[
                 "proto/transparency.proto",
                 "proto/vectors.proto",
-                "proto/key_transparency.proto",
+                "proto/health.proto",
             ],
             &["proto/"],
         )?;
 
+    // The auditor hosts its own service for pulling signed heads,
+    // and queries it from `compare-with-server`. The end-to-end benchmark
+    // hosts a mock key transparency service.
+    tonic_build::configure().compile_protos(
+        &["proto/auditor.proto", "proto/key_transparency.proto"],
+        &["proto/"],
+    )?;
+
+    emit_build_info();
+
     Ok(())
 }
+
+/// Expose the git commit, build time and enabled features to the crate
+/// as `SIGNAL_AUDITOR_*` environment variables.
+fn emit_build_info() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=SIGNAL_AUDITOR_GIT_COMMIT={commit}");
+
+    // Honour SOURCE_DATE_EPOCH so that reproducible builds have a stable timestamp
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+    println!("cargo:rustc-env=SIGNAL_AUDITOR_BUILD_TIMESTAMP={timestamp}");
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!(
+        "cargo:rustc-env=SIGNAL_AUDITOR_FEATURES={}",
+        features.join(",")
+    );
+
+    for path in [".git/HEAD", ".git/refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+}
@@ -2,7 +2,7 @@
 //! for a third party auditor.
 
 use crate::Hash;
-use crate::auditor::PublicConfig;
+use crate::auditor::{PublicConfig, SigningError, encode_statement};
 use crate::proto::transparency::AuditorTreeHead;
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -21,9 +21,14 @@ pub struct Auditor {
 
 // Gets the auditor public key as PEM from a KMS key version.
 impl Auditor {
-    pub async fn get_public_key(kms_name: &str) -> Result<String, anyhow::Error> {
-        let client_config = ClientConfig::default().with_auth().await?;
-        let client = Client::new(client_config).await?;
+    pub async fn get_public_key(kms_name: &str) -> Result<String, SigningError> {
+        let client_config = ClientConfig::default()
+            .with_auth()
+            .await
+            .map_err(SigningError::kms)?;
+        let client = Client::new(client_config)
+            .await
+            .map_err(SigningError::kms)?;
 
         let key_version = client
             .get_public_key(
@@ -33,20 +38,26 @@ impl Auditor {
                 },
                 None,
             )
-            .await?;
+            .await
+            .map_err(SigningError::kms)?;
 
         if key_version.algorithm() != CryptoKeyVersionAlgorithm::EcSignEd25519 {
-            return Err(anyhow::anyhow!("Key version algorithm is not Ed25519"));
+            return Err(SigningError::UnsupportedAlgorithm);
         }
 
         Ok(key_version.pem)
     }
 
     /// Sign a log head at the current time.
-    pub async fn sign_head(&self, head: Hash, size: u64) -> Result<AuditorTreeHead, anyhow::Error> {
+    pub async fn sign_head(&self, head: Hash, size: u64) -> Result<AuditorTreeHead, SigningError> {
         // TODO: consider keeping a client alive
-        let client_config = ClientConfig::default().with_auth().await?;
-        let client = Client::new(client_config).await?;
+        let client_config = ClientConfig::default()
+            .with_auth()
+            .await
+            .map_err(SigningError::kms)?;
+        let client = Client::new(client_config)
+            .await
+            .map_err(SigningError::kms)?;
 
         let ts = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -62,7 +73,8 @@ impl Auditor {
                 },
                 None,
             )
-            .await?;
+            .await
+            .map_err(SigningError::kms)?;
 
         Ok(AuditorTreeHead {
             tree_size: size,
@@ -70,4 +82,29 @@ impl Auditor {
             timestamp: ts as i64,
         })
     }
+
+    /// Sign a statement, domain separated from tree heads by [`crate::auditor::STATEMENT_CONTEXT`].
+    pub async fn sign_statement(&self, statement: &[u8]) -> Result<Vec<u8>, SigningError> {
+        let client_config = ClientConfig::default()
+            .with_auth()
+            .await
+            .map_err(SigningError::kms)?;
+        let client = Client::new(client_config)
+            .await
+            .map_err(SigningError::kms)?;
+
+        let sig = client
+            .asymmetric_sign(
+                AsymmetricSignRequest {
+                    name: self.key_name.clone(),
+                    data: encode_statement(statement),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .map_err(SigningError::kms)?;
+
+        Ok(sig.signature)
+    }
 }
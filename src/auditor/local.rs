@@ -8,7 +8,7 @@ use ed25519_dalek::Signer;
 use ed25519_dalek::SigningKey;
 
 use crate::Hash;
-use crate::auditor::PublicConfig;
+use crate::auditor::{PublicConfig, SigningError, encode_statement};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// `Auditor` holds a signing key and a public configuration.
@@ -19,7 +19,7 @@ pub struct Auditor {
 
 impl Auditor {
     /// Sign a log head at the current time.
-    pub async fn sign_head(&self, head: Hash, size: u64) -> Result<AuditorTreeHead, anyhow::Error> {
+    pub async fn sign_head(&self, head: Hash, size: u64) -> Result<AuditorTreeHead, SigningError> {
         let ts = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -33,6 +33,11 @@ impl Auditor {
         })
     }
 
+    /// Sign a statement, domain separated from tree heads by [`crate::auditor::STATEMENT_CONTEXT`].
+    pub async fn sign_statement(&self, statement: &[u8]) -> Result<Vec<u8>, SigningError> {
+        Ok(self.key.sign(&encode_statement(statement)).to_vec())
+    }
+
     // Used for testing
     pub fn sign_at_time(&self, head: Hash, size: u64, timestamp: i64) -> AuditorTreeHead {
         let msg = self.config.encode_at_time(head, size, timestamp);
@@ -11,7 +11,9 @@ pub use kms::*;
 pub use local::*;
 
 use crate::Hash;
-use ed25519_dalek::VerifyingKey;
+use crate::proto::transparency::AuditorTreeHead;
+use ed25519_dalek::{Signature, VerifyingKey};
+use std::fmt;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum DeploymentMode {
@@ -30,6 +32,16 @@ impl From<DeploymentMode> for u8 {
     }
 }
 
+impl fmt::Display for DeploymentMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DeploymentMode::ContactMonitoring => "contact-monitoring",
+            DeploymentMode::ThirdPartyManagement => "third-party-management",
+            DeploymentMode::ThirdPartyAuditing => "third-party-auditing",
+        })
+    }
+}
+
 impl TryFrom<u8> for DeploymentMode {
     type Error = u8;
 
@@ -43,6 +55,80 @@ impl TryFrom<u8> for DeploymentMode {
     }
 }
 
+/// The reason a signature made by the auditor key was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VerifyError {
+    /// The signature is not a well-formed Ed25519 signature
+    MalformedSignature,
+    /// The signature on a tree head does not verify
+    InvalidHeadSignature,
+    /// The signature on a statement does not verify
+    InvalidStatementSignature,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VerifyError::MalformedSignature => "Malformed signature",
+            VerifyError::InvalidHeadSignature => "Tree head signature is invalid",
+            VerifyError::InvalidStatementSignature => "Statement signature is invalid",
+        })
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// The reason the auditor key failed to sign.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SigningError {
+    /// The KMS key version is not an Ed25519 key
+    UnsupportedAlgorithm,
+    /// A request to the KMS failed
+    Kms(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl SigningError {
+    /// Wrap the error from a failed KMS request.
+    #[cfg_attr(not(feature = "kms-gcp"), allow(dead_code))]
+    fn kms(error: impl std::error::Error + Send + Sync + 'static) -> Self {
+        SigningError::Kms(Box::new(error))
+    }
+}
+
+impl fmt::Display for SigningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SigningError::UnsupportedAlgorithm => write!(f, "Key version algorithm is not Ed25519"),
+            SigningError::Kms(_) => write!(f, "KMS request failed"),
+        }
+    }
+}
+
+impl std::error::Error for SigningError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SigningError::UnsupportedAlgorithm => None,
+            SigningError::Kms(error) => Some(error.as_ref()),
+        }
+    }
+}
+
+/// Domain separation prefix for free-form statements signed by the auditor key.
+///
+/// Tree head messages begin with a zero ciphersuite identifier,
+/// so they can never collide with a message starting with this prefix.
+pub const STATEMENT_CONTEXT: &[u8] = b"signal-auditor-statement-v1\n";
+
+/// Encode a statement for signing by prefixing it with [`STATEMENT_CONTEXT`].
+fn encode_statement(statement: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(STATEMENT_CONTEXT.len() + statement.len());
+    msg.extend_from_slice(STATEMENT_CONTEXT);
+    msg.extend_from_slice(statement);
+    msg
+}
+
 /// Static public configuration for the transparency log.
 pub struct PublicConfig {
     pub mode: DeploymentMode,
@@ -56,7 +142,7 @@ pub struct PublicConfig {
 
 impl PublicConfig {
     /// Encode a log head for signing at a given time.
-    fn encode_at_time(&self, head: Hash, size: u64, time: i64) -> Vec<u8> {
+    pub(crate) fn encode_at_time(&self, head: Hash, size: u64, time: i64) -> Vec<u8> {
         let mut msg = Vec::new();
         msg.extend_from_slice(&[0, 0]); //Ciphersuite
         msg.extend_from_slice(&[self.mode.into()]); // Audit mode
@@ -86,4 +172,37 @@ impl PublicConfig {
 
         msg
     }
+
+    /// Verify the auditor's signature on a tree head whose log root is `head`.
+    ///
+    /// Verification is strict, rejecting signatures under small-order keys and
+    /// non-canonical signatures, so that every verifier agrees on which heads
+    /// the auditor signed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the signature is malformed or does not verify.
+    pub fn verify_head(&self, head: Hash, tree_head: &AuditorTreeHead) -> Result<(), VerifyError> {
+        let msg = self.encode_at_time(head, tree_head.tree_size, tree_head.timestamp);
+        let signature = Signature::from_slice(&tree_head.signature)
+            .map_err(|_| VerifyError::MalformedSignature)?;
+        self.auditor_key
+            .verify_strict(&msg, &signature)
+            .map_err(|_| VerifyError::InvalidHeadSignature)
+    }
+
+    /// Verify the auditor's signature on a statement, as made by `Auditor::sign_statement`.
+    ///
+    /// Verification is strict, as for [`PublicConfig::verify_head`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the signature is malformed or does not verify.
+    pub fn verify_statement(&self, statement: &[u8], signature: &[u8]) -> Result<(), VerifyError> {
+        let signature =
+            Signature::from_slice(signature).map_err(|_| VerifyError::MalformedSignature)?;
+        self.auditor_key
+            .verify_strict(&encode_statement(statement), &signature)
+            .map_err(|_| VerifyError::InvalidStatementSignature)
+    }
 }
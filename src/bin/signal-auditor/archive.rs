@@ -0,0 +1,526 @@
+//! An append-only local archive of the raw updates applied to the log.
+//!
+//! The archive allows the log head to be reconstructed from scratch
+//! after storage loss or corruption, without re-fetching the log from the server.
+//!
+//! Each record is the big-endian `u64` log position of the update followed
+//! by the length-delimited protobuf encoding of the `AuditorUpdate`, exactly as
+//! received from the server. Fields unknown to this version of the auditor are
+//! kept, so that a future version can re-verify them.
+//! Records are written after the update has been applied to the log, so the archive
+//! may run ahead of the committed head after a crash. Replays skip records
+//! for positions that have already been applied and fail on gaps.
+//!
+//! Alongside the records, an index file (the archive path with an `.idx` suffix)
+//! holds a big-endian `u64` position and `u64` byte offset for each record.
+//! The index is synced after the records it points to, so it never points past
+//! the end of the archive. Readers memory-map both files and decode records
+//! lazily, so replays stream the archive rather than loading it into memory.
+//! Readers stop at the end of the last indexed record, and use the index to
+//! seek to a position rather than decoding every record before it.
+//!
+//! A crash may leave a partial record or index entry, or records that were
+//! synced but not yet indexed. When the archive is opened for appending, a
+//! partial record or index entry is truncated, and complete records missing
+//! from the index are indexed.
+
+use anyhow::{Context, anyhow};
+use memmap2::Mmap;
+use prost::Message;
+use signal_auditor::proto::transparency::AuditorUpdate;
+use signal_auditor::transparency::TransparencyLog;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+/// Size of an index entry: a position and an offset.
+const INDEX_ENTRY: usize = 16;
+
+/// The path of the index for the archive at `path`.
+fn index_path(path: &Path) -> PathBuf {
+    let mut index = OsString::from(path.as_os_str());
+    index.push(".idx");
+    PathBuf::from(index)
+}
+
+/// A writer for the update archive.
+pub struct UpdateArchive {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    index: BufWriter<File>,
+    /// Byte offset of the next record
+    offset: u64,
+}
+
+impl UpdateArchive {
+    /// Open the archive at `path` for appending, creating it if necessary, and
+    /// recover from a crash while it was last written.
+    pub fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let open = |path: &Path| {
+            OpenOptions::new()
+                .create(true)
+                .read(true)
+                .append(true)
+                .open(path)
+                .context(format!("Failed to open update archive {}", path.display()))
+        };
+        let file = open(path)?;
+        let index = open(&index_path(path))?;
+        let offset = recover(path, &file, &index)?;
+        tracing::info!("Archiving updates to {}", path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(file),
+            index: BufWriter::new(index),
+            offset,
+        })
+    }
+
+    /// Append the encoded update that was applied at log position `position`.
+    ///
+    /// Records are buffered until [`UpdateArchive::sync`] is called.
+    pub fn append(&mut self, position: u64, encoded: &[u8]) -> Result<(), anyhow::Error> {
+        let mut delimiter = Vec::with_capacity(prost::length_delimiter_len(encoded.len()));
+        prost::encode_length_delimiter(encoded.len(), &mut delimiter)?;
+        self.writer.write_all(&position.to_be_bytes())?;
+        self.writer.write_all(&delimiter)?;
+        self.writer.write_all(encoded)?;
+        self.index.write_all(&position.to_be_bytes())?;
+        self.index.write_all(&self.offset.to_be_bytes())?;
+        self.offset += (8 + delimiter.len() + encoded.len()) as u64;
+        Ok(())
+    }
+
+    /// Flush buffered records and sync them to disk, followed by the index.
+    pub fn sync(&mut self) -> Result<(), anyhow::Error> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data().context(format!(
+            "Failed to sync update archive {}",
+            self.path.display()
+        ))?;
+        self.index.flush()?;
+        self.index.get_ref().sync_data().context(format!(
+            "Failed to sync update archive index {}",
+            index_path(&self.path).display()
+        ))?;
+        Ok(())
+    }
+}
+
+/// Truncate a partial index entry or record left by a crash, and index complete
+/// records that are missing from the index. Returns the end of the archive.
+fn recover(path: &Path, file: &File, index: &File) -> Result<u64, anyhow::Error> {
+    let index_len = index.metadata()?.len();
+    let entries = index_len / INDEX_ENTRY as u64;
+    if index_len % INDEX_ENTRY as u64 != 0 {
+        tracing::warn!(
+            "Truncating partial entry at the end of {}",
+            index_path(path).display()
+        );
+        index.set_len(entries * INDEX_ENTRY as u64)?;
+    }
+    // The last indexed record is complete, since the index is synced after it
+    let last_indexed = match entries.checked_sub(1) {
+        Some(last) => {
+            let mut entry = [0; INDEX_ENTRY];
+            index.read_exact_at(&mut entry, last * INDEX_ENTRY as u64)?;
+            Some(u64::from_be_bytes(entry[8..].try_into()?) as usize)
+        }
+        None => None,
+    };
+
+    let (end, len, unindexed) = {
+        let data = map(file)?;
+        let data = data.as_deref().unwrap_or_default();
+        let start = last_indexed.unwrap_or(0);
+        if start > data.len() {
+            return Err(anyhow!(
+                "Update archive index {} points past the end of the archive",
+                index_path(path).display()
+            ));
+        }
+        let mut records = EncodedRecords {
+            data,
+            offset: start,
+        };
+        let mut unindexed = vec![];
+        let mut end = start;
+        while let Some(Ok((position, _))) = records.next() {
+            if Some(end) != last_indexed {
+                unindexed.push((position, end as u64));
+            }
+            end = records.offset;
+        }
+        (end as u64, data.len() as u64, unindexed)
+    };
+
+    if end < len {
+        tracing::warn!("Truncating partial record at the end of {}", path.display());
+        file.set_len(end)?;
+    }
+    if !unindexed.is_empty() {
+        tracing::warn!(
+            "Indexing {} records missing from the index of {}",
+            unindexed.len(),
+            path.display()
+        );
+        let mut index = BufWriter::new(index);
+        for (position, offset) in unindexed {
+            index.write_all(&position.to_be_bytes())?;
+            index.write_all(&offset.to_be_bytes())?;
+        }
+        index.flush()?;
+        index.get_ref().sync_data()?;
+    }
+    Ok(end)
+}
+
+/// A read-only, memory-mapped view of an update archive.
+pub struct ArchiveReader {
+    /// `None` if the archive is empty, since empty files cannot be mapped
+    data: Option<Mmap>,
+    index: Option<Mmap>,
+    /// End of the last indexed record, or of the archive if it has no index
+    end: usize,
+}
+
+impl ArchiveReader {
+    /// Map the archive at `path`, and its index if present.
+    pub fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        let file = File::open(path)
+            .context(format!("Failed to open update archive {}", path.display()))?;
+        let index = match File::open(index_path(path)) {
+            Ok(index) => map(&index)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).context("Failed to open update archive index"),
+        };
+        let mut reader = Self {
+            data: map(&file)?,
+            index,
+            end: 0,
+        };
+        let data = reader.data.as_deref().unwrap_or_default();
+        // Records past the last indexed one may be partial, so they are not read
+        reader.end = match reader.entries().last() {
+            Some((_, offset)) => {
+                let mut records = EncodedRecords {
+                    data,
+                    offset: offset as usize,
+                };
+                records.next().and_then(Result::ok).ok_or(anyhow!(
+                    "Update archive index points to a malformed record at offset {offset}"
+                ))?;
+                records.offset
+            }
+            None => data.len(),
+        };
+        Ok(reader)
+    }
+
+    /// Iterate over every record in the archive, in order.
+    pub fn records(&self) -> Records<'_> {
+        Records(self.encoded_records())
+    }
+
+    /// Iterate over the records in the archive from the last record for
+    /// `position`, or from the start if the index has no record for it.
+    ///
+    /// Records after it are all for later positions, unless they were
+    /// re-archived after a restart, which replays skip.
+    pub fn records_from(&self, position: u64) -> Records<'_> {
+        let mut records = self.encoded_records();
+        if let Some((_, offset)) = self
+            .entries()
+            .filter(|(indexed, _)| *indexed == position)
+            .last()
+        {
+            records.offset = offset as usize;
+        }
+        Records(records)
+    }
+
+    /// Iterate over every record in the archive, in order, without decoding
+    /// the updates.
+    pub fn encoded_records(&self) -> EncodedRecords<'_> {
+        let data = self.data.as_deref().unwrap_or_default();
+        EncodedRecords {
+            data: &data[..self.end],
+            offset: 0,
+        }
+    }
+
+    /// The position and offset of every indexed record, in order.
+    fn entries(&self) -> impl DoubleEndedIterator<Item = (u64, u64)> + '_ {
+        self.index
+            .as_deref()
+            .unwrap_or_default()
+            .chunks_exact(INDEX_ENTRY)
+            .map(|entry| {
+                let (position, offset) = entry.split_at(8);
+                (
+                    u64::from_be_bytes(position.try_into().unwrap()),
+                    u64::from_be_bytes(offset.try_into().unwrap()),
+                )
+            })
+    }
+
+    /// The position of the first record, if any.
+    pub fn first_position(&self) -> Option<u64> {
+        let position = self.data.as_deref()?.first_chunk::<8>()?;
+        Some(u64::from_be_bytes(*position))
+    }
+
+    /// The position of the last indexed record, if the archive has an index.
+    pub fn last_position(&self) -> Option<u64> {
+        let index = self.index.as_deref()?;
+        let end = index.len() - index.len() % INDEX_ENTRY;
+        let entry = index.get(end.checked_sub(INDEX_ENTRY)?..end)?;
+        Some(u64::from_be_bytes(entry[..8].try_into().ok()?))
+    }
+}
+
+/// Memory-map `file`, returning `None` if it is empty.
+fn map(file: &File) -> Result<Option<Mmap>, anyhow::Error> {
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    // SAFETY: records are only ever appended, and only a partial record past the
+    // last indexed one is truncated, so bytes that are read are never modified
+    Ok(Some(unsafe { Mmap::map(file)? }))
+}
+
+/// A lazily decoding iterator over archive records.
+pub struct Records<'a>(EncodedRecords<'a>);
+
+impl Iterator for Records<'_> {
+    type Item = Result<(u64, AuditorUpdate), anyhow::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.0.next()?.and_then(|(position, encoded)| {
+            let update = AuditorUpdate::decode(encoded)
+                .context(format!("Malformed archive record for position {position}"))?;
+            Ok((position, update))
+        });
+        if record.is_err() {
+            self.0.offset = self.0.data.len();
+        }
+        Some(record)
+    }
+}
+
+/// An iterator over archive records, with each update left encoded.
+pub struct EncodedRecords<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for EncodedRecords<'a> {
+    type Item = Result<(u64, &'a [u8]), anyhow::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.data.len() {
+            return None;
+        }
+        let mut buf = &self.data[self.offset..];
+        let record = (|| {
+            let (position, rest) = buf.split_first_chunk::<8>().ok_or(anyhow!(
+                "Truncated archive record at offset {}",
+                self.offset
+            ))?;
+            let position = u64::from_be_bytes(*position);
+            buf = rest;
+            let len = prost::encoding::decode_varint(&mut buf)
+                .context(format!("Malformed archive record for position {position}"))?;
+            let (encoded, rest) = usize::try_from(len)
+                .ok()
+                .and_then(|len| buf.split_at_checked(len))
+                .ok_or(anyhow!("Truncated archive record for position {position}"))?;
+            buf = rest;
+            Ok((position, encoded))
+        })();
+        // Stop after the first error, since record boundaries are lost
+        self.offset = match record {
+            Ok(_) => self.data.len() - buf.len(),
+            Err(_) => self.data.len(),
+        };
+        Some(record)
+    }
+}
+
+/// Rebuild a log by replaying archived updates from position zero.
+///
+/// `on_position` is called with the log after each update is applied, which allows
+/// callers to observe intermediate roots.
+///
+/// # Errors
+///
+/// Returns an error if a record cannot be read, if the archive has a gap,
+/// or if any update fails verification.
+pub fn replay(
+    records: impl IntoIterator<Item = Result<(u64, AuditorUpdate), anyhow::Error>>,
+    on_position: impl FnMut(&TransparencyLog),
+) -> Result<TransparencyLog, anyhow::Error> {
+    replay_from(TransparencyLog::new(), records, on_position)
+}
+
+/// Replay archived updates onto `log`, skipping records for positions it
+/// already covers, like [`replay`].
+pub fn replay_from(
+    mut log: TransparencyLog,
+    records: impl IntoIterator<Item = Result<(u64, AuditorUpdate), anyhow::Error>>,
+    mut on_position: impl FnMut(&TransparencyLog),
+) -> Result<TransparencyLog, anyhow::Error> {
+    for record in records {
+        let (position, update) = record?;
+        if position < log.size() {
+            // Re-archived after a restart from an older committed head
+            continue;
+        }
+        if position > log.size() {
+            return Err(anyhow!(
+                "Archive is missing updates {} to {}",
+                log.size(),
+                position - 1
+            ));
+        }
+        log.apply_update(update).context(format!(
+            "Failed to apply archived update at position {position}"
+        ))?;
+        on_position(&log);
+    }
+    Ok(log)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(i: u8) -> Vec<u8> {
+        AuditorUpdate {
+            index: vec![i],
+            ..Default::default()
+        }
+        .encode_to_vec()
+    }
+
+    fn write(path: &Path, positions: impl IntoIterator<Item = u64>) {
+        let mut archive = UpdateArchive::open(path).unwrap();
+        for position in positions {
+            archive.append(position, &update(position as u8)).unwrap();
+        }
+        archive.sync().unwrap();
+    }
+
+    fn positions(records: Records<'_>) -> Vec<u64> {
+        records.map(|record| record.unwrap().0).collect()
+    }
+
+    fn len(path: &Path) -> u64 {
+        std::fs::metadata(path).unwrap().len()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive");
+        write(&path, 0..3);
+        write(&path, 3..5);
+        let reader = ArchiveReader::open(&path).unwrap();
+        let records: Vec<_> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 5);
+        for (i, (position, update)) in records.into_iter().enumerate() {
+            assert_eq!(position, i as u64);
+            assert_eq!(update.index, vec![i as u8]);
+        }
+        assert_eq!(reader.first_position(), Some(0));
+        assert_eq!(reader.last_position(), Some(4));
+    }
+
+    #[test]
+    fn test_torn_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive");
+        write(&path, 0..3);
+        let complete = len(&path);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&3u64.to_be_bytes()).unwrap();
+        file.write_all(&[10, 1]).unwrap();
+
+        // Readers stop at the last indexed record
+        let reader = ArchiveReader::open(&path).unwrap();
+        assert_eq!(positions(reader.records()), vec![0, 1, 2]);
+        drop(reader);
+
+        // The writer truncates the partial record and appends after it
+        write(&path, 3..4);
+        assert!(len(&path) > complete);
+        let reader = ArchiveReader::open(&path).unwrap();
+        assert_eq!(positions(reader.records()), vec![0, 1, 2, 3]);
+        assert_eq!(reader.last_position(), Some(3));
+    }
+
+    #[test]
+    fn test_unindexed_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive");
+        write(&path, 0..2);
+        let index_len = len(&index_path(&path));
+        write(&path, 2..4);
+        // The records were synced, but not the index
+        OpenOptions::new()
+            .write(true)
+            .open(index_path(&path))
+            .unwrap()
+            .set_len(index_len)
+            .unwrap();
+        let reader = ArchiveReader::open(&path).unwrap();
+        assert_eq!(positions(reader.records()), vec![0, 1]);
+        drop(reader);
+
+        UpdateArchive::open(&path).unwrap();
+        let reader = ArchiveReader::open(&path).unwrap();
+        assert_eq!(positions(reader.records()), vec![0, 1, 2, 3]);
+        assert_eq!(positions(reader.records_from(3)), vec![3]);
+    }
+
+    #[test]
+    fn test_partial_index_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive");
+        write(&path, 0..2);
+        let mut index = OpenOptions::new()
+            .append(true)
+            .open(index_path(&path))
+            .unwrap();
+        index.write_all(&[0; 5]).unwrap();
+
+        write(&path, 2..3);
+        assert_eq!(len(&index_path(&path)), 3 * INDEX_ENTRY as u64);
+        let reader = ArchiveReader::open(&path).unwrap();
+        assert_eq!(positions(reader.records()), vec![0, 1, 2]);
+        assert_eq!(positions(reader.records_from(2)), vec![2]);
+    }
+
+    #[test]
+    fn test_records_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive");
+        // Positions 2 and 3 are re-archived after a restart
+        write(&path, 0..4);
+        write(&path, 2..6);
+        let reader = ArchiveReader::open(&path).unwrap();
+        assert_eq!(
+            positions(reader.records_from(0)),
+            vec![0, 1, 2, 3, 2, 3, 4, 5]
+        );
+        assert_eq!(positions(reader.records_from(3)), vec![3, 4, 5]);
+        assert_eq!(positions(reader.records_from(5)), vec![5]);
+        // Positions missing from the index start from the beginning
+        assert_eq!(positions(reader.records_from(9)).len(), 8);
+    }
+}
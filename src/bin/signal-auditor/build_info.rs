@@ -0,0 +1,49 @@
+//! Provenance of the running auditor build.
+//!
+//! The values are embedded by `build.rs` so that signatures produced by
+//! this auditor can be correlated with a specific build.
+
+use serde::Serialize;
+use signal_auditor::proto::auditor::BuildProvenance;
+
+/// Build provenance for this binary.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BuildInfo {
+    /// The crate version
+    pub version: &'static str,
+    /// The git commit the binary was built from, or "unknown"
+    pub git_commit: &'static str,
+    /// Unix timestamp (seconds) of the build
+    pub build_timestamp: &'static str,
+    /// Comma-separated list of enabled cargo features
+    pub features: &'static str,
+}
+
+pub const BUILD_INFO: BuildInfo = BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    git_commit: env!("SIGNAL_AUDITOR_GIT_COMMIT"),
+    build_timestamp: env!("SIGNAL_AUDITOR_BUILD_TIMESTAMP"),
+    features: env!("SIGNAL_AUDITOR_FEATURES"),
+};
+
+impl From<BuildInfo> for BuildProvenance {
+    fn from(info: BuildInfo) -> Self {
+        BuildProvenance {
+            version: info.version.to_string(),
+            git_commit: info.git_commit.to_string(),
+            build_timestamp: info.build_timestamp.to_string(),
+            features: info.features.to_string(),
+        }
+    }
+}
+
+/// Log the build provenance as a `build-info` event.
+pub fn log_build_info() {
+    tracing::info!(
+        type = "build-info",
+        version = BUILD_INFO.version,
+        git_commit = BUILD_INFO.git_commit,
+        build_timestamp = BUILD_INFO.build_timestamp,
+        features = BUILD_INFO.features,
+    );
+}
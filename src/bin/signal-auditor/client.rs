@@ -1,32 +1,131 @@
 //! This module contains the primary event loop for the auditor.
 
 use anyhow::Context;
-use config::{Config, Environment, File};
+use config::{Config, Environment, File, FileFormat};
 use ed25519_dalek::{VerifyingKey, pkcs8::DecodePublicKey};
+use prost::Message;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::panic::{AssertUnwindSafe, catch_unwind, resume_unwind};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{
     collections::VecDeque,
+    net::SocketAddr,
     path::{Path, PathBuf},
 };
-use tonic::{Code, Request, Response, Status};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tonic::{Code, Request, Status};
 
+use signal_auditor::Hash;
 use signal_auditor::auditor::DeploymentMode;
 use signal_auditor::auditor::{Auditor, PublicConfig};
+use signal_auditor::proto::auditor::SignedAuditorHead;
+use signal_auditor::proto::health::health_check_response::ServingStatus;
+use signal_auditor::proto::health::{HealthCheckRequest, health_client::HealthClient};
+use signal_auditor::proto::kt::AuditRequest;
 use signal_auditor::proto::kt::key_transparency_auditor_service_client::KeyTransparencyAuditorServiceClient;
-use signal_auditor::proto::kt::{AuditRequest, AuditResponse};
+use signal_auditor::proto::transparency::{AuditorTreeHead, AuditorUpdate};
 use signal_auditor::transparency::TransparencyLog;
-use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+use signal_auditor::wire::unknown_fields;
+use tonic::transport::Channel;
 
-use crate::storage::{Backend, Storage};
+use crate::archive::UpdateArchive;
+use crate::build_info::BUILD_INFO;
+use crate::coverage::CoverageTracker;
+use crate::custody::CommittedHead;
+use crate::deadline::Cycle;
+use crate::halt::HaltState;
+use crate::history::History;
+use crate::journal::{Equivocation, SigningJournal};
+use crate::keycheck::{self, RunningKeys};
+use crate::prefix_roots::{PrefixRoots, RootConflict};
+use crate::pull::LatestHead;
+use crate::raw_audit::{RawAuditClient, RawAuditResponse};
+use crate::report::{self, ReportConfig};
+use crate::snapshot::{SnapshotSchedule, Snapshots};
+use crate::sops;
+use crate::status::StatusHandle;
+use crate::storage::{Backend, InvalidHead, Storage};
+use crate::transport::ServerEndpoint;
 
 #[cfg(not(feature = "gcloud-kms"))]
 use ed25519_dalek::{SigningKey, pkcs8::DecodePrivateKey};
 
+/// The flavor of tokio runtime to run the auditor on
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuntimeFlavor {
+    /// A work-stealing runtime with a pool of worker threads
+    #[default]
+    MultiThread,
+    /// A single-threaded runtime, suitable for small instances
+    CurrentThread,
+}
+
+/// Which storage backend holds the log head
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageKind {
+    /// A local file at `storage_path`
+    File,
+    /// Google Cloud Storage buckets named by `gcp_bucket` and `gcp_replica_buckets`,
+    /// available in builds with the `storage-gcp` feature
+    Gcs,
+}
+
+impl std::fmt::Display for StorageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            StorageKind::File => "file",
+            StorageKind::Gcs => "gcs",
+        })
+    }
+}
+
+/// Storage backend selection
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct StorageConfig {
+    /// The backend to use. Defaults to `gcs` if `gcp_bucket` is set, and is required otherwise
+    pub kind: Option<StorageKind>,
+}
+
+/// Which IP address families to connect to the server over
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum IpPreference {
+    /// Use the addresses in the order returned by the resolver
+    #[default]
+    Any,
+    /// Try IPv4 addresses before IPv6 addresses
+    PreferIpv4,
+    /// Try IPv6 addresses before IPv4 addresses
+    PreferIpv6,
+    /// Only connect over IPv4
+    Ipv4Only,
+    /// Only connect over IPv6
+    Ipv6Only,
+}
+
+/// How to handle updates with fields unknown to this version of the auditor
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownFieldPolicy {
+    /// Log and count the update, and verify the fields that are known
+    #[default]
+    Lenient,
+    /// Reject the update, stopping the audit until the auditor is upgraded
+    Strict,
+}
+
 /// Configuration for the Key Transparency client
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ClientConfig {
-    /// The server endpoint to connect to (e.g., "https://example.com:443")
+    /// The server endpoint to connect to (e.g., "https://example.com:443"),
+    /// or a local socket fronted by a proxy (e.g., "unix:///run/kt/proxy.sock")
     pub server_endpoint: String,
     /// Path to the client certificate file (PEM format)
     pub client_cert_path: PathBuf,
@@ -38,27 +137,167 @@ pub struct ClientConfig {
     pub default_batch_size: u64,
     /// Maximum number of retries for failed requests - TODO
     pub max_retries: u32,
-    /// Timeout for requests in seconds
+    /// Timeout for requests in seconds, used for any request without a more specific timeout
     pub request_timeout_seconds: u64,
+    /// Timeout in seconds for audit fetches, which may take long for large batches
+    pub audit_timeout_seconds: Option<u64>,
+    /// Timeout in seconds for submitting signed heads
+    pub submit_timeout_seconds: Option<u64>,
+    /// Timeout in seconds for establishing a connection
+    pub connect_timeout_seconds: Option<u64>,
+    /// Deadline in seconds for one cycle of the audit loop: waiting for a fetched
+    /// batch, applying it and, at the log end, committing and submitting the head.
+    /// A cycle that overruns is cancelled and the loop restarts with backoff, so this
+    /// should allow for fetch retries and throttling. Disabled if unset
+    pub cycle_timeout_seconds: Option<u64>,
+    /// Total seconds a fetch may spend paused by server throttling before it
+    /// fails like any other request
+    #[serde(default = "default_max_throttle")]
+    pub max_throttle_seconds: u64,
+    /// IP address family preference for server connections
+    #[serde(default)]
+    pub ip_preference: IpPreference,
+    /// Address of a SOCKS5 proxy (e.g., a Tor client at "127.0.0.1:9050") to
+    /// connect to the server through. The proxy resolves the server host name,
+    /// so `.onion` endpoints can be used. Fetching `public_keys_url` and
+    /// delivering error reports also go through the proxy
+    pub socks5_proxy: Option<String>,
     /// KT Log Public Key
     pub signal_public_key: PathBuf,
     /// VRF Public Key
     pub vrf_public_key: PathBuf,
     /// Poll interval for audit seconds
     pub poll_interval_seconds: u64,
+    /// URL of the log operator's published public keys, compared periodically
+    /// against `signal_public_key` and `vrf_public_key`
+    pub public_keys_url: Option<String>,
+    /// Interval in seconds between public key checks, at least 60
+    #[serde(default = "default_public_keys_check_interval")]
+    pub public_keys_check_interval_seconds: u64,
+    /// How to handle updates with fields or proof variants unknown to this auditor
+    #[serde(default)]
+    pub unknown_fields: UnknownFieldPolicy,
+    /// Number of updates verified at a time on the blocking thread pool. The
+    /// cycle timeout is checked between chunks of a large batch
+    #[serde(default = "default_apply_chunk_size")]
+    pub apply_chunk_size: usize,
+    /// Number of threads computing prefix tree roots for each chunk of updates,
+    /// defaults to the number of CPUs. The threads are started once, with the runtime
+    pub verify_threads: Option<usize>,
     /// Maximum number of concurrent requests to queue
     pub max_concurrent_requests: usize,
+    /// Budget in bytes for fetched responses awaiting application. Fewer
+    /// requests are queued when responses are large enough to exceed it
+    pub max_inflight_bytes: Option<u64>,
+    /// Tokio runtime flavor ("multi-thread" or "current-thread")
+    #[serde(default)]
+    pub runtime_flavor: RuntimeFlavor,
+    /// Number of worker threads for the multi-thread runtime, defaults to the number of CPUs.
+    /// Must be at least 1
+    pub worker_threads: Option<usize>,
     /// Interval in seconds between sync reports
     pub sync_progress_interval: u64,
 
+    /// With the `sandbox` feature, run without the Landlock filesystem ruleset on
+    /// kernels that do not support it, instead of refusing to start
+    #[serde(default)]
+    pub sandbox_best_effort: bool,
+
+    /// Storage backend selection
+    #[serde(default)]
+    pub storage: StorageConfig,
+
+    /// Error reporting: deduplication and the sinks errors are sent to
+    #[serde(default)]
+    pub error_reporting: ReportConfig,
+
     /// GCP bucket name
-    #[cfg(feature = "storage-gcp")]
     pub gcp_bucket: Option<String>,
 
+    /// Additional GCP buckets, e.g. in other regions, that the head is replicated to
+    #[serde(default)]
+    pub gcp_replica_buckets: Vec<String>,
+
+    /// Number of buckets that must accept a commit and agree on a read.
+    /// Defaults to a majority of `gcp_bucket` and `gcp_replica_buckets`.
+    pub gcp_quorum: Option<usize>,
+
+    /// GCP bucket for labeled snapshots, ideally with a longer retention lock
+    /// than the head buckets. Defaults to `gcp_bucket`.
+    pub gcp_snapshot_bucket: Option<String>,
+
     /// Path to the storage file
-    #[cfg(not(feature = "storage-gcp"))]
     pub storage_path: Option<PathBuf>,
 
+    /// Directory for labeled snapshots. Defaults to `snapshots` next to `storage_path`.
+    pub snapshot_dir: Option<PathBuf>,
+
+    /// Path to a secret key of at least 32 bytes that stored heads and snapshots
+    /// are authenticated with. When set, heads without a valid MAC are rejected
+    pub storage_mac_key_path: Option<PathBuf>,
+
+    /// Labeled snapshots of the committed head, each taken once per period
+    #[serde(default)]
+    pub snapshots: Vec<SnapshotSchedule>,
+
+    /// Path to a local archive of raw updates, used to rebuild the log head
+    pub archive_path: Option<PathBuf>,
+
+    /// Path to an append-only record of the prefix tree root at every log position,
+    /// for showing which prefix root a historical entry committed to
+    pub prefix_roots_path: Option<PathBuf>,
+
+    /// Path to a local SQLite database recording every applied batch, for forensics
+    pub history_db_path: Option<PathBuf>,
+
+    /// Path to a local journal of every signed head, used to refuse
+    /// signing two different roots for the same log size
+    pub signing_journal_path: Option<PathBuf>,
+
+    /// Path to the halt state. When set, evidence of log divergence halts signing
+    /// until an operator approval signed by `operator_public_key` is presented
+    pub halt_path: Option<PathBuf>,
+
+    /// Operator public key (PEM format) used to verify re-arm approvals
+    pub operator_public_key: Option<PathBuf>,
+
+    /// Auditor public key (PEM format) used by `compare-with-server` to verify
+    /// the published head without access to the signing key
+    pub auditor_public_key: Option<PathBuf>,
+
+    /// Interval in seconds between storage custody checks, which read back the
+    /// committed head and compare it with the in-memory log. Disabled if unset.
+    pub storage_custody_interval_seconds: Option<u64>,
+
+    /// Address to serve health checks and the status page on (e.g. "0.0.0.0:8080")
+    pub status_listen_addr: Option<SocketAddr>,
+
+    /// Secondary endpoint (e.g. a standby region) that signed heads are also
+    /// submitted to after the primary accepts them
+    pub mirror_endpoint: Option<String>,
+
+    /// Address to serve the signed head pull API on (e.g. "0.0.0.0:50051")
+    pub pull_listen_addr: Option<SocketAddr>,
+
+    /// Sign a new head once the log has grown by at least this many entries
+    /// since the last submission. If neither this nor `submit_interval_seconds`
+    /// is set, every new head is signed.
+    pub submit_min_entries: Option<u64>,
+
+    /// Sign a new head once this many seconds have passed since the last
+    /// submission, bounding how stale the signed head can be. An unchanged head
+    /// is signed again with a new timestamp after this long, or an hour if unset
+    pub submit_interval_seconds: Option<u64>,
+
+    /// Whether to push signed heads to the server with `set_auditor_head`.
+    /// May be disabled when the server pulls heads from the pull API instead.
+    #[serde(default = "default_submit_heads")]
+    pub submit_heads: bool,
+
+    /// Digest of the container image the auditor runs in, as reported by
+    /// the TEE or workload identity (e.g. via `AUDIT_IMAGE_DIGEST`)
+    pub image_digest: Option<String>,
+
     /// KMS key version name
     #[cfg(feature = "gcloud-kms")]
     pub kms_key_version: String,
@@ -68,34 +307,166 @@ pub struct ClientConfig {
     pub auditor_signing_key: PathBuf,
 }
 
+/// The gRPC service name used for health checks.
+const AUDITOR_SERVICE: &str = "kt.KeyTransparencyAuditorService";
+
+/// The result of probing the Key Transparency server.
+#[derive(Debug)]
+pub enum ServerHealth {
+    /// The server is reachable and serving requests.
+    Serving,
+    /// The server is reachable, but reports that it is not serving
+    /// or fails a trivial request.
+    Unhealthy(String),
+    /// The server could not be reached.
+    Unreachable(String),
+}
+
+impl ClientConfig {
+    /// Timeout for requests without a more specific timeout
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout_seconds)
+    }
+
+    /// Timeout for audit fetches
+    pub fn audit_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.audit_timeout_seconds
+                .unwrap_or(self.request_timeout_seconds),
+        )
+    }
+
+    /// Timeout for submitting signed heads
+    pub fn submit_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.submit_timeout_seconds
+                .unwrap_or(self.request_timeout_seconds),
+        )
+    }
+
+    /// Deadline for one cycle of the audit loop, if any
+    pub fn cycle_timeout(&self) -> Option<Duration> {
+        self.cycle_timeout_seconds.map(Duration::from_secs)
+    }
+
+    /// Total time a fetch may spend throttled
+    pub fn max_throttle(&self) -> Duration {
+        Duration::from_secs(self.max_throttle_seconds)
+    }
+
+    /// Number of threads to verify each chunk of updates with
+    pub fn verify_threads(&self) -> usize {
+        self.verify_threads.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+        })
+    }
+
+    /// Check settings that deserialization alone cannot.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        self.storage_kind()?;
+        if self.worker_threads == Some(0) {
+            return Err(anyhow::anyhow!("`worker_threads` must be at least 1"));
+        }
+        if self.public_keys_check_interval_seconds < keycheck::MIN_CHECK_INTERVAL_SECONDS {
+            return Err(anyhow::anyhow!(
+                "`public_keys_check_interval_seconds` must be at least {}",
+                keycheck::MIN_CHECK_INTERVAL_SECONDS
+            ));
+        }
+        Ok(())
+    }
+
+    /// The configured storage backend kind
+    ///
+    /// Without `storage.kind`, `gcs` is used if `gcp_bucket` is set. File storage
+    /// is never chosen implicitly, so that a deployment missing its bucket fails
+    /// instead of running without the bucket's retention lock.
+    pub fn storage_kind(&self) -> Result<StorageKind, anyhow::Error> {
+        match (self.storage.kind, &self.gcp_bucket) {
+            (Some(kind), _) => Ok(kind),
+            (None, Some(_)) => Ok(StorageKind::Gcs),
+            (None, None) => Err(anyhow::anyhow!(
+                "No storage backend configured: set `storage.kind`, or `gcp_bucket` for GCS storage"
+            )),
+        }
+    }
+
+    /// Timeout for establishing a connection
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.connect_timeout_seconds
+                .unwrap_or(self.request_timeout_seconds),
+        )
+    }
+}
+
+fn default_apply_chunk_size() -> usize {
+    100
+}
+
+fn default_submit_heads() -> bool {
+    true
+}
+
+fn default_public_keys_check_interval() -> u64 {
+    3600
+}
+
+fn default_max_throttle() -> u64 {
+    1800
+}
+
 /// A stateful Auditor client for the Key Transparency service
 /// Consists of a transparency log cache, a storage backend,
 /// and an auditor key.
 pub struct KeyTransparencyClient {
-    endpoint: Endpoint,
+    endpoint: ServerEndpoint,
+    /// Secondary endpoint that signed heads are also submitted to
+    mirror_endpoint: Option<ServerEndpoint>,
+    /// In-flight submission to the mirror, superseded by newer heads
+    mirror_task: Option<JoinHandle<()>>,
     config: ClientConfig,
     transparency_log: TransparencyLog,
     storage: Backend,
+    /// Optional archive of applied updates
+    archive: Option<UpdateArchive>,
+    /// Optional record of the prefix root at every position
+    prefix_roots: Option<PrefixRoots>,
+    /// Optional history of applied batches
+    history: Option<History>,
+    /// Status shared with the embedded status page
+    status: StatusHandle,
+    /// Latest signed head, shared with the pull API
+    latest_head: LatestHead,
+    /// Last committed head, shared with the storage custody check
+    committed_head: CommittedHead,
+    /// The (size, root) of the last head acknowledged by the server
+    last_submitted: Option<(u64, Hash)>,
+    /// When the last head was acknowledged
+    last_submitted_at: Option<Instant>,
+    /// A signed head whose submission has not been acknowledged.
+    /// Retries resubmit this head rather than signing a new timestamp.
+    pending_head: Option<(u64, Hash, AuditorTreeHead)>,
+    /// Observed server log sizes, for coverage latency
+    coverage: CoverageTracker,
+    /// Pause shared by fetch tasks when the server throttles requests
+    throttle: Throttle,
+    /// Optional journal of signed heads, checked before every signature
+    journal: Option<SigningJournal>,
+    /// Optional halt state, set when the log is observed to diverge
+    halt: Option<HaltState>,
+    /// Scheduled snapshots of the committed head
+    snapshots: Snapshots,
     /// Auditor key material
     auditor: Auditor,
 }
 
 impl KeyTransparencyClient {
     /// Create a new client with the given configuration
-    pub async fn new(config: ClientConfig) -> Result<Self, anyhow::Error> {
-        let identity = Identity::from_pem(
-            std::fs::read(&config.client_cert_path).context("Failed to read client cert")?,
-            std::fs::read(&config.client_key_path).context("Failed to read client key")?,
-        );
-
-        let mut tls_config = ClientTlsConfig::new().identity(identity);
-        if let Some(ca_cert_path) = &config.ca_cert_path {
-            let ca_certificate = Certificate::from_pem(std::fs::read(ca_cert_path)?);
-            tls_config = tls_config.ca_certificate(ca_certificate);
-        } else {
-            tls_config = tls_config.with_enabled_roots();
-        }
-
+    ///
+    /// If `allow_resync` is set, a stored head that fails validation is quarantined
+    /// and the log is resynced from scratch. Otherwise, an invalid head is a fatal error.
+    pub async fn new(config: ClientConfig, allow_resync: bool) -> Result<Self, anyhow::Error> {
         let auditor = create_auditor(&config)
             .await
             .context("Failed to initialize auditor")?;
@@ -104,63 +475,499 @@ impl KeyTransparencyClient {
             .await
             .context("Failed to initialize storage backend")?;
 
-        let transparency_log = storage
-            .get_head()
-            .await
-            .context("Error trying to get log head")?
-            .unwrap_or_else(|| {
-                tracing::info!("No log head found, creating new log");
-                TransparencyLog::new()
-            });
-
-        let endpoint = Endpoint::from_shared(config.server_endpoint.clone())
-            .context("Failed to create endpoint")?
-            .tls_config(tls_config)
-            .context("Failed to create TLS config")?
-            .timeout(Duration::from_secs(config.request_timeout_seconds));
+        let transparency_log = match storage.get_head().await {
+            Err(e) if allow_resync && e.downcast_ref::<InvalidHead>().is_some() => {
+                let location = storage
+                    .quarantine_head()
+                    .await
+                    .context("Failed to quarantine invalid log head")?;
+                report_error!(format!(
+                    "{e:#}; quarantined to {location}, resyncing from the start of the log"
+                ));
+                None
+            }
+            head => head.context("Error trying to get log head")?,
+        }
+        .unwrap_or_else(|| {
+            tracing::info!("No log head found, creating new log");
+            TransparencyLog::new()
+        });
+
+        let archive = config
+            .archive_path
+            .as_deref()
+            .map(UpdateArchive::open)
+            .transpose()?;
+
+        let prefix_roots = config
+            .prefix_roots_path
+            .as_deref()
+            .map(PrefixRoots::open)
+            .transpose()?;
+
+        let history = config
+            .history_db_path
+            .as_deref()
+            .map(History::open)
+            .transpose()?;
+
+        let journal = config
+            .signing_journal_path
+            .as_deref()
+            .map(SigningJournal::open)
+            .transpose()?;
+
+        let halt = config.halt_path.as_deref().map(HaltState::new);
+        if let Some(current) = halt.as_ref().map(HaltState::current).transpose()?.flatten() {
+            report_error!(format!(
+                "Signing is halted ({}): {}; run `rearm` with an operator approval to resume",
+                current.halt_id, current.reason
+            ));
+        }
+
+        let snapshots = Snapshots::new(config.snapshots.clone())?;
+
+        let status = StatusHandle::new();
+        status.set_size(transparency_log.size());
+        report::set_size(transparency_log.size());
+
+        let coverage = CoverageTracker::new(transparency_log.size());
+
+        let committed_head = CommittedHead::new(
+            transparency_log
+                .is_initialized()
+                .then(|| transparency_log.clone()),
+        );
+
+        let mirror_endpoint = config
+            .mirror_endpoint
+            .as_ref()
+            .map(|mirror| ServerEndpoint::new(mirror, &config))
+            .transpose()
+            .context("Failed to create mirror endpoint")?;
+
+        let endpoint = ServerEndpoint::new(&config.server_endpoint, &config)?;
 
         Ok(Self {
             endpoint,
+            mirror_endpoint,
+            mirror_task: None,
             config,
             transparency_log,
             storage,
+            archive,
+            prefix_roots,
+            history,
+            status,
+            latest_head: LatestHead::default(),
+            committed_head,
+            last_submitted: None,
+            last_submitted_at: None,
+            pending_head: None,
+            coverage,
+            throttle: Throttle::default(),
+            journal,
+            halt,
+            snapshots,
             auditor,
         })
     }
 
+    /// Log the state the auditor starts from as a single `startup` event, so that
+    /// it can be found without piecing together earlier log lines.
+    pub fn log_startup_summary(&self) {
+        let auditor_key = self.auditor.config.auditor_key.as_bytes();
+        let root = self.transparency_log.log_root().ok().map(hex::encode);
+        tracing::info!(
+            type = "startup",
+            endpoint = self.config.server_endpoint,
+            socks5_proxy = self.config.socks5_proxy,
+            mirror_endpoint = self.config.mirror_endpoint,
+            storage = %self.storage.kind(),
+            storage_location = self.storage.location(),
+            deployment_mode = %self.auditor.config.mode,
+            auditor_key_fingerprint = hex::encode(Sha256::digest(auditor_key)),
+            size = self.transparency_log.size(),
+            root = root,
+            version = BUILD_INFO.version,
+            git_commit = BUILD_INFO.git_commit,
+        );
+    }
+
+    /// A handle to the status shown on the embedded status page
+    pub fn status(&self) -> StatusHandle {
+        self.status.clone()
+    }
+
+    /// Probe the health of the server.
+    ///
+    /// Uses the standard gRPC health protocol if the server exposes it,
+    /// and otherwise falls back to requesting a single audit entry.
+    pub async fn probe_server(&self) -> ServerHealth {
+        let channel = match self.endpoint.connect().await {
+            Ok(channel) => channel,
+            Err(e) => return ServerHealth::Unreachable(format!("{e:#}")),
+        };
+
+        let mut health = HealthClient::new(channel.clone());
+        let request = HealthCheckRequest {
+            service: AUDITOR_SERVICE.to_string(),
+        };
+        match health.check(request).await {
+            Ok(response) => match response.into_inner().status() {
+                ServingStatus::Serving => ServerHealth::Serving,
+                status => ServerHealth::Unhealthy(format!("Health check reported {status:?}")),
+            },
+            Err(status) if status.code() == Code::Unimplemented => {
+                let mut client = KeyTransparencyAuditorServiceClient::new(channel);
+                match client.audit(AuditRequest { start: 0, limit: 1 }).await {
+                    Ok(_) => ServerHealth::Serving,
+                    Err(status) => ServerHealth::Unhealthy(format!("Audit probe failed: {status}")),
+                }
+            }
+            Err(status) => ServerHealth::Unhealthy(format!("Health check failed: {status}")),
+        }
+    }
+
+    /// Probe the server and log the result as a `server-health` event.
+    /// Returns true if the server is serving.
+    pub async fn report_server_health(&self) -> bool {
+        match self.probe_server().await {
+            ServerHealth::Serving => {
+                tracing::info!(type = "server-health", state = "serving");
+                true
+            }
+            ServerHealth::Unhealthy(reason) => {
+                tracing::warn!(type = "server-health", state = "unhealthy", reason = reason);
+                false
+            }
+            ServerHealth::Unreachable(reason) => {
+                tracing::warn!(type = "server-health", state = "unreachable", reason = reason);
+                false
+            }
+        }
+    }
+
+    /// Record evidence that the log has diverged from the auditor's view.
+    ///
+    /// If a halt path is configured, signing is halted until an operator re-arms the
+    /// auditor. Returns the error to propagate to the caller.
+    fn diverged(&self, reason: String) -> anyhow::Error {
+        if let Some(halt) = &self.halt {
+            match halt.halt(&reason, self.transparency_log.size()) {
+                Ok(halt) => {
+                    report_error!(format!("Signing halted ({}): {reason}", halt.halt_id));
+                }
+                Err(e) => {
+                    report_error!(format!("Failed to record halt: {e:?}"));
+                }
+            }
+        }
+        anyhow::anyhow!(reason)
+    }
+
+    /// Number of fetches to keep queued during sync.
+    ///
+    /// With `max_inflight_bytes` set, this assumes each response has the encoded size
+    /// `response_bytes` of the last one, and fetches one batch at a time until a
+    /// response has been measured. At least one fetch is always queued.
+    fn prefetch_depth(&self, response_bytes: Option<u64>) -> usize {
+        let max = self.config.max_concurrent_requests.max(1);
+        match (self.config.max_inflight_bytes, response_bytes) {
+            (None, _) => max,
+            (Some(_), None) => 1,
+            (Some(budget), Some(bytes)) => (budget / bytes.max(1)).clamp(1, max as u64) as usize,
+        }
+    }
+
+    /// Decode a batch of updates starting at log position `start`, applying the
+    /// configured policy to updates with unknown fields.
+    fn decode_batch(
+        &self,
+        start: u64,
+        encoded: &[Vec<u8>],
+    ) -> Result<Vec<AuditorUpdate>, anyhow::Error> {
+        let mut updates = Vec::with_capacity(encoded.len());
+        for (position, encoded) in (start..).zip(encoded) {
+            let unknown = unknown_fields(encoded)
+                .context(format!("Malformed update at position {position}"))?;
+            if !unknown.is_empty() {
+                let fields = unknown
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                if self.config.unknown_fields == UnknownFieldPolicy::Strict {
+                    return Err(anyhow::anyhow!(
+                        "Update at position {position} has fields unknown to this auditor: {fields}"
+                    ));
+                }
+                tracing::warn!(type = "unknown-fields", position = position, fields = fields);
+                self.status.record_unknown_fields();
+            }
+            updates.push(
+                AuditorUpdate::decode(encoded.as_slice())
+                    .context(format!("Malformed update at position {position}"))?,
+            );
+        }
+        Ok(updates)
+    }
+
+    /// Archive the encoded updates of a verified batch starting at `start`, and
+    /// record the prefix root after each of them.
+    ///
+    /// This must happen before the log is advanced past the batch, so that the
+    /// archive and the prefix roots never fall behind the log or the committed head.
+    fn archive_batch(
+        &mut self,
+        start: u64,
+        encoded: &[Vec<u8>],
+        prefix_roots: &[Hash],
+    ) -> Result<(), anyhow::Error> {
+        if let Some(archive) = &mut self.archive {
+            for (position, encoded) in (start..).zip(encoded) {
+                archive.append(position, encoded)?;
+            }
+            archive.sync()?;
+        }
+        if let Some(recorder) = &mut self.prefix_roots {
+            recorder
+                .append(start, prefix_roots)
+                .and_then(|()| recorder.sync())
+                .context("Failed to record prefix roots")?;
+        }
+        Ok(())
+    }
+
+    /// Commit the verified part of a batch in which verifying an update panicked.
+    ///
+    /// `log` holds the updates verified before the chunk that panicked, and
+    /// `prefix_roots` at least their prefix roots. The updates of that chunk are
+    /// re-applied one at a time, stopping at the first one that fails or panics.
+    /// The verified part of the batch is archived before the result is committed,
+    /// and nothing is committed if archiving fails.
+    async fn commit_after_panic(
+        &mut self,
+        mut log: TransparencyLog,
+        chunk: &[AuditorUpdate],
+        batch_start: u64,
+        encoded: &[Vec<u8>],
+        mut prefix_roots: Vec<Hash>,
+    ) {
+        let record_roots = self.prefix_roots.is_some();
+        prefix_roots.truncate((log.size() - batch_start) as usize);
+        for update in chunk {
+            match catch_unwind(AssertUnwindSafe(|| {
+                log.verify_batch(std::slice::from_ref(update))
+            })) {
+                Ok(Ok(verified)) => {
+                    log = verified;
+                    if record_roots {
+                        prefix_roots.extend(log.prefix_root());
+                    }
+                }
+                _ => break,
+            }
+        }
+        let Ok(root) = log.log_root() else {
+            return;
+        };
+        let verified = (log.size() - batch_start) as usize;
+        if let Err(e) = self.archive_batch(batch_start, &encoded[..verified], &prefix_roots) {
+            report_error!(format!(
+                "Failed to archive updates after panic, not committing: {e:?}"
+            ));
+            return;
+        }
+        let mut committed = self.committed_head.lock().await;
+        match self.storage.commit_head(&log).await {
+            Ok(()) => {
+                tracing::warn!(
+                    type = "panic-commit",
+                    size = log.size(),
+                    root = hex::encode(root),
+                );
+                *committed = Some(log);
+            }
+            Err(e) => {
+                report_error!(format!("Failed to commit log head after panic: {e:?}"));
+            }
+        }
+    }
+
+    /// The log operator keys the auditor verifies the log with
+    pub fn running_keys(&self) -> RunningKeys {
+        RunningKeys {
+            sig_key: self.auditor.config.sig_key,
+            vrf_key: self.auditor.config.vrf_key,
+        }
+    }
+
+    /// A handle to the last head committed to storage
+    pub fn committed_head(&self) -> CommittedHead {
+        self.committed_head.clone()
+    }
+
+    /// A handle to the latest signed head served by the pull API
+    pub fn latest_head(&self) -> LatestHead {
+        self.latest_head.clone()
+    }
+
     // Fetch the log size from the server
     pub async fn fetch_log_size(&mut self) -> Result<u64, anyhow::Error> {
         let mut client = KeyTransparencyAuditorServiceClient::new(self.endpoint.connect().await?);
-        let response = client.tree_size(()).await?;
-        Ok(response.into_inner().tree_size)
+        let mut request = Request::new(());
+        request.set_timeout(self.config.request_timeout());
+        let tree_size = client.tree_size(request).await?.into_inner().tree_size;
+        self.coverage.observe(tree_size);
+        Ok(tree_size)
     }
 
-    /// Submit an auditor tree head signature
+    /// Sign the current log head, publish it to the pull API, and submit
+    /// it to the server unless pushing is disabled.
+    ///
+    /// Submission is skipped if the server has already acknowledged a head with
+    /// the same size and root, until it is older than `resign_interval`. If a previous submission of this head failed,
+    /// possibly after reaching the server, the identical signed head is resubmitted.
     /// SECURITY: Tree head must be committed _before_ signing
     /// or sending to the server. This prevents visible equivocation in case of a crash
     async fn submit_auditor_head(
         &mut self,
         client: &mut KeyTransparencyAuditorServiceClient<Channel>,
-    ) -> Result<Response<()>, anyhow::Error> {
-        let tree_head = self
-            .auditor
-            .sign_head(
-                self.transparency_log
-                    .log_root()
-                    .context("Tried to submit empty log root")?,
-                self.transparency_log.size(),
-            )
-            .await
-            .context("Failed to sign auditor head")?;
+    ) -> Result<(), anyhow::Error> {
+        let root = self
+            .transparency_log
+            .log_root()
+            .context("Tried to submit empty log root")?;
+        let size = self.transparency_log.size();
 
-        let mut request = Request::new(tree_head.clone());
-        request.set_timeout(Duration::from_secs(self.config.request_timeout_seconds));
+        if self.last_submitted == Some((size, root)) {
+            let Some(age) = self.last_submitted_at.map(|at| at.elapsed()) else {
+                return Ok(());
+            };
+            if age < self.resign_interval() {
+                tracing::debug!("Log head unchanged at size {size}, skipping submission");
+                return Ok(());
+            }
+            // A quiet log still needs a fresh signature, so that the signed
+            // head does not go stale
+            tracing::info!(
+                "Log head unchanged at size {size} for {}s, signing it again",
+                age.as_secs()
+            );
+        }
 
-        let response = client
-            .set_auditor_head(request)
-            .await
-            .context(format!("Failed to submit auditor head: {tree_head:?}"))?;
-        Ok(response)
+        if !self.submission_due(size) {
+            tracing::debug!("Log head at size {size} does not meet the submission policy yet");
+            return Ok(());
+        }
+
+        let halted = self
+            .halt
+            .as_ref()
+            .map(HaltState::current)
+            .transpose()?
+            .flatten();
+        if let Some(current) = halted {
+            return Err(anyhow::anyhow!(
+                "Signing is halted ({}): {}",
+                current.halt_id,
+                current.reason
+            ));
+        }
+
+        let tree_head = match &self.pending_head {
+            Some((pending_size, pending_root, head))
+                if *pending_size == size && *pending_root == root =>
+            {
+                tracing::info!("Resubmitting previously signed head at size {size}");
+                head.clone()
+            }
+            _ => {
+                if let Some(journal) = &mut self.journal {
+                    // Recording re-reads the journal, which other processes may have signed to
+                    if let Err(e) = journal.record(size, &root) {
+                        return Err(match e.downcast_ref::<Equivocation>() {
+                            Some(equivocation) => self.diverged(equivocation.to_string()),
+                            None => e.context("Signing journal check failed"),
+                        });
+                    }
+                }
+                let head = self
+                    .auditor
+                    .sign_head(root, size)
+                    .await
+                    .context("Failed to sign auditor head")?;
+                self.pending_head = Some((size, root, head.clone()));
+                head
+            }
+        };
+
+        self.latest_head.set(SignedAuditorHead {
+            tree_head: Some(tree_head.clone()),
+            root: root.to_vec(),
+            build: Some(BUILD_INFO.into()),
+        });
+
+        if self.config.submit_heads {
+            let mut request = Request::new(tree_head.clone());
+            request.set_timeout(self.config.submit_timeout());
+
+            client
+                .set_auditor_head(request)
+                .await
+                .context(format!("Failed to submit auditor head: {tree_head:?}"))?;
+
+            if let Some(mirror) = &self.mirror_endpoint {
+                if let Some(task) = self.mirror_task.take() {
+                    task.abort();
+                }
+                self.mirror_task = Some(tokio::spawn(submit_to_mirror(
+                    mirror.clone(),
+                    tree_head,
+                    self.config.submit_timeout(),
+                    self.config.max_retries,
+                )));
+            }
+        }
+
+        self.last_submitted = Some((size, root));
+        self.last_submitted_at = Some(Instant::now());
+        self.pending_head = None;
+        Ok(())
+    }
+
+    /// Age after which an unchanged head is signed again: `submit_interval_seconds`,
+    /// or an hour if unset.
+    fn resign_interval(&self) -> Duration {
+        self.config
+            .submit_interval_seconds
+            .map_or(DEFAULT_RESIGN_INTERVAL, Duration::from_secs)
+    }
+
+    /// Whether a changed head at `size` should be signed under the submission policy.
+    ///
+    /// The first head after startup, and a head whose submission failed, are always
+    /// submitted. Otherwise, a head is due once
+    /// the log has grown by `submit_min_entries`, or once `submit_interval_seconds`
+    /// have passed since the last submission.
+    fn submission_due(&self, size: u64) -> bool {
+        if self.pending_head.is_some() {
+            return true;
+        }
+        let (Some((last_size, _)), Some(last_at)) = (self.last_submitted, self.last_submitted_at)
+        else {
+            return true;
+        };
+        let (min_entries, interval) = (
+            self.config.submit_min_entries,
+            self.config.submit_interval_seconds,
+        );
+        if min_entries.is_none() && interval.is_none() {
+            return true;
+        }
+        min_entries.is_some_and(|min| size.saturating_sub(last_size) >= min)
+            || interval.is_some_and(|secs| last_at.elapsed() >= Duration::from_secs(secs))
     }
 
     /// Format a duration in hours, minutes, and seconds
@@ -178,15 +985,30 @@ impl KeyTransparencyClient {
         let initial_log_end = self.fetch_log_size().await?;
         tracing::info!("Log end: {initial_log_end}");
 
+        if initial_log_end < self.transparency_log.size() {
+            return Err(self.diverged(format!(
+                "Server reports log size {initial_log_end}, but size {} has been verified",
+                self.transparency_log.size()
+            )));
+        }
+
+        let batch_size = self.config.default_batch_size;
+
+        // Check the server is healthy before starting a sync of more than one round of batches
+        let behind = initial_log_end.saturating_sub(self.transparency_log.size());
+        if behind > batch_size * self.config.max_concurrent_requests as u64
+            && !self.report_server_health().await
+        {
+            return Err(anyhow::anyhow!("Server is not healthy, not starting sync"));
+        }
+
         // Connect to the server
         let transport = self
             .endpoint
             .connect()
             .await
             .context("Failed to connect to server")?;
-        let mut client = KeyTransparencyAuditorServiceClient::new(transport);
-
-        let batch_size = self.config.default_batch_size;
+        let mut client = KeyTransparencyAuditorServiceClient::new(transport.clone());
 
         // Tracks the last log size that we have reported in performance metrics
         let mut progress = self.transparency_log.size();
@@ -195,21 +1017,41 @@ impl KeyTransparencyClient {
         // Are we currently in the initial catch-up sync?
         let mut syncing = true;
 
+        // Cancelled when this function returns, stopping any queued fetches
+        let stopped = CancellationToken::new();
+        let _stop_fetches = stopped.clone().drop_guard();
+
         // Pre-fetch batches in parallel, since fetch latency is the
         // primary bottleneck during sync. During sync the queue contains
-        // `max_concurrent_requests` jobs.
+        // `max_concurrent_requests` jobs, or fewer to stay within `max_inflight_bytes`.
         // During steady-state operation, the queue contains one job.
         let config = self.config.clone();
-        let fetch_client = client.clone();
+        let fetch_client = RawAuditClient::new(transport);
+        let throttle = self.throttle.clone();
         let fetch_job = |start_index| {
-            let mut client: KeyTransparencyAuditorServiceClient<Channel> = fetch_client.clone();
+            let mut client = fetch_client.clone();
             let config = config.clone();
+            let throttle = throttle.clone();
+            let stopped = stopped.clone();
             async move {
-                fetch_audit_entries(&config, &mut client, start_index, Some(batch_size), true).await
+                let fetch = fetch_audit_entries(
+                    &config,
+                    &mut client,
+                    &throttle,
+                    start_index,
+                    Some(batch_size),
+                    true,
+                );
+                tokio::select! {
+                    _ = stopped.cancelled() => Err(Status::cancelled("Audit loop stopped")),
+                    response = fetch => response,
+                }
             }
         };
         let mut queue = VecDeque::new();
-        for i in 0..self.config.max_concurrent_requests as u64 {
+        // Encoded size of the last response, which estimates the size of those in flight
+        let mut response_bytes = None;
+        for i in 0..self.prefetch_depth(response_bytes) as u64 {
             let start_index = progress + batch_size * i;
             queue.push_back(tokio::spawn(fetch_job(start_index)))
         }
@@ -217,23 +1059,109 @@ impl KeyTransparencyClient {
         // Main event loop
         // Does not exit unless an error occurs
         loop {
+            let cycle = Cycle::start(&stopped, self.config.cycle_timeout());
+
             // Wait for the next fetch to complete
-            let response = queue
-                .pop_front()
-                .unwrap()
-                .await
-                .context("Fetch thread panicked")??;
+            let job = queue.pop_front().unwrap();
+            let response = cycle
+                .run("fetch", async {
+                    Ok::<_, anyhow::Error>(job.await.context("Fetch thread panicked")??)
+                })
+                .await?;
+
+            response_bytes = Some(response.updates.iter().map(|u| u.len() as u64).sum());
 
             // Apply the updates to the log
-            for update in &response.updates {
-                self.transparency_log
-                    .apply_update(update.clone())
-                    .context(format!("Failed to apply update: {update:?}"))?;
+            let batch_start = self.transparency_log.size();
+            report::set_batch(batch_start, batch_start + response.updates.len() as u64);
+            let updates: Arc<[AuditorUpdate]> =
+                self.decode_batch(batch_start, &response.updates)?.into();
+            // Verify the whole batch before advancing, so a failure part way through
+            // never leaves the log ahead of what is archived and committed
+            let mut staged = self.transparency_log.clone();
+            let threads = self.config.verify_threads();
+            // The prefix root after each update, if they are recorded
+            let record_roots = self.prefix_roots.is_some();
+            let mut prefix_roots = vec![];
+            let chunk_size = self.config.apply_chunk_size.max(1);
+            for (i, start) in (0..updates.len()).step_by(chunk_size).enumerate() {
+                let chunk = start..(start + chunk_size).min(updates.len());
+                if i > 0 {
+                    // The staged log is discarded, leaving the log at the last batch
+                    cycle.check("apply")?;
+                }
+                // Verification blocks until the verification threads finish,
+                // so it runs on the blocking pool rather than on a runtime worker
+                let verified = tokio::task::spawn_blocking({
+                    let log = staged.clone();
+                    let updates = updates.clone();
+                    let chunk = chunk.clone();
+                    move || {
+                        let mut roots = vec![];
+                        let verified = log.verify_batch_observed(&updates[chunk], threads, |log| {
+                            if record_roots {
+                                roots.extend(log.prefix_root());
+                            }
+                        });
+                        verified.map(|verified| (verified, roots))
+                    }
+                })
+                .await;
+                staged = match verified {
+                    Ok(Ok((verified, roots))) => {
+                        prefix_roots.extend(roots);
+                        verified
+                    }
+                    Ok(Err(e)) => {
+                        let position = staged.size() + e.index as u64;
+                        return Err(self.diverged(format!(
+                            "Update at position {position} failed verification: {}",
+                            e.error
+                        )));
+                    }
+                    // Persist the progress made before the panic, then let the process die
+                    Err(e) if e.is_panic() => {
+                        let prefix_roots = std::mem::take(&mut prefix_roots);
+                        self.commit_after_panic(
+                            staged,
+                            &updates[chunk],
+                            batch_start,
+                            &response.updates,
+                            prefix_roots,
+                        )
+                        .await;
+                        resume_unwind(e.into_panic());
+                    }
+                    Err(e) => return Err(e).context("Verification task failed"),
+                };
+            }
+            // Archive and record the batch before advancing, so that a failure
+            // leaves the log where the archive and history can be retried from
+            if let Err(e) = self.archive_batch(batch_start, &response.updates, &prefix_roots) {
+                return Err(match e.downcast_ref::<RootConflict>() {
+                    Some(conflict) => self.diverged(conflict.to_string()),
+                    None => e,
+                });
             }
+            match &self.history {
+                Some(history) if !response.updates.is_empty() => history
+                    .record_batch(
+                        batch_start,
+                        response.updates.len() as u64,
+                        staged.log_root()?,
+                    )
+                    .await
+                    .context("Failed to record batch history")?,
+                _ => {}
+            }
+            self.transparency_log = staged;
+            self.status.set_size(self.transparency_log.size());
+            report::set_size(self.transparency_log.size());
+            self.coverage.observe(self.transparency_log.size());
 
             // Report progress if we are syncing
             if syncing && last_reported.elapsed().as_secs() > self.config.sync_progress_interval {
-                let log_end = self.fetch_log_size().await?;
+                let log_end = cycle.run("progress", self.fetch_log_size()).await?;
                 let diff = self.transparency_log.size() - progress;
                 progress = self.transparency_log.size();
                 // Report progress, don't use newlines
@@ -247,6 +1175,8 @@ impl KeyTransparencyClient {
                     rate = rate,
                     percent = percent,
                     remaining = remaining,
+                    version = BUILD_INFO.version,
+                    git_commit = BUILD_INFO.git_commit,
                 );
             }
 
@@ -263,35 +1193,202 @@ impl KeyTransparencyClient {
                     syncing = false
                 }
 
-                // Always commit the head to storage before submitting
-                self.storage
-                    .commit_head(&self.transparency_log)
-                    .await
-                    .context("Failed to commit log head")?;
-                self.submit_auditor_head(&mut client)
+                // Always commit the head to storage before submitting,
+                // and check that it was persisted before signing it.
+                // The committed head is updated under the same lock, so the
+                // custody check never sees storage ahead of it
+                let committed_head = self.committed_head.clone();
+                cycle
+                    .run("commit", async {
+                        let mut committed = committed_head.lock().await;
+                        self.storage
+                            .commit_head(&self.transparency_log)
+                            .await
+                            .context("Failed to commit log head")?;
+                        *committed = Some(self.transparency_log.clone());
+                        self.storage
+                            .verify_committed(&self.transparency_log)
+                            .await
+                            .context("Refusing to sign log head")
+                    })
+                    .await?;
+                cycle
+                    .run("snapshot", async {
+                        self.snapshots
+                            .take_due(&mut self.storage, &self.transparency_log)
+                            .await;
+                        Ok(())
+                    })
+                    .await?;
+                cycle
+                    .run("submit", self.submit_auditor_head(&mut client))
                     .await
                     .context("Failed to submit auditor head")?;
+                // Only heads that were actually signed count towards coverage
+                let coverage_latency = self
+                    .last_submitted
+                    .filter(|(size, _)| *size == self.transparency_log.size())
+                    .and_then(|(size, _)| self.coverage.covered(size));
 
-                let log_end = self.fetch_log_size().await?;
+                let log_end = cycle.run("submit", self.fetch_log_size()).await?;
                 // Log the submission; this serves as the primary health metric
-                tracing::info!(type="submit-head", index=self.transparency_log.size(), lag=log_end - self.transparency_log.size());
+                tracing::info!(
+                    type = "submit-head",
+                    index = self.transparency_log.size(),
+                    lag = log_end - self.transparency_log.size(),
+                    coverage_latency_ms = coverage_latency.map(|latency| latency.as_millis() as u64),
+                    version = BUILD_INFO.version,
+                    git_commit = BUILD_INFO.git_commit,
+                );
+                if let Some(latency) = coverage_latency {
+                    self.status.record_coverage_latency(latency);
+                }
+                self.status.record_signed(
+                    self.transparency_log.log_root()?,
+                    self.transparency_log.size(),
+                    log_end.saturating_sub(self.transparency_log.size()),
+                );
 
-                // Wait for the entries to start filling up again
+                // Wait for the entries to start filling up again, outside the cycle deadline
+                drop(cycle);
                 let poll_interval = Duration::from_secs(self.config.poll_interval_seconds);
                 tokio::time::sleep(poll_interval).await;
             }
 
-            // Queue the next job
-            let fetch_start = self.transparency_log.size() + batch_size * (queue.len() as u64);
-            queue.push_back(tokio::spawn(fetch_job(fetch_start)));
+            // Queue the next jobs, up to the depth allowed by the memory budget
+            let depth = if syncing {
+                self.prefetch_depth(response_bytes)
+            } else {
+                1
+            };
+            while queue.len() < depth {
+                let fetch_start = self.transparency_log.size() + batch_size * (queue.len() as u64);
+                queue.push_back(tokio::spawn(fetch_job(fetch_start)));
+            }
+        }
+    }
+}
+
+/// Age after which an unchanged head is signed again, without `submit_interval_seconds`.
+const DEFAULT_RESIGN_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Pause applied when the server throttles a request without a `retry-after` hint.
+const DEFAULT_THROTTLE_PAUSE: Duration = Duration::from_secs(30);
+/// Upper bound on a server-requested pause.
+const MAX_THROTTLE_PAUSE: Duration = Duration::from_secs(600);
+
+/// A pause shared by all fetch tasks, set when the server signals throttling,
+/// so that concurrent fetches back off together rather than independently.
+#[derive(Clone, Default)]
+struct Throttle(Arc<Mutex<Option<Instant>>>);
+
+impl Throttle {
+    /// Pause all fetches for at least `duration` from now.
+    fn pause_for(&self, duration: Duration) {
+        let until = Instant::now() + duration.min(MAX_THROTTLE_PAUSE);
+        let mut paused = self.0.lock().unwrap();
+        if paused.is_none_or(|current| current < until) {
+            *paused = Some(until);
         }
     }
+
+    /// Wait until any current pause has elapsed, including pauses extended
+    /// while waiting.
+    async fn wait(&self) {
+        loop {
+            let paused = *self.0.lock().unwrap();
+            match paused {
+                Some(until) if until > Instant::now() => tokio::time::sleep_until(until).await,
+                _ => return,
+            }
+        }
+    }
+}
+
+/// Parse a `retry-after` hint, in seconds, from the response metadata.
+fn retry_after(status: &Status) -> Option<Duration> {
+    let value = status.metadata().get("retry-after")?.to_str().ok()?;
+    value.trim().parse().ok().map(Duration::from_secs)
 }
 
-/// Load configuration from a YAML file with environment variable support
-pub fn load_config_from_file(path: &Path) -> Result<ClientConfig, anyhow::Error> {
+/// Submit a signed head to the mirror endpoint, retrying independently of the primary.
+/// Failures are reported as `mirror-submit` events rather than interrupting the audit.
+async fn submit_to_mirror(
+    endpoint: ServerEndpoint,
+    tree_head: AuditorTreeHead,
+    timeout: Duration,
+    max_retries: u32,
+) {
+    let size = tree_head.tree_size;
+    let mut attempt = 0;
+    loop {
+        let result = async {
+            let mut client = KeyTransparencyAuditorServiceClient::new(endpoint.connect().await?);
+            let mut request = Request::new(tree_head.clone());
+            request.set_timeout(timeout);
+            client.set_auditor_head(request).await?;
+            Ok::<(), anyhow::Error>(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                tracing::info!(
+                    type = "mirror-submit",
+                    index = size,
+                    success = true,
+                    version = BUILD_INFO.version,
+                    git_commit = BUILD_INFO.git_commit,
+                );
+                return;
+            }
+            Err(e) if attempt < max_retries => {
+                tracing::warn!(
+                    type = "mirror-submit",
+                    index = size,
+                    success = false,
+                    retries_remaining = max_retries - attempt,
+                    error = format!("{e:#}"),
+                );
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                report_error!(format!(
+                    "Failed to submit auditor head at size {size} to mirror: {e:?}"
+                ));
+                return;
+            }
+        }
+    }
+}
+
+/// Load configuration from a YAML or JSON file with environment variable support
+///
+/// The format is JSON for files with a `.json` extension, and YAML otherwise.
+/// SOPS-encrypted files are decrypted in memory before parsing, with the `sops`
+/// binary at `sops_path`.
+pub fn load_config_from_file(
+    path: &Path,
+    sops_path: Option<&Path>,
+) -> Result<ClientConfig, anyhow::Error> {
+    let contents = std::fs::read_to_string(path).context(format!(
+        "Failed to read configuration file {}",
+        path.display()
+    ))?;
+    let (format, name) = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => (FileFormat::Json, "json"),
+        _ => (FileFormat::Yaml, "yaml"),
+    };
+    let contents = if sops::is_encrypted(&contents) {
+        tracing::info!("Decrypting SOPS-encrypted configuration {}", path.display());
+        sops::decrypt(sops_path, path, name)?
+    } else {
+        contents
+    };
+
     let config = Config::builder()
-        .add_source(File::from(path.to_path_buf()).required(true))
+        .add_source(File::from_str(&contents, format))
         .add_source(Environment::with_prefix("AUDIT"))
         .build()
         .context("Failed to build configuration")?;
@@ -299,35 +1396,80 @@ pub fn load_config_from_file(path: &Path) -> Result<ClientConfig, anyhow::Error>
     let client_config: ClientConfig = config
         .try_deserialize()
         .context("Failed to deserialize configuration")?;
+    client_config.validate()?;
 
     Ok(client_config)
 }
 
+/// Fetch up to `limit` encoded updates starting at `start`, for one-shot commands.
+pub async fn fetch_batch(
+    config: &ClientConfig,
+    client: &mut RawAuditClient,
+    start: u64,
+    limit: u64,
+) -> Result<RawAuditResponse, anyhow::Error> {
+    fetch_audit_entries(
+        config,
+        client,
+        &Throttle::default(),
+        start,
+        Some(limit),
+        true,
+    )
+    .await
+    .context(format!(
+        "Failed to fetch updates starting at position {start}"
+    ))
+}
+
 /// Fetch audit entries starting from the given position
 /// If retry is true, we will retry on failure, and report intermediate errors
 async fn fetch_audit_entries(
     config: &ClientConfig,
-    client: &mut KeyTransparencyAuditorServiceClient<Channel>,
+    client: &mut RawAuditClient,
+    throttle: &Throttle,
     start: u64,
     limit: Option<u64>,
     // If true, we will retry on failure, and report the error
     // False is used for head estimation
     retry: bool,
-) -> Result<AuditResponse, Status> {
+) -> Result<RawAuditResponse, Status> {
     let limit = limit.unwrap_or(config.default_batch_size);
 
     let mut retries = if retry { config.max_retries } else { 0 };
+    // When the server first throttled this fetch
+    let mut throttled_since = None;
 
     loop {
+        // Don't send requests while the server has asked us to back off
+        throttle.wait().await;
+
         // Make the request
         let mut request = Request::new(AuditRequest { start, limit });
-        request.set_timeout(Duration::from_secs(config.request_timeout_seconds));
+        request.set_timeout(config.audit_timeout());
         let result = client.audit(request).await;
 
         match result {
             Ok(response) => {
                 return Ok(response.into_inner());
             }
+            Err(status) if status.code() == Code::ResourceExhausted => {
+                // Throttling is not a failure of this request, so pause
+                // every fetch task without consuming a retry, up to a limit
+                let pause = retry_after(&status)
+                    .unwrap_or(DEFAULT_THROTTLE_PAUSE)
+                    .min(MAX_THROTTLE_PAUSE);
+                tracing::warn!(
+                    type = "throttled",
+                    start = start,
+                    pause_seconds = pause.as_secs(),
+                );
+                throttle.pause_for(pause);
+                let since = *throttled_since.get_or_insert_with(Instant::now);
+                if !retry || since.elapsed() + pause > config.max_throttle() {
+                    return Err(status);
+                }
+            }
             Err(status) => {
                 if retries > 0 {
                     if status.code() != Code::OutOfRange {
@@ -350,49 +1492,53 @@ async fn fetch_audit_entries(
     }
 }
 
-#[cfg(not(feature = "gcloud-kms"))]
-async fn create_auditor(client_config: &ClientConfig) -> Result<Auditor, anyhow::Error> {
+/// The deployment mode heads are signed for: third party auditing, since we're an auditor.
+pub const DEPLOYMENT_MODE: DeploymentMode = DeploymentMode::ThirdPartyAuditing;
+
+/// The public configuration heads are signed under, with the configured log
+/// operator keys and the given auditor key.
+pub fn public_config(
+    client_config: &ClientConfig,
+    auditor_key: VerifyingKey,
+) -> Result<PublicConfig, anyhow::Error> {
     let signal_public_key = std::fs::read_to_string(&client_config.signal_public_key)
         .context("Failed to read signal public key")?;
     let vrf_public_key = std::fs::read_to_string(&client_config.vrf_public_key)
         .context("Failed to read VRF public key")?;
+
+    Ok(PublicConfig {
+        mode: DEPLOYMENT_MODE,
+        sig_key: VerifyingKey::from_public_key_pem(&signal_public_key)
+            .context("Failed to parse signal public key")?,
+        vrf_key: VerifyingKey::from_public_key_pem(&vrf_public_key)
+            .context("Failed to parse VRF public key")?,
+        auditor_key,
+    })
+}
+
+#[cfg(not(feature = "gcloud-kms"))]
+pub async fn create_auditor(client_config: &ClientConfig) -> Result<Auditor, anyhow::Error> {
     let auditor_signing_key = std::fs::read_to_string(&client_config.auditor_signing_key)
         .context("Failed to read auditor signing key")?;
 
     let key = SigningKey::from_pkcs8_pem(&auditor_signing_key)
         .context("Failed to parse auditor signing key")?;
 
-    let config = PublicConfig {
-        mode: DeploymentMode::ThirdPartyAuditing, // Assume third party auditing, since we're an auditor...
-        sig_key: VerifyingKey::from_public_key_pem(&signal_public_key)
-            .context("Failed to parse signal public key")?,
-        vrf_key: VerifyingKey::from_public_key_pem(&vrf_public_key)
-            .context("Failed to parse VRF public key")?,
-        auditor_key: key.verifying_key(),
-    };
+    let config = public_config(client_config, key.verifying_key())?;
 
     Ok(Auditor { config, key })
 }
 
 #[cfg(feature = "gcloud-kms")]
-async fn create_auditor(client_config: &ClientConfig) -> Result<Auditor, anyhow::Error> {
-    let signal_public_key = std::fs::read_to_string(&client_config.signal_public_key)
-        .context("Failed to read signal public key")?;
-    let vrf_public_key = std::fs::read_to_string(&client_config.vrf_public_key)
-        .context("Failed to read VRF public key")?;
-
+pub async fn create_auditor(client_config: &ClientConfig) -> Result<Auditor, anyhow::Error> {
     let key_name = client_config.kms_key_version.clone();
     let auditor_public_key = Auditor::get_public_key(&key_name).await?;
 
-    let config = PublicConfig {
-        mode: DeploymentMode::ThirdPartyAuditing, // Assume third party auditing, since we're an auditor...
-        sig_key: VerifyingKey::from_public_key_pem(&signal_public_key)
-            .context("Failed to parse signal public key")?,
-        vrf_key: VerifyingKey::from_public_key_pem(&vrf_public_key)
-            .context("Failed to parse VRF public key")?,
-        auditor_key: VerifyingKey::from_public_key_pem(&auditor_public_key)
+    let config = public_config(
+        client_config,
+        VerifyingKey::from_public_key_pem(&auditor_public_key)
             .context("Failed to parse auditor public key")?,
-    };
+    )?;
 
     Ok(Auditor { config, key_name })
 }
@@ -0,0 +1,1312 @@
+//! One-shot maintenance subcommands.
+//!
+//! These commands operate on the auditor state and exit, rather than
+//! running the audit event loop.
+
+use anyhow::{Context, anyhow};
+use ed25519_dalek::{VerifyingKey, pkcs8::DecodePublicKey};
+use prost::Message;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tonic::Request;
+use tonic::transport::Endpoint;
+
+use crate::archive::{ArchiveReader, replay, replay_from};
+use crate::build_info::{BUILD_INFO, BuildInfo};
+use crate::client::{ClientConfig, DEPLOYMENT_MODE, create_auditor, fetch_batch, public_config};
+use crate::halt::{HaltState, RearmApproval};
+use crate::history::HistoryReader;
+use crate::journal::SigningJournal;
+use crate::prefix_roots;
+use crate::raw_audit::RawAuditClient;
+use crate::statement::{SignedStatement, sign_statement};
+use crate::storage::{Backend, Storage};
+use crate::transport::ServerEndpoint;
+use signal_auditor::Hash;
+use signal_auditor::conformance::{Recorder, Recording};
+use signal_auditor::proto::auditor::SignedAuditorHead;
+use signal_auditor::proto::auditor::auditor_head_service_client::AuditorHeadServiceClient;
+use signal_auditor::proto::kt::key_transparency_auditor_service_client::KeyTransparencyAuditorServiceClient;
+use signal_auditor::proto::transparency::AuditorUpdate;
+use signal_auditor::transcript::Transcript;
+use signal_auditor::transparency::{TransparencyLog, UpdateStats};
+
+/// Number of replayed updates between rebuild progress reports.
+const REBUILD_PROGRESS_INTERVAL: u64 = 1_000_000;
+/// Prefix of the published release statement artifacts, which are named by
+/// image digest.
+const RELEASE_STATEMENT_PREFIX: &str = "release_statement.";
+/// Name of the published revocation statement artifact.
+const REVOCATION_STATEMENT: &str = "revocation_statement.json";
+
+/// The JSON Schema for [`ClientConfig`], pretty-printed.
+pub fn config_schema() -> String {
+    let schema = schemars::schema_for!(ClientConfig);
+    serde_json::to_string_pretty(&schema).expect("schema is serializable")
+}
+
+/// Load the stored log head, verify its MAC, and check that it is internally consistent.
+///
+/// This verifies that the head decodes, that the cached log frontier is well
+/// formed and agrees with the prefix tree size, and that the log root can be
+/// recomputed. The MAC is verified by the storage backend when the head is
+/// loaded, so the command fails if `storage_mac_key_path` is not set, even if
+/// the head is otherwise consistent.
+pub async fn verify_snapshot(config: &ClientConfig) -> Result<(), anyhow::Error> {
+    let mut storage = Backend::init_from_config(config)
+        .await
+        .context("Failed to initialize storage backend")?;
+
+    let head = storage
+        .get_head()
+        .await
+        .context("Failed to load stored log head")?
+        .ok_or(anyhow!("No stored log head found"))?;
+
+    head.verify_consistency()
+        .context("Stored log head is inconsistent")?;
+
+    let root = head.log_root().context("Stored log head is empty")?;
+    let authenticated = config.storage_mac_key_path.is_some();
+    tracing::info!(
+        type = "verify-snapshot",
+        size = head.size(),
+        root = hex::encode(root),
+        authenticated = authenticated,
+    );
+    if !authenticated {
+        return Err(anyhow!(
+            "Stored log head is consistent, but its MAC was not verified; set `storage_mac_key_path`"
+        ));
+    }
+    Ok(())
+}
+
+/// Update statistics for the stored log head, as printed by `stats`.
+#[derive(Serialize)]
+struct StatsReport<'a> {
+    size: u64,
+    /// Number of updates covered by the statistics, which may be less than the
+    /// log size if the head was stored before statistics were tracked
+    recorded: u64,
+    #[serde(flatten)]
+    stats: &'a UpdateStats,
+}
+
+/// Print the update statistics persisted with the stored log head as JSON.
+pub async fn stats(config: &ClientConfig) -> Result<(), anyhow::Error> {
+    let mut storage = Backend::init_from_config(config)
+        .await
+        .context("Failed to initialize storage backend")?;
+    let head = storage
+        .get_head()
+        .await
+        .context("Failed to load stored log head")?
+        .ok_or(anyhow!("No stored log head found"))?;
+
+    let report = StatsReport {
+        size: head.size(),
+        recorded: head.stats().total(),
+        stats: head.stats(),
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// How the stored log head compares with the head rebuilt from the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RebuildOutcome {
+    /// The stored head is the rebuilt head
+    Matches,
+    /// The stored head is an earlier state of the archive, as it is when the
+    /// archive ran ahead of the last commit before a crash
+    Behind,
+    /// The stored head is missing, unreadable or not a state of the archive
+    Differs,
+}
+
+impl RebuildOutcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            RebuildOutcome::Matches => "matches",
+            RebuildOutcome::Behind => "behind",
+            RebuildOutcome::Differs => "differs",
+        }
+    }
+}
+
+/// Reconstruct the log head from the update archive and compare it against the stored head.
+///
+/// If the stored head is missing, unreadable or disagrees with the archive, the
+/// command fails unless `commit` is set, in which case the rebuilt head replaces it.
+/// A stored head that is an earlier state of the archive is reported as behind,
+/// and is advanced to the rebuilt head if `commit` is set.
+pub async fn rebuild(config: &ClientConfig, commit: bool) -> Result<(), anyhow::Error> {
+    let archive_path = config
+        .archive_path
+        .as_ref()
+        .ok_or(anyhow!("Archive path not set"))?;
+
+    let mut storage = Backend::init_from_config(config)
+        .await
+        .context("Failed to initialize storage backend")?;
+
+    let stored = match storage.get_head().await {
+        Ok(head) => head,
+        Err(e) => {
+            tracing::warn!("Stored log head is unreadable: {e:?}");
+            None
+        }
+    };
+
+    // Record the rebuilt root at the stored size, since the archive
+    // may run ahead of the last committed head.
+    let stored_size = stored.as_ref().map(|head| head.size());
+    let mut root_at_stored_size = None;
+    let archive = ArchiveReader::open(archive_path)?;
+    let end = archive.last_position().map(|position| position + 1);
+    let rebuilt = replay(archive.records(), |log| {
+        if Some(log.size()) == stored_size {
+            root_at_stored_size = log.log_root().ok();
+        }
+        if log.size() % REBUILD_PROGRESS_INTERVAL == 0 {
+            tracing::info!(type = "rebuild-progress", size = log.size(), end = end);
+        }
+    })?;
+    rebuilt.verify_consistency()?;
+
+    let rebuilt_root = rebuilt.log_root().context("Archive is empty")?;
+    let matches = match &stored {
+        Some(head) => {
+            let stored_root = head.log_root().ok();
+            stored_root.is_some() && stored_root == root_at_stored_size
+        }
+        None => false,
+    };
+    let outcome = match matches {
+        true if stored_size == Some(rebuilt.size()) => RebuildOutcome::Matches,
+        true => RebuildOutcome::Behind,
+        false => RebuildOutcome::Differs,
+    };
+    tracing::info!(
+        type = "rebuild",
+        size = rebuilt.size(),
+        root = hex::encode(rebuilt_root),
+        stored_size = stored_size,
+        outcome = outcome.as_str(),
+    );
+
+    match outcome {
+        RebuildOutcome::Matches => {
+            tracing::info!("Stored log head matches the archive");
+            return Ok(());
+        }
+        RebuildOutcome::Behind => {
+            tracing::info!(
+                "Stored log head is behind the archive ({} of {} entries)",
+                stored_size.unwrap_or_default(),
+                rebuilt.size()
+            );
+            if !commit {
+                tracing::info!("Re-run with --commit to advance it to the archive");
+                return Ok(());
+            }
+        }
+        RebuildOutcome::Differs => {
+            tracing::warn!("Stored log head does not match the archive");
+            if !commit {
+                return Err(anyhow!(
+                    "Stored log head differs from the archive; re-run with --commit to replace it"
+                ));
+            }
+        }
+    }
+
+    storage
+        .commit_head(&rebuilt)
+        .await
+        .context("Failed to commit rebuilt log head")?;
+    tracing::info!("Committed rebuilt log head at size {}", rebuilt.size());
+    Ok(())
+}
+
+/// The state `verify-range` replays the range from.
+#[derive(Debug, Serialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+enum Frontier {
+    /// The empty log, if no stored state precedes the range
+    Empty,
+    /// The update archive, replayed up to the start of the range, or as far
+    /// towards it as it goes
+    Archive,
+    /// The stored log head, which precedes the start of the range
+    StoredHead,
+    /// The latest snapshot preceding the start of the range
+    Snapshot,
+}
+
+/// The report printed by `verify-range`.
+#[derive(Serialize)]
+struct RangeVerification {
+    start: u64,
+    count: u64,
+    frontier: Frontier,
+    frontier_size: u64,
+    /// The head after the last update in the range
+    end: HeadSummary,
+    /// Number of recorded roots compared against the replay
+    checked: u64,
+    mismatches: Vec<String>,
+}
+
+/// Re-fetch `count` updates starting at `start` from the server, replay them,
+/// and compare the resulting roots with those the auditor recorded, printing a
+/// report as JSON.
+///
+/// The replay starts from the latest stored state at or before `start`: the
+/// stored head or a snapshot, or else the empty log. The update archive then
+/// advances it as far towards `start` as it goes, with any updates before the
+/// start of the archive fetched from the server, and the rest of the gap is
+/// fetched from the server too.
+/// Replayed roots are compared with the archive, the batch history, the
+/// signing journal and the stored head, wherever they record one. Fails if an
+/// update fails verification or any root differs.
+pub async fn verify_range(
+    config: &ClientConfig,
+    start: u64,
+    count: u64,
+) -> Result<(), anyhow::Error> {
+    let end = start
+        .checked_add(count)
+        .filter(|_| count > 0)
+        .ok_or(anyhow!("Invalid range of {count} updates at {start}"))?;
+
+    let mut storage = Backend::init_from_config(config)
+        .await
+        .context("Failed to initialize storage backend")?;
+    let stored = storage
+        .get_head()
+        .await
+        .context("Failed to load stored log head")?
+        .filter(|head| head.is_initialized());
+
+    // Start from the latest stored state at or before `start`
+    let mut frontier = (Frontier::Empty, TransparencyLog::new());
+    if let Some(head) = stored.as_ref().filter(|head| head.size() <= start) {
+        frontier = (Frontier::StoredHead, head.clone());
+    }
+    let snapshot = storage
+        .latest_snapshot(start)
+        .await
+        .context("Failed to read snapshots")?;
+    if let Some((location, snapshot)) =
+        snapshot.filter(|(_, snapshot)| snapshot.size() > frontier.1.size())
+    {
+        tracing::info!(
+            "Replaying from snapshot {location} at size {}",
+            snapshot.size()
+        );
+        frontier = (Frontier::Snapshot, snapshot);
+    }
+    let (mut frontier, mut log) = frontier;
+
+    let endpoint = ServerEndpoint::new(&config.server_endpoint, config)?;
+    let mut client = RawAuditClient::new(
+        endpoint
+            .connect()
+            .await
+            .context("Failed to connect to server")?,
+    );
+
+    // Recorded roots by log size, with where each was recorded
+    let mut expected: BTreeMap<u64, Vec<(&str, String)>> = BTreeMap::new();
+
+    // The archive advances the state towards `start`, and records roots past it
+    if let Some(path) = &config.archive_path {
+        let archive = ArchiveReader::open(path)?;
+        match archive.first_position() {
+            Some(first) if first <= start => {
+                if first > log.size() {
+                    tracing::info!(
+                        "Update archive starts at position {first}, fetching updates {} to {first} from the server",
+                        log.size()
+                    );
+                    for encoded in fetch_range(config, &mut client, log.size(), first).await? {
+                        apply_encoded(&mut log, &encoded)?;
+                    }
+                }
+                let records = archive
+                    .records_from(log.size())
+                    .take_while(|record| !matches!(record, Ok((position, _)) if *position >= end));
+                let mut at_start = (log.size() == start).then(|| log.clone());
+                let replayed = replay_from(log.clone(), records, |log| {
+                    if log.size() == start {
+                        at_start = Some(log.clone());
+                    }
+                    if log.size() > start {
+                        if let Ok(root) = log.log_root() {
+                            expected
+                                .entry(log.size())
+                                .or_default()
+                                .push(("archive", hex::encode(root)));
+                        }
+                    } else if log.size() % REBUILD_PROGRESS_INTERVAL == 0 {
+                        tracing::info!(type = "rebuild-progress", size = log.size(), end = start);
+                    }
+                })
+                .context("Failed to replay the update archive")?;
+                // The archive may end before `start`, in which case the rest is fetched
+                let advanced = at_start.unwrap_or(replayed);
+                if advanced.size() > log.size() {
+                    frontier = Frontier::Archive;
+                    log = advanced;
+                }
+            }
+            Some(first) => {
+                tracing::info!("Update archive starts at position {first}, after the range");
+            }
+            None => {}
+        }
+    }
+    let frontier_size = log.size();
+
+    let stored_in_range = stored
+        .as_ref()
+        .filter(|head| head.size() > frontier_size && head.size() <= end);
+    if let Some(head) = stored_in_range {
+        expected
+            .entry(head.size())
+            .or_default()
+            .push(("stored head", hex::encode(head.log_root()?)));
+    }
+    if let Some(path) = &config.history_db_path {
+        for record in HistoryReader::open(path)?.ending_in(frontier_size, end)? {
+            expected
+                .entry(record.start + record.count)
+                .or_default()
+                .push(("history", record.root));
+        }
+    }
+    if let Some(path) = &config.signing_journal_path {
+        for (size, root) in SigningJournal::open(path)?.signed_in(frontier_size, end) {
+            expected
+                .entry(size)
+                .or_default()
+                .push(("signing journal", hex::encode(root)));
+        }
+    }
+
+    let mut checked = 0;
+    let mut mismatches = vec![];
+    while log.size() < end {
+        let remaining = end - log.size();
+        let response = fetch_batch(
+            config,
+            &mut client,
+            log.size(),
+            remaining.min(config.default_batch_size),
+        )
+        .await?;
+        if response.updates.is_empty() {
+            return Err(anyhow!(
+                "Server returned no updates at position {}, the range may extend past the end of the log",
+                log.size()
+            ));
+        }
+        for encoded in response.updates.iter().take(remaining as usize) {
+            apply_encoded(&mut log, encoded)?;
+            let root = hex::encode(log.log_root()?);
+            for (source, recorded) in expected.remove(&log.size()).unwrap_or_default() {
+                checked += 1;
+                if recorded != root {
+                    mismatches.push(format!(
+                        "Replayed root {root} at size {} differs from the {source} root {recorded}",
+                        log.size()
+                    ));
+                }
+            }
+        }
+    }
+    if checked == 0 {
+        tracing::warn!("No recorded roots in the range, only checked that the updates verify");
+    }
+
+    let verification = RangeVerification {
+        start,
+        count,
+        frontier,
+        frontier_size,
+        end: HeadSummary::new(log.size(), &log.log_root()?),
+        checked,
+        mismatches,
+    };
+    println!("{}", serde_json::to_string_pretty(&verification)?);
+
+    if verification.mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} recorded roots differ from the replayed range",
+            verification.mismatches.len()
+        ))
+    }
+}
+
+/// Decode `encoded` and apply it at the next position of `log`.
+fn apply_encoded(log: &mut TransparencyLog, encoded: &[u8]) -> Result<(), anyhow::Error> {
+    let position = log.size();
+    let update = AuditorUpdate::decode(encoded)
+        .context(format!("Malformed update at position {position}"))?;
+    log.apply_update(update)
+        .context(format!("Update at position {position} failed verification"))?;
+    Ok(())
+}
+
+/// Fetch the encoded updates at positions `start..end` from the server.
+async fn fetch_range(
+    config: &ClientConfig,
+    client: &mut RawAuditClient,
+    start: u64,
+    end: u64,
+) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+    let mut updates = vec![];
+    while start + (updates.len() as u64) < end {
+        let position = start + updates.len() as u64;
+        let remaining = end - position;
+        let response = fetch_batch(
+            config,
+            client,
+            position,
+            remaining.min(config.default_batch_size),
+        )
+        .await?;
+        if response.updates.is_empty() {
+            return Err(anyhow!(
+                "Server returned no updates at position {position}, the range may extend past the end of the log"
+            ));
+        }
+        updates.extend(response.updates.into_iter().take(remaining as usize));
+    }
+    Ok(updates)
+}
+
+/// Export a signed transcript of `count` updates starting at `start`, which can
+/// be verified offline with `Transcript::verify`.
+///
+/// The state at `start` and the updates are taken from the update archive where
+/// it has them, exactly as they were received, and fetched from the server
+/// otherwise, starting from the stored head if the archive does not reach
+/// `start`. The range must end at or before the committed head. The resulting
+/// head is signed like any other, so this refuses to run while signing is
+/// halted, and checks it against the committed head and records it in the
+/// signing journal.
+pub async fn export_transcript(
+    config: &ClientConfig,
+    start: u64,
+    count: u64,
+    output: Option<PathBuf>,
+) -> Result<(), anyhow::Error> {
+    let end = start
+        .checked_add(count)
+        .filter(|_| count > 0)
+        .ok_or(anyhow!("Invalid range of {count} updates at {start}"))?;
+
+    let halted = config
+        .halt_path
+        .as_deref()
+        .map(|path| HaltState::new(path).current())
+        .transpose()?
+        .flatten();
+    if let Some(current) = halted {
+        return Err(anyhow!(
+            "Signing is halted ({}); re-arm the auditor first",
+            current.halt_id
+        ));
+    }
+
+    // Only heads the auditor has verified and committed itself are signed
+    let stored = Backend::init_from_config(config)
+        .await
+        .context("Failed to initialize storage backend")?
+        .get_head()
+        .await
+        .context("Failed to load stored log head")?
+        .filter(|head| head.is_initialized());
+    let committed = stored.as_ref().map_or(0, TransparencyLog::size);
+    if end > committed {
+        return Err(anyhow!(
+            "The range ends at {end}, past the committed head at size {committed}; only ranges the auditor has verified can be exported"
+        ));
+    }
+
+    let mut checkpoint = (start == 0).then(TransparencyLog::new);
+    let mut updates = vec![];
+    if let Some(path) = &config.archive_path {
+        let archive = ArchiveReader::open(path)?;
+        let records = archive
+            .records()
+            .take_while(|record| !matches!(record, Ok((position, _)) if *position >= start));
+        let replayed = replay(records, |log| {
+            if log.size() % REBUILD_PROGRESS_INTERVAL == 0 {
+                tracing::info!(type = "transcript-progress", size = log.size(), end = start);
+            }
+        })
+        .context("Failed to replay the update archive")?;
+        if replayed.size() == start {
+            checkpoint = Some(replayed);
+            for record in archive.encoded_records() {
+                let (position, encoded) = record?;
+                if position >= end {
+                    break;
+                }
+                // Skips records re-archived after a restart
+                if position == start + updates.len() as u64 {
+                    updates.push(encoded.to_vec());
+                }
+            }
+        }
+    }
+
+    let mut checkpoint = match checkpoint {
+        Some(checkpoint) => checkpoint,
+        None => stored
+            .clone()
+            .filter(|head| head.size() <= start)
+            .ok_or(anyhow!(
+                "No stored state at or before position {start}: the archive does not reach it and the stored head is past it"
+            ))?,
+    };
+    if checkpoint.size() < start || (updates.len() as u64) < count {
+        let endpoint = ServerEndpoint::new(&config.server_endpoint, config)?;
+        let mut client = RawAuditClient::new(
+            endpoint
+                .connect()
+                .await
+                .context("Failed to connect to server")?,
+        );
+        for encoded in fetch_range(config, &mut client, checkpoint.size(), start).await? {
+            apply_encoded(&mut checkpoint, &encoded)?;
+        }
+        let fetched = start + updates.len() as u64;
+        updates.extend(fetch_range(config, &mut client, fetched, end).await?);
+    }
+
+    // Check the resulting head against the signing journal before signing it
+    let decoded = updates
+        .iter()
+        .map(|encoded| AuditorUpdate::decode(encoded.as_slice()))
+        .collect::<Result<Vec<_>, _>>()
+        .context("Malformed update in the range")?;
+    let verified = checkpoint.verify_batch(&decoded).map_err(|e| {
+        let position = start + e.index as u64;
+        anyhow::Error::new(e).context(format!("Update at position {position} failed verification"))
+    })?;
+    let root = verified.log_root()?;
+    if let Some(head) = stored.as_ref().filter(|head| head.size() == end) {
+        if head.log_root()? != root {
+            return Err(anyhow!(
+                "Replayed root {} at size {end} differs from the committed root {}",
+                hex::encode(root),
+                hex::encode(head.log_root()?)
+            ));
+        }
+    }
+    if let Some(path) = &config.signing_journal_path {
+        SigningJournal::open(path)?.record(end, &root)?;
+    }
+
+    let auditor = create_auditor(config)
+        .await
+        .context("Failed to initialize auditor")?;
+    let transcript = Transcript::sign(&auditor, &checkpoint, updates).await?;
+    let encoded = serde_json::to_string_pretty(&transcript)?;
+    match &output {
+        Some(path) => {
+            std::fs::write(path, encoded).context(format!("Failed to write {}", path.display()))?
+        }
+        None => println!("{encoded}"),
+    }
+
+    tracing::info!(
+        type = "export-transcript",
+        start = start,
+        count = count,
+        root = hex::encode(root),
+        digest = hex::encode(transcript.digest()),
+    );
+    Ok(())
+}
+
+/// Replay conformance recordings, checking that this build reproduces every
+/// recorded root and signature.
+pub fn conformance_check(recordings: &[PathBuf]) -> Result<(), anyhow::Error> {
+    let threads = std::thread::available_parallelism().map_or(1, usize::from);
+    for path in recordings {
+        let recording: Recording = serde_json::from_slice(
+            &std::fs::read(path).context(format!("Failed to read {}", path.display()))?,
+        )
+        .context(format!("Malformed recording {}", path.display()))?;
+        let log = recording
+            .check(threads)
+            .context(format!("{} does not conform", path.display()))?;
+        tracing::info!(
+            type = "conformance",
+            recording = %path.display(),
+            description = recording.description,
+            responses = recording.responses.len(),
+            size = log.size(),
+            root = log.log_root().ok().map(hex::encode),
+            git_commit = BUILD_INFO.git_commit,
+        );
+    }
+    Ok(())
+}
+
+/// Record the responses for the first `count` updates of the log from the
+/// server, with the golden head after each, for `conformance check`.
+pub async fn record_conformance(
+    config: &ClientConfig,
+    count: u64,
+    description: String,
+    output: &Path,
+) -> Result<(), anyhow::Error> {
+    if count == 0 {
+        return Err(anyhow!("Nothing to record"));
+    }
+    let signal_public_key = std::fs::read_to_string(&config.signal_public_key)
+        .context("Failed to read signal public key")?;
+    let vrf_public_key =
+        std::fs::read_to_string(&config.vrf_public_key).context("Failed to read VRF public key")?;
+    let mut recorder = Recorder::new(
+        description,
+        DEPLOYMENT_MODE,
+        VerifyingKey::from_public_key_pem(&signal_public_key)
+            .context("Failed to parse signal public key")?,
+        VerifyingKey::from_public_key_pem(&vrf_public_key)
+            .context("Failed to parse VRF public key")?,
+    );
+
+    let endpoint = ServerEndpoint::new(&config.server_endpoint, config)?;
+    let mut client = RawAuditClient::new(
+        endpoint
+            .connect()
+            .await
+            .context("Failed to connect to server")?,
+    );
+    let mut position = 0;
+    while position < count {
+        let remaining = count - position;
+        let response = fetch_batch(
+            config,
+            &mut client,
+            position,
+            remaining.min(config.default_batch_size),
+        )
+        .await?;
+        if response.updates.is_empty() {
+            return Err(anyhow!(
+                "Server returned no updates at position {position}, the log may be shorter than {count} updates"
+            ));
+        }
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let updates: Vec<_> = response
+            .updates
+            .into_iter()
+            .take(remaining as usize)
+            .collect();
+        position += updates.len() as u64;
+        let head = recorder.push(updates, timestamp).context(format!(
+            "Updates before position {position} failed verification"
+        ))?;
+        tracing::info!(
+            type = "conformance-record",
+            size = head.tree_size,
+            root = hex::encode(head.root),
+        );
+    }
+
+    let encoded = serde_json::to_string_pretty(&recorder.finish())?;
+    std::fs::write(output, encoded).context(format!("Failed to write {}", output.display()))?;
+    Ok(())
+}
+
+/// A statement binding the auditor public key to the image it runs in.
+#[derive(Serialize)]
+struct ReleaseStatement<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    /// Hex-encoded auditor Ed25519 public key
+    auditor_public_key: String,
+    image_digest: &'a str,
+    /// How the image digest was obtained. Always `operator-asserted`, since the
+    /// digest is supplied by whoever runs the command rather than derived from
+    /// an attestation, so verifiers must check it against the image themselves
+    image_digest_source: &'static str,
+    build: BuildInfo,
+    /// Milliseconds since the Unix epoch
+    timestamp: i64,
+}
+
+/// Sign a statement binding the auditor public key to the container image
+/// digest, and publish it next to the log head under a name derived from the
+/// digest, so that statements for other images are kept.
+///
+/// The digest is taken as given by the operator, and the statement says so.
+pub async fn release_statement(
+    config: &ClientConfig,
+    image_digest: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let image_digest = image_digest
+        .or_else(|| config.image_digest.clone())
+        .ok_or(anyhow!("Image digest not provided"))?;
+    let hex_digest = image_digest
+        .strip_prefix("sha256:")
+        .filter(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or(anyhow!(
+            "Invalid image digest {image_digest:?}, expected sha256: followed by 64 hex digits"
+        ))?;
+
+    let auditor = create_auditor(config)
+        .await
+        .context("Failed to initialize auditor")?;
+
+    let statement = ReleaseStatement {
+        kind: "release-statement",
+        auditor_public_key: hex::encode(auditor.config.auditor_key.as_bytes()),
+        image_digest: &image_digest,
+        image_digest_source: "operator-asserted",
+        build: BUILD_INFO,
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64,
+    };
+    let signed = sign_statement(&auditor, &statement).await?;
+
+    let mut storage = Backend::init_from_config(config)
+        .await
+        .context("Failed to initialize storage backend")?;
+    let location = storage
+        .publish_artifact(
+            &format!(
+                "{RELEASE_STATEMENT_PREFIX}sha256-{}.json",
+                hex_digest.to_ascii_lowercase()
+            ),
+            serde_json::to_vec_pretty(&signed)?,
+        )
+        .await
+        .context("Failed to publish release statement")?;
+
+    tracing::info!(
+        type = "release-statement",
+        image_digest = image_digest,
+        location = location,
+    );
+    Ok(())
+}
+
+/// A statement that auditor signatures made after `not_after` must not be trusted.
+#[derive(Serialize)]
+struct RevocationStatement<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    /// Hex-encoded auditor Ed25519 public key
+    auditor_public_key: String,
+    /// Milliseconds since the Unix epoch after which signatures are revoked
+    not_after: i64,
+    reason: &'a str,
+    /// Milliseconds since the Unix epoch
+    timestamp: i64,
+}
+
+/// Sign a statement revoking auditor signatures made after `not_after`, for use
+/// if the auditor key is compromised.
+///
+/// `not_after` defaults to the current time. If `output` is set, the statement is
+/// written there instead of being published, so that it can be prepared in advance
+/// and kept offline until it is needed.
+pub async fn revocation_statement(
+    config: &ClientConfig,
+    not_after: Option<i64>,
+    reason: &str,
+    output: Option<PathBuf>,
+) -> Result<(), anyhow::Error> {
+    let auditor = create_auditor(config)
+        .await
+        .context("Failed to initialize auditor")?;
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+    let statement = RevocationStatement {
+        kind: "revocation-statement",
+        auditor_public_key: hex::encode(auditor.config.auditor_key.as_bytes()),
+        not_after: not_after.unwrap_or(timestamp),
+        reason,
+        timestamp,
+    };
+    let signed = serde_json::to_vec_pretty(&sign_statement(&auditor, &statement).await?)?;
+
+    let location = match output {
+        Some(path) => {
+            std::fs::write(&path, signed).context(format!("Failed to write {}", path.display()))?;
+            path.display().to_string()
+        }
+        None => {
+            let mut storage = Backend::init_from_config(config)
+                .await
+                .context("Failed to initialize storage backend")?;
+            storage
+                .publish_artifact(REVOCATION_STATEMENT, signed)
+                .await
+                .context("Failed to publish revocation statement")?
+        }
+    };
+
+    tracing::warn!(
+        type = "revocation-statement",
+        not_after = statement.not_after,
+        location = location,
+    );
+    Ok(())
+}
+
+/// A head signed out of band by `sign-head`.
+#[derive(Serialize)]
+struct ManualHead {
+    tree_size: u64,
+    timestamp: i64,
+    /// Hex-encoded log root
+    root: String,
+    /// Hex-encoded auditor signature
+    signature: String,
+    /// The build that signed the head
+    build: BuildInfo,
+}
+
+/// Sign a head out of band, for incident response.
+///
+/// This bypasses log verification entirely, so it requires explicit confirmation,
+/// and refuses to run without a signing journal or to sign a root that conflicts
+/// with one already signed or with the stored log head.
+pub async fn sign_head(
+    config: &ClientConfig,
+    root: &str,
+    size: u64,
+    confirmed: bool,
+) -> Result<(), anyhow::Error> {
+    if !confirmed {
+        return Err(anyhow!(
+            "Manual signing bypasses log verification; pass --i-know-what-i-am-doing to proceed"
+        ));
+    }
+
+    let root: Hash = hex::decode(root)
+        .context("Root is not valid hex")?
+        .try_into()
+        .map_err(|_| anyhow!("Root must be 32 bytes"))?;
+
+    let journal_path = config.signing_journal_path.as_ref().ok_or(anyhow!(
+        "Manual signing requires signing_journal_path to be set"
+    ))?;
+    let mut journal = SigningJournal::open(journal_path)?;
+    journal.check(size, &root)?;
+
+    // Manual signing must not be used to get around the re-arm requirement
+    let halted = config
+        .halt_path
+        .as_deref()
+        .map(|path| HaltState::new(path).current())
+        .transpose()?
+        .flatten();
+    if let Some(current) = halted {
+        return Err(anyhow!(
+            "Signing is halted ({}); re-arm the auditor first",
+            current.halt_id
+        ));
+    }
+
+    // The stored head is the auditor's own verified view, so never contradict it
+    let mut storage = Backend::init_from_config(config)
+        .await
+        .context("Failed to initialize storage backend")?;
+    let stored_root = storage
+        .get_head()
+        .await?
+        .filter(|stored| stored.size() == size)
+        .map(|stored| stored.log_root())
+        .transpose()?;
+    if stored_root.is_some_and(|stored_root| stored_root != root) {
+        return Err(anyhow!(
+            "Root does not match the stored log head at size {size}"
+        ));
+    }
+
+    let auditor = create_auditor(config)
+        .await
+        .context("Failed to initialize auditor")?;
+
+    tracing::warn!(
+        type = "break-glass-sign",
+        size = size,
+        root = hex::encode(root),
+    );
+    journal.record(size, &root)?;
+    let head = auditor.sign_head(root, size).await?;
+
+    let output = ManualHead {
+        tree_size: head.tree_size,
+        timestamp: head.timestamp,
+        root: hex::encode(root),
+        signature: hex::encode(head.signature),
+        build: BUILD_INFO,
+    };
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+/// Resume signing after a divergence halt.
+///
+/// Without a token, prints the current halt and the approval statement that an
+/// operator must sign. With a token, verifies it against the configured operator
+/// key and clears the halt.
+pub fn rearm(config: &ClientConfig, token: Option<&Path>) -> Result<(), anyhow::Error> {
+    let halt_path = config
+        .halt_path
+        .as_ref()
+        .ok_or(anyhow!("Re-arming requires halt_path to be set"))?;
+    let halt = HaltState::new(halt_path);
+
+    let Some(current) = halt.current()? else {
+        tracing::info!("Signing is not halted");
+        return Ok(());
+    };
+
+    let Some(token) = token else {
+        let approval = RearmApproval {
+            kind: "rearm".to_string(),
+            halt_id: current.halt_id.clone(),
+            approver: String::new(),
+            comment: String::new(),
+        };
+        println!("{}", serde_json::to_string_pretty(&current)?);
+        println!("{}", serde_json::to_string(&approval)?);
+        return Ok(());
+    };
+
+    let operator_key_path = config
+        .operator_public_key
+        .as_ref()
+        .ok_or(anyhow!("Re-arming requires operator_public_key to be set"))?;
+    let operator_key = VerifyingKey::from_public_key_pem(
+        &std::fs::read_to_string(operator_key_path)
+            .context("Failed to read operator public key")?,
+    )
+    .context("Failed to parse operator public key")?;
+
+    let token: SignedStatement =
+        serde_json::from_slice(&std::fs::read(token).context("Failed to read approval token")?)
+            .context("Malformed approval token")?;
+
+    let resolved = halt.rearm(&token, &operator_key)?;
+    tracing::info!(
+        "Cleared halt {} at size {}; signing will resume",
+        resolved.halt_id,
+        resolved.size
+    );
+    Ok(())
+}
+
+/// Print the recorded batch history as JSON lines.
+///
+/// With `position`, prints every batch that included that log position;
+/// otherwise prints the most recent `limit` batches.
+pub fn history(
+    config: &ClientConfig,
+    position: Option<u64>,
+    limit: u64,
+) -> Result<(), anyhow::Error> {
+    let path = config
+        .history_db_path
+        .as_ref()
+        .ok_or(anyhow!("history_db_path is not set"))?;
+    let history = HistoryReader::open(path)?;
+    let records = match position {
+        Some(position) => history.containing(position)?,
+        None => history.latest(limit)?,
+    };
+    for record in records {
+        println!("{}", serde_json::to_string(&record)?);
+    }
+    Ok(())
+}
+
+/// A prefix root printed by `prefix-root`.
+#[derive(Serialize)]
+struct RecordedPrefixRoot {
+    position: u64,
+    /// Hex-encoded prefix tree root that the entry at `position` commits to
+    prefix_root: String,
+}
+
+/// Print the prefix tree root recorded for the log entry at `position`.
+pub fn prefix_root(config: &ClientConfig, position: u64) -> Result<(), anyhow::Error> {
+    let path = config
+        .prefix_roots_path
+        .as_ref()
+        .ok_or(anyhow!("prefix_roots_path is not set"))?;
+    let root = prefix_roots::lookup(path, position)?.ok_or(anyhow!(
+        "No prefix root is recorded for position {position}"
+    ))?;
+    let recorded = RecordedPrefixRoot {
+        position,
+        prefix_root: hex::encode(root),
+    };
+    println!("{}", serde_json::to_string(&recorded)?);
+    Ok(())
+}
+
+/// The verdict of `compare-with-server`.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum Verdict {
+    /// The server log is at least as large as every head the auditor has
+    /// verified or signed. Only sizes are compared with the server, since it
+    /// does not serve its roots
+    ConsistentSize,
+    /// The server reports a smaller log than the auditor has verified or signed
+    ServerBehind,
+    /// A stored or published head contradicts the signing journal or each other
+    JournalConflict,
+    /// The published head is not validly signed by the auditor key
+    InvalidAuditorHead,
+}
+
+/// A log size and hex-encoded root.
+#[derive(Serialize)]
+struct HeadSummary {
+    size: u64,
+    root: String,
+}
+
+impl HeadSummary {
+    fn new(size: u64, root: &[u8]) -> Self {
+        Self {
+            size,
+            root: hex::encode(root),
+        }
+    }
+}
+
+/// The report printed by `compare-with-server`.
+#[derive(Serialize)]
+struct Comparison {
+    verdict: Verdict,
+    server_size: u64,
+    stored: Option<HeadSummary>,
+    /// Entries on the server not yet covered by the stored head
+    lag: Option<u64>,
+    /// The largest head in the signing journal
+    last_signed: Option<HeadSummary>,
+    /// The head published on the pull API
+    auditor_head: Option<HeadSummary>,
+    problems: Vec<String>,
+}
+
+/// Compare the server's current tree size with the stored head, the signing
+/// journal and the head published on the pull API, and print a verdict as JSON.
+///
+/// The pull API is queried at `auditor_head`, defaulting to `pull_listen_addr`.
+/// It is skipped with a warning if unavailable, since the running auditor may
+/// be on another host. The published head's signature is verified with
+/// `auditor_public_key`, so no signing credentials are needed. Fails if the
+/// verdict is not [`Verdict::ConsistentSize`].
+pub async fn compare_with_server(
+    config: &ClientConfig,
+    auditor_head: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let endpoint = ServerEndpoint::new(&config.server_endpoint, config)?;
+    let mut client = KeyTransparencyAuditorServiceClient::new(
+        endpoint
+            .connect()
+            .await
+            .context("Failed to connect to server")?,
+    );
+    let mut request = Request::new(());
+    request.set_timeout(config.request_timeout());
+    let server_size = client
+        .tree_size(request)
+        .await
+        .context("Failed to fetch server tree size")?
+        .into_inner()
+        .tree_size;
+
+    let mut storage = Backend::init_from_config(config)
+        .await
+        .context("Failed to initialize storage backend")?;
+    let stored = storage
+        .get_head()
+        .await
+        .context("Failed to load stored log head")?
+        .filter(|head| head.is_initialized())
+        .map(|head| head.log_root().map(|root| (head.size(), root)))
+        .transpose()?;
+
+    let journal = config
+        .signing_journal_path
+        .as_deref()
+        .map(SigningJournal::open)
+        .transpose()?;
+
+    let auditor_head_uri = auditor_head.or_else(|| config.pull_listen_addr.map(local_uri));
+    let published = match auditor_head_uri {
+        Some(uri) => match fetch_auditor_head(&uri, config.request_timeout()).await {
+            Ok(SignedAuditorHead {
+                tree_head: Some(tree_head),
+                root,
+                ..
+            }) => Some((tree_head, root)),
+            Ok(_) => None,
+            Err(e) => {
+                tracing::warn!("Auditor head unavailable from {uri}: {e:#}");
+                None
+            }
+        },
+        None => None,
+    };
+
+    // Check the published head's signature, which covers its root
+    let invalid_head = match &published {
+        Some((tree_head, root)) => {
+            let key_path = config.auditor_public_key.as_ref().ok_or(anyhow!(
+                "Verifying the published head requires auditor_public_key to be set"
+            ))?;
+            let auditor_key = VerifyingKey::from_public_key_pem(
+                &std::fs::read_to_string(key_path).context("Failed to read auditor public key")?,
+            )
+            .context("Failed to parse auditor public key")?;
+            let keys = public_config(config, auditor_key)?;
+            Hash::try_from(root.as_slice())
+                .map_err(|_| anyhow!("Malformed root of {} bytes", root.len()))
+                .and_then(|root| Ok(keys.verify_head(root, tree_head)?))
+                .err()
+        }
+        None => None,
+    };
+    let published = published.map(|(tree_head, root)| (tree_head.tree_size, root));
+
+    let mut verdict = Verdict::ConsistentSize;
+    let mut problems = vec![];
+    let mut behind = |size: u64, what: &str| {
+        if size > server_size {
+            problems.push(format!(
+                "Server tree size {server_size} is smaller than the {what} size {size}"
+            ));
+            verdict = Verdict::ServerBehind;
+        }
+    };
+    if let Some((size, _)) = &stored {
+        behind(*size, "stored head");
+    }
+    if let Some((size, _)) = journal.as_ref().and_then(SigningJournal::latest) {
+        behind(size, "last signed head");
+    }
+    if let Some((size, _)) = &published {
+        behind(*size, "published head");
+    }
+
+    let conflict = |what: &str, size: u64, root: &[u8]| {
+        let signed_root = journal.as_ref()?.get(size)?;
+        (signed_root.as_slice() != root).then(|| {
+            format!(
+                "{what} root {} differs from the signed root {} at size {size}",
+                hex::encode(root),
+                hex::encode(signed_root)
+            )
+        })
+    };
+    let conflicts: Vec<String> = [
+        stored
+            .as_ref()
+            .map(|(size, root)| ("Stored head", *size, root.as_slice())),
+        published
+            .as_ref()
+            .map(|(size, root)| ("Published head", *size, root.as_slice())),
+    ]
+    .into_iter()
+    .flatten()
+    .filter_map(|(what, size, root)| conflict(what, size, root))
+    .collect();
+    if !conflicts.is_empty() {
+        problems.extend(conflicts);
+        verdict = Verdict::JournalConflict;
+    }
+    if let (Some((stored_size, stored_root)), Some((size, root))) = (&stored, &published) {
+        if stored_size == size && stored_root.as_slice() != root.as_slice() {
+            problems.push(format!(
+                "Published head root {} differs from the stored root {} at size {size}",
+                hex::encode(root),
+                hex::encode(stored_root)
+            ));
+            verdict = Verdict::JournalConflict;
+        }
+    }
+    if let Some(e) = invalid_head {
+        problems.push(format!("Published head is invalid: {e:#}"));
+        verdict = Verdict::InvalidAuditorHead;
+    }
+
+    let comparison = Comparison {
+        server_size,
+        stored: stored
+            .as_ref()
+            .map(|(size, root)| HeadSummary::new(*size, root)),
+        lag: stored
+            .as_ref()
+            .map(|(size, _)| server_size.saturating_sub(*size)),
+        last_signed: journal
+            .as_ref()
+            .and_then(SigningJournal::latest)
+            .map(|(size, root)| HeadSummary::new(size, root)),
+        auditor_head: published
+            .as_ref()
+            .map(|(size, root)| HeadSummary::new(*size, root)),
+        problems,
+        verdict,
+    };
+    println!("{}", serde_json::to_string_pretty(&comparison)?);
+
+    match comparison.verdict {
+        Verdict::ConsistentSize => Ok(()),
+        verdict => Err(anyhow!(
+            "The auditor does not agree with the server: {verdict:?}"
+        )),
+    }
+}
+
+/// A URI for the pull API listening on `addr`, connecting over loopback if it
+/// listens on all interfaces.
+fn local_uri(addr: SocketAddr) -> String {
+    let mut addr = addr;
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            SocketAddr::V4(_) => std::net::Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    format!("http://{addr}")
+}
+
+/// Fetch the latest signed head from the pull API at `uri`.
+async fn fetch_auditor_head(
+    uri: &str,
+    timeout: Duration,
+) -> Result<SignedAuditorHead, anyhow::Error> {
+    let channel = Endpoint::from_shared(uri.to_string())?
+        .timeout(timeout)
+        .connect_timeout(timeout)
+        .connect()
+        .await?;
+    Ok(AuditorHeadServiceClient::new(channel)
+        .get_auditor_head(())
+        .await?
+        .into_inner())
+}
@@ -0,0 +1,56 @@
+//! Tracking of how stale the auditor's coverage of the log is.
+//!
+//! The coverage latency of a signed head is the time between the auditor first
+//! seeing that the oldest entry it newly covers was available on the server, and
+//! the head being signed. Availability is observed from the server's reported
+//! tree size and from fetched batches, so it is an upper bound on the server's
+//! own publication time only up to the poll interval.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Observations of the server's log size, and the size covered by signed heads.
+pub struct CoverageTracker {
+    /// (size, first time a log of at least this size was seen), increasing in size
+    observations: VecDeque<(u64, Instant)>,
+    /// Size of the last signed head
+    covered: u64,
+}
+
+impl CoverageTracker {
+    /// Start tracking, with entries up to `covered` already signed.
+    pub fn new(covered: u64) -> Self {
+        Self {
+            observations: VecDeque::new(),
+            covered,
+        }
+    }
+
+    /// Record that the server had at least `size` entries available.
+    pub fn observe(&mut self, size: u64) {
+        let newest = self.observations.back().map_or(self.covered, |(s, _)| *s);
+        if size > newest {
+            self.observations.push_back((size, Instant::now()));
+        }
+    }
+
+    /// Record that a head of `size` was signed, returning its coverage latency
+    /// if it covers entries that were not covered before.
+    pub fn covered(&mut self, size: u64) -> Option<Duration> {
+        if size <= self.covered {
+            return None;
+        }
+        // The first observation past the previous head is when the oldest
+        // newly covered entry was first seen
+        let latency = self
+            .observations
+            .front()
+            .map_or(Duration::ZERO, |(_, seen)| seen.elapsed());
+        while self.observations.front().is_some_and(|(s, _)| *s <= size) {
+            self.observations.pop_front();
+        }
+        self.covered = size;
+        Some(latency)
+    }
+}
@@ -0,0 +1,163 @@
+//! Periodic proof that storage still holds the committed log head.
+//!
+//! Between commits the stored head is otherwise only read at startup, so silent
+//! corruption or a permission regression on the bucket would go unnoticed until
+//! a restart. This task reads the head back on a schedule, validates it along
+//! with its MAC if `storage_mac_key_path` is set, and compares it with the last
+//! head committed by the audit loop, reporting the result as the
+//! `storage_custody_ok` field of a `storage-custody` event.
+//!
+//! The audit loop holds the committed head's lock while it commits, so a check
+//! never reads a head from storage that is newer than the one it compares with.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use signal_auditor::transparency::TransparencyLog;
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::build_info::BUILD_INFO;
+use crate::client::ClientConfig;
+use crate::status::StatusHandle;
+use crate::storage::{Backend, CommitMismatch, Storage};
+
+/// The last log head committed by the audit loop, shared with the custody task.
+#[derive(Clone, Default)]
+pub struct CommittedHead(Arc<Mutex<Option<TransparencyLog>>>);
+
+impl CommittedHead {
+    pub fn new(head: Option<TransparencyLog>) -> Self {
+        Self(Arc::new(Mutex::new(head)))
+    }
+
+    /// Lock the committed head for the duration of a commit or a check.
+    pub async fn lock(&self) -> MutexGuard<'_, Option<TransparencyLog>> {
+        self.0.lock().await
+    }
+}
+
+/// Check custody of the committed head every `interval`, until the process exits.
+pub async fn watch(
+    config: ClientConfig,
+    interval: Duration,
+    committed: CommittedHead,
+    status: StatusHandle,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let result = match Backend::init_from_config(&config).await {
+            Ok(mut storage) => check(&mut storage, &committed).await,
+            Err(e) => Some(Err(CommitMismatch::Unreadable(e))),
+        };
+
+        match result {
+            None => {}
+            Some(Ok(())) => {
+                tracing::info!(
+                    type = "storage-custody",
+                    storage_custody_ok = true,
+                    version = BUILD_INFO.version,
+                    git_commit = BUILD_INFO.git_commit,
+                );
+                status.record_custody(true);
+            }
+            Some(Err(e)) => {
+                tracing::error!(
+                    type = "storage-custody",
+                    storage_custody_ok = false,
+                    reason = e.to_string(),
+                    version = BUILD_INFO.version,
+                    git_commit = BUILD_INFO.git_commit,
+                );
+                report_error!(format!("Storage custody check failed: {e}"));
+                status.record_custody(false);
+            }
+        }
+    }
+}
+
+/// Read the head back from `storage` and compare it with the committed head,
+/// or return `None` if no head has been committed yet.
+async fn check(
+    storage: &mut impl Storage,
+    committed: &CommittedHead,
+) -> Option<Result<(), CommitMismatch>> {
+    let committed = committed.lock().await;
+    let expected = committed.as_ref()?;
+    Some(storage.verify_committed(expected).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::filestore::FileBackend;
+    use crate::storage::{HeadMac, test_log};
+
+    fn backend(path: &std::path::Path, key: u8) -> FileBackend {
+        let mac = HeadMac::new(&[key; 32]).unwrap();
+        FileBackend::new(path, None, Some(mac)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("head");
+        let mut storage = backend(&path, 1);
+        let committed = CommittedHead::default();
+        assert!(check(&mut storage, &committed).await.is_none());
+
+        let head = test_log();
+        storage.commit_head(&head).await.unwrap();
+        *committed.lock().await = Some(head.clone());
+        assert!(matches!(
+            check(&mut storage, &committed).await,
+            Some(Ok(()))
+        ));
+
+        // A head that is not the committed one
+        *committed.lock().await = Some(TransparencyLog::new());
+        assert!(matches!(
+            check(&mut storage, &committed).await,
+            Some(Err(CommitMismatch::Size { .. }))
+        ));
+
+        // A head authenticated with a different key
+        *committed.lock().await = Some(head);
+        assert!(matches!(
+            check(&mut backend(&path, 2), &committed).await,
+            Some(Err(CommitMismatch::Unreadable(_)))
+        ));
+
+        // A corrupted head
+        let mut stored = std::fs::read(&path).unwrap();
+        stored[10] ^= 1;
+        std::fs::write(&path, stored).unwrap();
+        assert!(matches!(
+            check(&mut storage, &committed).await,
+            Some(Err(CommitMismatch::Unreadable(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_check_waits_for_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("head");
+        let mut storage = backend(&path, 1);
+        let committed = CommittedHead::new(Some(TransparencyLog::new()));
+        storage.commit_head(&TransparencyLog::new()).await.unwrap();
+
+        // A check started during a commit sees the head it committed
+        let mut guard = committed.lock().await;
+        let head = test_log();
+        storage.commit_head(&head).await.unwrap();
+        let pending = tokio::spawn({
+            let committed = committed.clone();
+            let mut storage = backend(&path, 1);
+            async move { check(&mut storage, &committed).await }
+        });
+        tokio::task::yield_now().await;
+        *guard = Some(head);
+        drop(guard);
+        assert!(matches!(pending.await.unwrap(), Some(Ok(()))));
+    }
+}
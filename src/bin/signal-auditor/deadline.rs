@@ -0,0 +1,99 @@
+//! Deadlines for each cycle of the audit loop.
+//!
+//! A cycle waits for a fetched batch, applies it, and when the log end is reached,
+//! commits and submits the head. Each stage runs under the cycle's cancellation
+//! token, which a watchdog task cancels once `cycle_timeout_seconds` has passed.
+//! A stuck storage call or RPC then fails the cycle at its next await point, and
+//! the audit loop restarts with backoff rather than hanging indefinitely.
+//!
+//! Cycle tokens are children of a token covering the whole `run_audit` call,
+//! which fetch tasks also watch, so that they stop when the loop exits instead
+//! of running on detached.
+
+use std::future::Future;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// A cycle of the audit loop was cancelled before completing.
+#[derive(Debug)]
+pub struct CycleCancelled {
+    /// The stage that was running when the cycle was cancelled
+    pub stage: &'static str,
+    pub timeout: Option<Duration>,
+}
+
+impl std::fmt::Display for CycleCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.timeout {
+            Some(timeout) => write!(
+                f,
+                "Audit cycle cancelled during {} after exceeding its deadline of {timeout:?}",
+                self.stage
+            ),
+            None => write!(f, "Audit cycle cancelled during {}", self.stage),
+        }
+    }
+}
+
+impl std::error::Error for CycleCancelled {}
+
+/// One cycle of the audit loop, cancelled at its deadline or when dropped.
+pub struct Cycle {
+    token: CancellationToken,
+    timeout: Option<Duration>,
+}
+
+impl Cycle {
+    /// Start a cycle that is cancelled after `timeout`, if set, or when `parent` is.
+    pub fn start(parent: &CancellationToken, timeout: Option<Duration>) -> Self {
+        let token = parent.child_token();
+        if let Some(timeout) = timeout {
+            let watchdog = token.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = tokio::time::sleep(timeout) => watchdog.cancel(),
+                    // The cycle completed
+                    _ = watchdog.cancelled() => {}
+                }
+            });
+        }
+        Self { token, timeout }
+    }
+
+    /// Run one stage of the cycle, failing with [`CycleCancelled`] if the cycle is
+    /// cancelled first. The stage's future is dropped on cancellation.
+    pub async fn run<T>(
+        &self,
+        stage: &'static str,
+        future: impl Future<Output = Result<T, anyhow::Error>>,
+    ) -> Result<T, anyhow::Error> {
+        tokio::select! {
+            biased;
+            _ = self.token.cancelled() => Err(self.cancelled(stage).into()),
+            result = future => result,
+        }
+    }
+
+    /// Fail with [`CycleCancelled`] if the cycle has been cancelled, for stages
+    /// that check between steps rather than awaiting a single future.
+    pub fn check(&self, stage: &'static str) -> Result<(), CycleCancelled> {
+        if self.token.is_cancelled() {
+            return Err(self.cancelled(stage));
+        }
+        Ok(())
+    }
+
+    fn cancelled(&self, stage: &'static str) -> CycleCancelled {
+        CycleCancelled {
+            stage,
+            timeout: self.timeout,
+        }
+    }
+}
+
+impl Drop for Cycle {
+    // Stops the watchdog
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
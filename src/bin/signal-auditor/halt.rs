@@ -0,0 +1,264 @@
+//! The divergence policy: a persistent halt on signing.
+//!
+//! When the auditor observes evidence that the log has diverged from its own
+//! view — the server reporting a smaller log than the auditor has verified, an
+//! update that fails verification, or a request to sign a conflicting root — it
+//! records a halt and refuses to sign until an operator re-arms it.
+//!
+//! Re-arming requires an approval statement signed by the configured operator key
+//! that names the specific halt being cleared, so resuming after a suspected split
+//! view is an auditable decision by a second person, and approvals cannot be replayed.
+
+use anyhow::{Context, anyhow};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::statement::SignedStatement;
+
+/// A recorded halt on signing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Halt {
+    /// Unique identifier of this halt, which approvals must reference
+    pub halt_id: String,
+    pub reason: String,
+    /// The verified log size at the time of the halt
+    pub size: u64,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: i64,
+}
+
+/// An operator's approval to resume signing after a halt.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RearmApproval {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub halt_id: String,
+    /// Who approved resuming, and why
+    pub approver: String,
+    pub comment: String,
+}
+
+/// The persistent halt state, stored as a JSON file at `path`.
+pub struct HaltState {
+    path: PathBuf,
+}
+
+impl HaltState {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// The current halt, if signing is halted.
+    pub fn current(&self) -> Result<Option<Halt>, anyhow::Error> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let data = std::fs::read(&self.path)
+            .context(format!("Failed to read halt state {}", self.path.display()))?;
+        Ok(Some(serde_json::from_slice(&data)?))
+    }
+
+    /// Halt signing. An existing halt is kept, since it is the one that must be reviewed.
+    pub fn halt(&self, reason: &str, size: u64) -> Result<Halt, anyhow::Error> {
+        if let Some(existing) = self.current()? {
+            return Ok(existing);
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let mut hasher = Sha256::new();
+        hasher.update(timestamp.to_be_bytes());
+        hasher.update(size.to_be_bytes());
+        hasher.update(reason.as_bytes());
+        let halt = Halt {
+            halt_id: hex::encode(&hasher.finalize()[..16]),
+            reason: reason.to_string(),
+            size,
+            timestamp,
+        };
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, serde_json::to_vec_pretty(&halt)?)?;
+        Ok(halt)
+    }
+
+    /// Clear the current halt, given an approval signed by `operator_key`.
+    ///
+    /// The halt and the approval are kept alongside the halt file for audit.
+    pub fn rearm(
+        &self,
+        token: &SignedStatement,
+        operator_key: &VerifyingKey,
+    ) -> Result<Halt, anyhow::Error> {
+        let halt = self.current()?.ok_or(anyhow!("Signing is not halted"))?;
+        let resolved = self.resolved_path(&halt.halt_id);
+        if resolved.exists() {
+            return Err(anyhow!("Halt {} was already re-armed", halt.halt_id));
+        }
+
+        token.verify(operator_key)?;
+        let approval: RearmApproval =
+            serde_json::from_str(&token.statement).context("Malformed approval statement")?;
+        if approval.kind != "rearm" {
+            return Err(anyhow!("Statement is not a re-arm approval"));
+        }
+        if approval.halt_id != halt.halt_id {
+            return Err(anyhow!(
+                "Approval is for halt {}, but the current halt is {}",
+                approval.halt_id,
+                halt.halt_id
+            ));
+        }
+
+        let mut approval_path = resolved.clone().into_os_string();
+        approval_path.push(".approval");
+        std::fs::write(&approval_path, serde_json::to_vec_pretty(token)?)?;
+        std::fs::rename(&self.path, &resolved)?;
+
+        tracing::warn!(
+            type = "rearm",
+            halt_id = halt.halt_id,
+            approver = approval.approver,
+            comment = approval.comment,
+        );
+        Ok(halt)
+    }
+
+    /// Where the halt `halt_id` is kept once it has been re-armed.
+    fn resolved_path(&self, halt_id: &str) -> PathBuf {
+        let mut resolved = self.path.clone().into_os_string();
+        resolved.push(format!(".resolved-{halt_id}"));
+        PathBuf::from(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use signal_auditor::auditor::STATEMENT_CONTEXT;
+
+    fn sign(key: &SigningKey, statement: String) -> SignedStatement {
+        let mut msg = STATEMENT_CONTEXT.to_vec();
+        msg.extend_from_slice(statement.as_bytes());
+        SignedStatement {
+            signature: hex::encode(key.sign(&msg).to_bytes()),
+            statement,
+        }
+    }
+
+    fn approval(key: &SigningKey, kind: &str, halt_id: &str) -> SignedStatement {
+        let approval = RearmApproval {
+            kind: kind.to_string(),
+            halt_id: halt_id.to_string(),
+            approver: "operator".to_string(),
+            comment: "investigated".to_string(),
+        };
+        sign(key, serde_json::to_string(&approval).unwrap())
+    }
+
+    fn operator() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    #[test]
+    fn test_halt() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = HaltState::new(&dir.path().join("halt").join("halt.json"));
+        assert!(state.current().unwrap().is_none());
+
+        let halt = state.halt("log shrank", 10).unwrap();
+        assert_eq!(halt.size, 10);
+        let current = state.current().unwrap().unwrap();
+        assert_eq!(current.halt_id, halt.halt_id);
+
+        // The first halt is kept
+        let again = state.halt("update failed verification", 20).unwrap();
+        assert_eq!(again.halt_id, halt.halt_id);
+        assert_eq!(again.reason, "log shrank");
+    }
+
+    #[test]
+    fn test_rearm() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("halt.json");
+        let state = HaltState::new(&path);
+        let key = operator();
+        let token = approval(&key, "rearm", "0");
+        assert!(state.rearm(&token, &key.verifying_key()).is_err());
+
+        let halt = state.halt("log shrank", 10).unwrap();
+        let token = approval(&key, "rearm", &halt.halt_id);
+        let rearmed = state.rearm(&token, &key.verifying_key()).unwrap();
+        assert_eq!(rearmed.halt_id, halt.halt_id);
+        assert!(state.current().unwrap().is_none());
+
+        let resolved = state.resolved_path(&halt.halt_id);
+        assert!(resolved.exists());
+        let mut approval_path = resolved.into_os_string();
+        approval_path.push(".approval");
+        assert!(Path::new(&approval_path).exists());
+    }
+
+    #[test]
+    fn test_rearm_rejects_wrong_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = HaltState::new(&dir.path().join("halt.json"));
+        let key = operator();
+        let halt = state.halt("log shrank", 10).unwrap();
+
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let wrong_key = approval(&other, "rearm", &halt.halt_id);
+        let wrong_kind = approval(&key, "halt", &halt.halt_id);
+        let wrong_halt = approval(&key, "rearm", "00000000000000000000000000000000");
+        let mut tampered = approval(&key, "rearm", &halt.halt_id);
+        tampered.statement = tampered.statement.replace("investigated", "approved");
+        for token in [wrong_key, wrong_kind, wrong_halt, tampered] {
+            assert!(state.rearm(&token, &key.verifying_key()).is_err());
+        }
+
+        // A small-order key accepts this signature on any message unless
+        // verification is strict
+        let mut identity = [0; 32];
+        identity[0] = 1;
+        let weak_key = VerifyingKey::from_bytes(&identity).unwrap();
+        let mut signature = identity.to_vec();
+        signature.extend_from_slice(&[0; 32]);
+        let forged = SignedStatement {
+            statement: approval(&key, "rearm", &halt.halt_id).statement,
+            signature: hex::encode(signature),
+        };
+        assert!(state.rearm(&forged, &weak_key).is_err());
+
+        assert_eq!(state.current().unwrap().unwrap().halt_id, halt.halt_id);
+    }
+
+    #[test]
+    fn test_rearm_rejects_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("halt.json");
+        let state = HaltState::new(&path);
+        let key = operator();
+
+        let first = state.halt("log shrank", 10).unwrap();
+        let token = approval(&key, "rearm", &first.halt_id);
+        state.rearm(&token, &key.verifying_key()).unwrap();
+
+        // An approval does not clear a later halt
+        let second = state.halt("update failed verification", 20).unwrap();
+        assert_ne!(second.halt_id, first.halt_id);
+        assert!(state.rearm(&token, &key.verifying_key()).is_err());
+        assert_eq!(state.current().unwrap().unwrap().halt_id, second.halt_id);
+
+        // Nor a halt restored with a resolved halt id
+        std::fs::write(&path, serde_json::to_vec(&first).unwrap()).unwrap();
+        assert!(state.rearm(&token, &key.verifying_key()).is_err());
+        assert!(state.current().unwrap().is_some());
+    }
+}
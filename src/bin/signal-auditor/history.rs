@@ -0,0 +1,183 @@
+//! A local SQLite database recording every batch applied to the log.
+//!
+//! The history is for forensics: if an entry is later found to be bad, it
+//! shows when the auditor's view incorporated it and which roots followed.
+//! It is not used to recover state.
+//!
+//! The audit loop writes the history through [`History`], on the blocking
+//! thread pool so that a slow disk does not stall the runtime. Queries go
+//! through a [`HistoryReader`], which opens the database read-only and never
+//! creates it.
+
+use anyhow::{Context, anyhow};
+use rusqlite::{Connection, OpenFlags, Params, params};
+use serde::Serialize;
+use signal_auditor::Hash;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A batch of updates applied to the log.
+#[derive(Debug, Serialize)]
+pub struct BatchRecord {
+    /// Log position of the first update in the batch
+    pub start: u64,
+    pub count: u64,
+    /// Hex-encoded log root after the batch was applied
+    pub root: String,
+    /// Milliseconds since the Unix epoch
+    pub applied_at: i64,
+}
+
+/// A writer for the audit history database.
+pub struct History {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl History {
+    /// Open the history database at `path` for writing, creating it if necessary.
+    pub fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path).context(format!(
+            "Failed to open history database {}",
+            path.display()
+        ))?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS batches (
+                 start INTEGER NOT NULL,
+                 count INTEGER NOT NULL,
+                 root BLOB NOT NULL,
+                 applied_at INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS batches_start ON batches (start);",
+        )?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Record that `count` updates starting at `start` were applied, resulting in `root`.
+    pub async fn record_batch(
+        &self,
+        start: u64,
+        count: u64,
+        root: Hash,
+    ) -> Result<(), anyhow::Error> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let applied_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+            conn.lock()
+                .map_err(|_| anyhow!("History database lock poisoned"))?
+                .execute(
+                    "INSERT INTO batches (start, count, root, applied_at) VALUES (?1, ?2, ?3, ?4)",
+                    params![start as i64, count as i64, root.as_slice(), applied_at],
+                )?;
+            Ok(())
+        })
+        .await?
+    }
+}
+
+/// A read-only view of the audit history database.
+pub struct HistoryReader {
+    conn: Connection,
+}
+
+impl HistoryReader {
+    /// Open the existing history database at `path`.
+    pub fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).context(
+            format!("Failed to open history database {}", path.display()),
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// The batches that included log position `position`, oldest first.
+    ///
+    /// A position may appear more than once if the log was resynced.
+    pub fn containing(&self, position: u64) -> Result<Vec<BatchRecord>, anyhow::Error> {
+        self.query(
+            "SELECT start, count, root, applied_at FROM batches
+             WHERE start <= ?1 AND start + count > ?1 ORDER BY rowid",
+            [position as i64],
+        )
+    }
+
+    /// The most recent `limit` batches, oldest first.
+    pub fn latest(&self, limit: u64) -> Result<Vec<BatchRecord>, anyhow::Error> {
+        let mut records = self.query(
+            "SELECT start, count, root, applied_at FROM batches ORDER BY rowid DESC LIMIT ?1",
+            [limit as i64],
+        )?;
+        records.reverse();
+        Ok(records)
+    }
+
+    /// The batches that ended at a log size in `start + 1..=end`, oldest first.
+    pub fn ending_in(&self, start: u64, end: u64) -> Result<Vec<BatchRecord>, anyhow::Error> {
+        self.query(
+            "SELECT start, count, root, applied_at FROM batches
+             WHERE start + count > ?1 AND start + count <= ?2 ORDER BY rowid",
+            [start as i64, end as i64],
+        )
+    }
+
+    fn query(&self, sql: &str, params: impl Params) -> Result<Vec<BatchRecord>, anyhow::Error> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let rows = stmt.query_map(params, |row| {
+            Ok(BatchRecord {
+                start: row.get::<_, i64>(0)? as u64,
+                count: row.get::<_, i64>(1)? as u64,
+                root: hex::encode(row.get::<_, Vec<u8>>(2)?),
+                applied_at: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn starts(records: Vec<BatchRecord>) -> Vec<u64> {
+        records.into_iter().map(|record| record.start).collect()
+    }
+
+    #[tokio::test]
+    async fn test_record_and_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history").join("history.db");
+        let history = History::open(&path).unwrap();
+        history.record_batch(0, 10, [1; 32]).await.unwrap();
+        history.record_batch(10, 5, [2; 32]).await.unwrap();
+        history.record_batch(15, 5, [3; 32]).await.unwrap();
+        // A resync from the start of the log
+        history.record_batch(0, 20, [3; 32]).await.unwrap();
+
+        let reader = HistoryReader::open(&path).unwrap();
+        assert_eq!(starts(reader.containing(12).unwrap()), vec![10, 0]);
+        assert_eq!(starts(reader.containing(20).unwrap()), Vec::<u64>::new());
+        assert_eq!(starts(reader.latest(2).unwrap()), vec![15, 0]);
+        assert_eq!(starts(reader.ending_in(10, 20).unwrap()), vec![10, 15, 0]);
+
+        let latest = reader.latest(1).unwrap();
+        assert_eq!(latest[0].count, 20);
+        assert_eq!(latest[0].root, hex::encode([3; 32]));
+    }
+
+    #[test]
+    fn test_reader_does_not_create() {
+        let dir = tempfile::tempdir().unwrap();
+        let parent = dir.path().join("missing");
+        assert!(HistoryReader::open(&parent.join("history.db")).is_err());
+        assert!(!parent.exists());
+
+        let path = dir.path().join("history.db");
+        assert!(HistoryReader::open(&path).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,249 @@
+//! An append-only journal of every head signed by the auditor.
+//!
+//! Before signing, the journal is checked to ensure the auditor has never
+//! signed a different root for the same log size. Signing two roots for one
+//! size would be visible equivocation by the auditor.
+//!
+//! Each line of the journal is `{size} {hex root} {unix millis}`, and is synced
+//! to disk before the head is signed. Processes that record heads, such as the
+//! auditor and `sign-head`, hold an exclusive lock on the journal while they
+//! re-read, check and append to it, so they never sign conflicting roots.
+
+use anyhow::{Context, anyhow};
+use signal_auditor::Hash;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A request to sign a root that conflicts with one already signed.
+#[derive(Debug)]
+pub struct Equivocation {
+    pub size: u64,
+    pub signed: Hash,
+    pub requested: Hash,
+}
+
+impl std::fmt::Display for Equivocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Refusing to sign root {} at size {}: root {} was already signed at this size",
+            hex::encode(self.requested),
+            self.size,
+            hex::encode(self.signed)
+        )
+    }
+}
+
+impl std::error::Error for Equivocation {}
+
+/// The journal of signed heads.
+pub struct SigningJournal {
+    path: PathBuf,
+    signed: BTreeMap<u64, Hash>,
+}
+
+impl SigningJournal {
+    /// Open the journal at `path`, creating it if necessary, and load its entries.
+    pub fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        let mut signed = BTreeMap::new();
+        if path.exists() {
+            let file = File::open(path)
+                .context(format!("Failed to open signing journal {}", path.display()))?;
+            file.lock_shared()?;
+            signed = read_entries(&file)?.0;
+        } else if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            signed,
+        })
+    }
+
+    /// Check that signing `root` at `size` would not equivocate.
+    ///
+    /// This only checks the entries loaded when the journal was opened or last
+    /// recorded to; [`SigningJournal::record`] checks the journal on disk.
+    pub fn check(&self, size: u64, root: &Hash) -> Result<(), Equivocation> {
+        check(&self.signed, size, root)
+    }
+
+    /// The root signed at `size`, if any.
+    pub fn get(&self, size: u64) -> Option<&Hash> {
+        self.signed.get(&size)
+    }
+
+    /// The heads signed at sizes in `start + 1..=end`, in order.
+    pub fn signed_in(&self, start: u64, end: u64) -> impl Iterator<Item = (u64, &Hash)> {
+        self.signed
+            .range(start + 1..=end)
+            .map(|(size, root)| (*size, root))
+    }
+
+    /// The largest signed head, if any.
+    pub fn latest(&self) -> Option<(u64, &Hash)> {
+        self.signed
+            .last_key_value()
+            .map(|(size, root)| (*size, root))
+    }
+
+    /// Check and durably record a head that is about to be signed.
+    ///
+    /// The journal is locked and re-read first, so that heads recorded by other
+    /// processes, such as `sign-head`, are checked too. A torn final entry left
+    /// by a crash while recording is truncated.
+    pub fn record(&mut self, size: u64, root: &Hash) -> Result<(), anyhow::Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(format!(
+                "Failed to open signing journal {}",
+                self.path.display()
+            ))?;
+        // Released when the file is closed
+        file.lock()?;
+        let (signed, len) = read_entries(&file)?;
+        self.signed = signed;
+        self.check(size, root)?;
+        if self.signed.contains_key(&size) {
+            return Ok(());
+        }
+
+        if file.metadata()?.len() > len {
+            tracing::warn!(
+                "Truncating partial entry at the end of {}",
+                self.path.display()
+            );
+            file.set_len(len)?;
+        }
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        writeln!(file, "{size} {} {timestamp}", hex::encode(root))?;
+        file.sync_all()?;
+        self.signed.insert(size, *root);
+        Ok(())
+    }
+}
+
+fn check(signed: &BTreeMap<u64, Hash>, size: u64, root: &Hash) -> Result<(), Equivocation> {
+    match signed.get(&size) {
+        Some(signed) if signed != root => Err(Equivocation {
+            size,
+            signed: *signed,
+            requested: *root,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Read the entries of the journal, and the length of its complete lines.
+///
+/// A final line without a newline was torn by a crash before it was synced,
+/// and is ignored.
+fn read_entries(mut file: &File) -> Result<(BTreeMap<u64, Hash>, u64), anyhow::Error> {
+    let mut data = vec![];
+    file.read_to_end(&mut data)?;
+    let len = data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+
+    let mut signed = BTreeMap::new();
+    for (i, line) in data[..len].lines().enumerate() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        let (Some(size), Some(root)) = (fields.next(), fields.next()) else {
+            return Err(anyhow!("Malformed signing journal entry on line {}", i + 1));
+        };
+        let size: u64 = size.parse()?;
+        let root: Hash = hex::decode(root)?
+            .try_into()
+            .map_err(|_| anyhow!("Invalid root on line {}", i + 1))?;
+        // A journal that already contains an equivocation must not be used
+        check(&signed, size, &root)?;
+        signed.insert(size, root);
+    }
+    Ok((signed, len as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(path: &Path) -> usize {
+        std::fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    #[test]
+    fn test_equivocation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal");
+        let mut journal = SigningJournal::open(&path).unwrap();
+        journal.record(10, &[1; 32]).unwrap();
+        journal.record(20, &[2; 32]).unwrap();
+
+        let e = journal.record(10, &[3; 32]).unwrap_err();
+        let equivocation = e.downcast_ref::<Equivocation>().unwrap();
+        assert_eq!(equivocation.signed, [1; 32]);
+        assert_eq!(equivocation.requested, [3; 32]);
+        assert!(journal.check(20, &[3; 32]).is_err());
+        assert_eq!(lines(&path), 2);
+
+        // A journal that contains an equivocation is refused
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(file, "20 {} 0", hex::encode([3; 32])).unwrap();
+        assert!(SigningJournal::open(&path).is_err());
+    }
+
+    #[test]
+    fn test_record_is_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal");
+        let mut journal = SigningJournal::open(&path).unwrap();
+        journal.record(10, &[1; 32]).unwrap();
+        journal.record(10, &[1; 32]).unwrap();
+        assert_eq!(lines(&path), 1);
+
+        let journal = SigningJournal::open(&path).unwrap();
+        assert_eq!(journal.get(10), Some(&[1; 32]));
+        assert_eq!(journal.latest(), Some((10, &[1; 32])));
+    }
+
+    #[test]
+    fn test_record_sees_other_writers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal");
+        let mut daemon = SigningJournal::open(&path).unwrap();
+        let mut other = SigningJournal::open(&path).unwrap();
+        other.record(10, &[1; 32]).unwrap();
+
+        // The daemon's view is stale, but recording checks the file
+        assert!(daemon.check(10, &[2; 32]).is_ok());
+        let e = daemon.record(10, &[2; 32]).unwrap_err();
+        assert!(e.is::<Equivocation>());
+        assert_eq!(lines(&path), 1);
+    }
+
+    #[test]
+    fn test_torn_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal");
+        SigningJournal::open(&path)
+            .unwrap()
+            .record(10, &[1; 32])
+            .unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "20 {}", &hex::encode([2; 32])[..10]).unwrap();
+
+        let mut journal = SigningJournal::open(&path).unwrap();
+        assert_eq!(journal.latest(), Some((10, &[1; 32])));
+
+        // The torn entry is replaced by the next one
+        journal.record(20, &[3; 32]).unwrap();
+        let journal = SigningJournal::open(&path).unwrap();
+        assert_eq!(journal.get(20), Some(&[3; 32]));
+        assert_eq!(lines(&path), 2);
+    }
+}
@@ -0,0 +1,121 @@
+//! Periodic re-validation of the log operator's public keys.
+//!
+//! The auditor verifies the log against the signature and VRF keys in its
+//! configuration. This task fetches the keys Signal publishes and compares them
+//! against both the keys the auditor is running with and the configured key files,
+//! so that configuration drift and unexpected key rotation are both reported.
+//!
+//! The published document is JSON with the PEM-encoded keys in the
+//! `signature_key` and `vrf_key` fields.
+
+use anyhow::Context;
+use ed25519_dalek::{VerifyingKey, pkcs8::DecodePublicKey};
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::client::ClientConfig;
+
+/// The shortest accepted `public_keys_check_interval_seconds`, which keeps the
+/// published document from being polled continuously.
+pub const MIN_CHECK_INTERVAL_SECONDS: u64 = 60;
+
+/// The public keys published by the log operator.
+#[derive(Debug, Deserialize)]
+struct PublishedKeys {
+    /// PEM-encoded Ed25519 signature public key
+    signature_key: String,
+    /// PEM-encoded Ed25519 VRF public key
+    vrf_key: String,
+}
+
+/// The keys the auditor is verifying the log with.
+#[derive(Clone, Copy)]
+pub struct RunningKeys {
+    pub sig_key: VerifyingKey,
+    pub vrf_key: VerifyingKey,
+}
+
+/// Compare the published keys against the running and configured keys every
+/// `public_keys_check_interval_seconds`, until the process exits.
+pub async fn watch(
+    client: reqwest::Client,
+    url: String,
+    config: ClientConfig,
+    running: RunningKeys,
+) {
+    let interval = Duration::from_secs(config.public_keys_check_interval_seconds);
+    loop {
+        match check(&client, &url, &config, running).await {
+            Ok(mismatches) if mismatches.is_empty() => {
+                tracing::info!(type = "public-keys", state = "ok");
+            }
+            Ok(mismatches) => {
+                report_error!(format!(
+                    "Public key mismatch against {url}: {}",
+                    mismatches.join("; ")
+                ));
+            }
+            Err(e) => {
+                tracing::warn!(type = "public-keys", state = "unavailable", reason = format!("{e:#}"));
+            }
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Fetch the published keys and describe every mismatch.
+async fn check(
+    client: &reqwest::Client,
+    url: &str,
+    config: &ClientConfig,
+    running: RunningKeys,
+) -> Result<Vec<String>, anyhow::Error> {
+    let published: PublishedKeys = client
+        .get(url)
+        .timeout(config.request_timeout())
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .context("Malformed public key document")?;
+    let published_sig = VerifyingKey::from_public_key_pem(&published.signature_key)
+        .context("Failed to parse published signature key")?;
+    let published_vrf = VerifyingKey::from_public_key_pem(&published.vrf_key)
+        .context("Failed to parse published VRF key")?;
+
+    let mut mismatches = Vec::new();
+    let mut compare = |name: &str, published: &VerifyingKey, local: &VerifyingKey| {
+        if published != local {
+            mismatches.push(format!(
+                "published {name} is {}, but the auditor is running with {}",
+                hex::encode(published.as_bytes()),
+                hex::encode(local.as_bytes())
+            ));
+        }
+    };
+    compare("signature key", &published_sig, &running.sig_key);
+    compare("VRF key", &published_vrf, &running.vrf_key);
+
+    // The configured files are re-read to catch drift that a restart would pick up
+    for (name, path, published) in [
+        ("signature key", &config.signal_public_key, &published_sig),
+        ("VRF key", &config.vrf_public_key, &published_vrf),
+    ] {
+        match read_key(path) {
+            Ok(configured) if configured != *published => {
+                mismatches.push(format!("published {name} differs from {}", path.display()))
+            }
+            Ok(_) => {}
+            Err(e) => mismatches.push(format!("{e:#}")),
+        }
+    }
+    Ok(mismatches)
+}
+
+fn read_key(path: &Path) -> Result<VerifyingKey, anyhow::Error> {
+    let pem =
+        std::fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+    VerifyingKey::from_public_key_pem(&pem).context(format!("Failed to parse {}", path.display()))
+}
@@ -1,16 +1,87 @@
 use anyhow::Context;
-use clap::Parser;
-use std::{
-    path::{Path, PathBuf},
-    time::Duration,
-};
-use tracing::{error, info};
+use clap::{Parser, Subcommand};
+use std::{path::PathBuf, time::Duration};
+use tokio::runtime::{self, Runtime};
+use tracing::info;
 use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
 
+#[cfg(all(feature = "sandbox", not(target_os = "linux")))]
+compile_error!("feature `sandbox` is only supported on Linux");
+
+// jemalloc takes precedence if both allocator features are enabled, so that
+// features stay additive
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[cfg(feature = "stackdriver")]
+const GCP_ERROR_TYPE: &str =
+    "type.googleapis.com/google.devtools.clouderrorreporting.v1beta1.ReportedErrorEvent";
+
+// Defined before the module declarations so that submodules can raise alerts.
+// Every error is logged, but repeats are only reported once per window, with
+// the audit context attached, see `report`.
+macro_rules! report_error {
+    ($message:expr $(, $($field:tt)+)?) => {{
+        let message = ::std::string::ToString::to_string(&$message);
+        match $crate::report::admit(&message) {
+            Some(report) => {
+                #[cfg(feature = "stackdriver")]
+                tracing::error!(
+                    "@type" = $crate::GCP_ERROR_TYPE,
+                    $($($field)+,)?
+                    size = report.size,
+                    batch_start = report.batch_start,
+                    batch_end = report.batch_end,
+                    suppressed = report.suppressed,
+                    message = message.as_str(),
+                );
+                #[cfg(not(feature = "stackdriver"))]
+                tracing::error!(
+                    $($($field)+,)?
+                    size = report.size,
+                    batch_start = report.batch_start,
+                    batch_end = report.batch_end,
+                    suppressed = report.suppressed,
+                    message = message.as_str(),
+                );
+                $crate::report::dispatch(message, report);
+            }
+            // A suppressed repeat is still logged, but not as an Error Reporting event
+            None => tracing::error!($($($field)+,)? repeat = true, message = message.as_str()),
+        }
+    }};
+}
+
 mod client;
-use client::{KeyTransparencyClient, load_config_from_file};
+use client::{ClientConfig, KeyTransparencyClient, RuntimeFlavor, load_config_from_file};
 
+mod archive;
+mod build_info;
+mod commands;
+mod coverage;
+mod custody;
+mod deadline;
+mod halt;
+mod history;
+mod journal;
+mod keycheck;
+mod prefix_roots;
+mod pull;
+mod raw_audit;
+mod report;
+#[cfg(feature = "sandbox")]
+mod sandbox;
+mod snapshot;
+mod sops;
+mod statement;
+mod status;
 mod storage;
+mod transport;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -18,33 +89,367 @@ struct Args {
     /// Path to the configuration file
     #[arg(short, long, default_value = "config.yaml")]
     config: PathBuf,
+
+    /// Absolute path of the `sops` binary, required if the configuration file
+    /// is SOPS-encrypted
+    #[arg(long)]
+    sops_path: Option<PathBuf>,
+
+    /// If the stored log head fails validation, quarantine it and resync
+    /// from the start of the log instead of exiting
+    #[arg(long)]
+    allow_resync: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[cfg(feature = "stackdriver")]
-const GCP_ERROR_TYPE: &str =
-    "type.googleapis.com/google.devtools.clouderrorreporting.v1beta1.ReportedErrorEvent";
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the auditor event loop (default)
+    Run,
+    /// Verify the integrity of the stored log head and exit
+    VerifySnapshot,
+    /// Print the update statistics stored with the log head
+    Stats,
+    /// Compare the server's tree size with the stored head, signing journal and
+    /// published auditor head, and print a verdict
+    CompareWithServer {
+        /// URI of the pull API to fetch the published auditor head from
+        /// (defaults to `pull_listen_addr`)
+        #[arg(long)]
+        auditor_head: Option<String>,
+    },
+    /// Re-fetch a range of updates from the server and check that replaying it
+    /// reproduces the roots the auditor recorded
+    VerifyRange {
+        /// Log position of the first update to verify
+        #[arg(long)]
+        start: u64,
+        /// Number of updates to verify
+        #[arg(long)]
+        count: u64,
+    },
+    /// Export a signed transcript of a range of updates, which can be verified
+    /// offline without access to the log
+    ExportTranscript {
+        /// Log position of the first update to export
+        #[arg(long)]
+        start: u64,
+        /// Number of updates to export
+        #[arg(long)]
+        count: u64,
+        /// Write the transcript to this file instead of standard output
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Rebuild the log head from the update archive and compare it with the stored head
+    Rebuild {
+        /// Replace the stored head with the rebuilt head if they differ
+        #[arg(long)]
+        commit: bool,
+    },
+    /// Sign and publish a statement binding the auditor key to the container image digest
+    ReleaseStatement {
+        /// The image digest, overriding `image_digest` from the configuration
+        #[arg(long)]
+        image_digest: Option<String>,
+    },
+    /// Sign a statement revoking auditor signatures made after a point in time,
+    /// for use if the auditor key is compromised
+    RevocationStatement {
+        /// Milliseconds since the Unix epoch after which signatures must not be
+        /// trusted (defaults to now)
+        #[arg(long)]
+        not_after: Option<i64>,
+        /// Reason for the revocation
+        #[arg(long, default_value = "key compromise")]
+        reason: String,
+        /// Write the statement to this file instead of publishing it
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Sign a log head out of band, bypassing log verification (incident response only)
+    SignHead {
+        /// Hex-encoded log root
+        #[arg(long)]
+        root: String,
+        /// Log size
+        #[arg(long)]
+        size: u64,
+        /// Confirm that the head has been verified by other means
+        #[arg(long = "i-know-what-i-am-doing")]
+        confirmed: bool,
+    },
+    /// Resume signing after a divergence halt, with an operator-signed approval
+    Rearm {
+        /// Path to the signed approval token. Without it, the current halt and
+        /// the statement to approve are printed.
+        #[arg(long)]
+        token: Option<PathBuf>,
+    },
+    /// Query the history of applied batches
+    History {
+        /// Show the batches that included this log position
+        #[arg(long)]
+        position: Option<u64>,
+        /// Number of recent batches to show when no position is given
+        #[arg(long, default_value_t = 20)]
+        limit: u64,
+    },
+    /// Print the prefix tree root recorded for a log position
+    PrefixRoot {
+        /// Log position of the entry
+        #[arg(long)]
+        position: u64,
+    },
+    /// Pinned recordings of log responses and the heads they lead to, for
+    /// confirming that a build behaves like the reviewed one
+    Conformance {
+        #[command(subcommand)]
+        command: ConformanceCommand,
+    },
+    /// Configuration file utilities
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
 
-macro_rules! gcp_error {
-    ($message:expr) => {
-        #[cfg(feature = "stackdriver")]
-        error!("@type" = GCP_ERROR_TYPE, message = $message,);
-        #[cfg(not(feature = "stackdriver"))]
-        error!(message = $message);
-    };
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the JSON Schema for the configuration file
+    Schema,
 }
 
+#[derive(Subcommand, Debug)]
+enum ConformanceCommand {
+    /// Replay recordings and check that every recorded root and signature is
+    /// reproduced
+    Check {
+        /// Recording files
+        #[arg(required = true)]
+        recordings: Vec<PathBuf>,
+    },
+    /// Record the responses for the start of the log from the configured server
+    Record {
+        /// Number of updates to record
+        #[arg(long)]
+        count: u64,
+        /// Where and when the responses are recorded
+        #[arg(long)]
+        description: String,
+        /// File to write the recording to
+        #[arg(long)]
+        output: PathBuf,
+    },
+}
+
+/// Number of consecutive audit failures after which the server health is probed.
+const HEALTH_PROBE_AFTER_FAILURES: u32 = 3;
+
 // TODO - improve error handling, distinguish between fatal and non-fatal errors
 // TODO - distinguish between measured and unmeasured config items
-#[tokio::main]
-async fn main() {
+fn main() {
     let args = Args::parse();
 
-    if let Err(e) = run(&args.config).await {
-        gcp_error!(format!("Error running audit: {e:?}"));
+    // Printed to stdout before logging starts, so the output can be piped
+    if let Some(Command::Config {
+        command: ConfigCommand::Schema,
+    }) = &args.command
+    {
+        println!("{}", commands::config_schema());
+        return;
+    }
+
+    init_tracing();
+    build_info::log_build_info();
+
+    // Recordings are self-contained, so checking them needs no configuration
+    if let Some(Command::Conformance {
+        command: ConformanceCommand::Check { recordings },
+    }) = &args.command
+    {
+        if let Err(e) = commands::conformance_check(recordings) {
+            report_error!(format!("Conformance check failed: {e:?}"));
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Load configuration from YAML or JSON file
+    // The runtime shape is configurable, so this happens before the runtime is started
+    let config = match load_config_from_file(&args.config, args.sops_path.as_deref())
+        .context("Failed to load config")
+    {
+        Ok(config) => config,
+        Err(e) => {
+            report_error!(format!("{e:?}"));
+            std::process::exit(1);
+        }
+    };
+
+    // Landlock applies to threads created after it, so this precedes the runtime
+    #[cfg(feature = "sandbox")]
+    if matches!(args.command, None | Some(Command::Run)) {
+        sandbox::restrict_paths(&config).unwrap_or_else(|e| {
+            report_error!(format!("Failed to sandbox filesystem access: {e:?}"));
+            std::process::exit(1);
+        });
     }
+
+    let runtime = match build_runtime(&config) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            report_error!(format!("Failed to start runtime: {e:?}"));
+            std::process::exit(1);
+        }
+    };
+
+    runtime.block_on(async move {
+        if let Err(e) = transport::http_client(&config)
+            .and_then(|client| report::install(&config.error_reporting, client))
+        {
+            report_error!(format!("Failed to configure error reporting: {e:?}"));
+            std::process::exit(1);
+        }
+        match args.command.unwrap_or(Command::Run) {
+            Command::Run => {
+                if let Err(e) = run(config, args.allow_resync).await {
+                    report_error!(format!("Error running audit: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::VerifySnapshot => {
+                if let Err(e) = commands::verify_snapshot(&config).await {
+                    report_error!(format!("Snapshot verification failed: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::Stats => {
+                if let Err(e) = commands::stats(&config).await {
+                    report_error!(format!("Failed to read statistics: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::CompareWithServer { auditor_head } => {
+                if let Err(e) = commands::compare_with_server(&config, auditor_head).await {
+                    report_error!(format!("Comparison with server failed: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::VerifyRange { start, count } => {
+                if let Err(e) = commands::verify_range(&config, start, count).await {
+                    report_error!(format!("Range verification failed: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::ExportTranscript {
+                start,
+                count,
+                output,
+            } => {
+                if let Err(e) = commands::export_transcript(&config, start, count, output).await {
+                    report_error!(format!("Transcript export failed: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::Rebuild { commit } => {
+                if let Err(e) = commands::rebuild(&config, commit).await {
+                    report_error!(format!("Rebuild failed: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::ReleaseStatement { image_digest } => {
+                if let Err(e) = commands::release_statement(&config, image_digest).await {
+                    report_error!(format!("Failed to publish release statement: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::RevocationStatement {
+                not_after,
+                reason,
+                output,
+            } => {
+                if let Err(e) =
+                    commands::revocation_statement(&config, not_after, &reason, output).await
+                {
+                    report_error!(format!("Failed to create revocation statement: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::SignHead {
+                root,
+                size,
+                confirmed,
+            } => {
+                if let Err(e) = commands::sign_head(&config, &root, size, confirmed).await {
+                    report_error!(format!("Manual signing failed: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::Rearm { token } => {
+                if let Err(e) = commands::rearm(&config, token.as_deref()) {
+                    report_error!(format!("Re-arm failed: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::History { position, limit } => {
+                if let Err(e) = commands::history(&config, position, limit) {
+                    report_error!(format!("History query failed: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::PrefixRoot { position } => {
+                if let Err(e) = commands::prefix_root(&config, position) {
+                    report_error!(format!("Prefix root query failed: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::Conformance {
+                command:
+                    ConformanceCommand::Record {
+                        count,
+                        description,
+                        output,
+                    },
+            } => {
+                if let Err(e) =
+                    commands::record_conformance(&config, count, description, &output).await
+                {
+                    report_error!(format!("Conformance recording failed: {e:?}"));
+                    report::exit(1).await;
+                }
+            }
+            Command::Config { .. }
+            | Command::Conformance {
+                command: ConformanceCommand::Check { .. },
+            } => unreachable!("handled before loading the configuration"),
+        }
+    });
 }
 
-async fn run(config_path: &Path) -> Result<(), anyhow::Error> {
+/// Build the tokio runtime described by the configuration, and the thread pool
+/// that verifies updates in parallel.
+fn build_runtime(config: &ClientConfig) -> Result<Runtime, anyhow::Error> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(config.verify_threads())
+        .thread_name(|i| format!("verify-{i}"))
+        .build_global()?;
+    let mut builder = match config.runtime_flavor {
+        RuntimeFlavor::MultiThread => {
+            let mut builder = runtime::Builder::new_multi_thread();
+            if let Some(worker_threads) = config.worker_threads {
+                builder.worker_threads(worker_threads);
+            }
+            builder
+        }
+        RuntimeFlavor::CurrentThread => runtime::Builder::new_current_thread(),
+    };
+    Ok(builder.enable_all().build()?)
+}
+
+fn init_tracing() {
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
 
     let builder = tracing_subscriber::registry().with(env_filter);
@@ -54,21 +459,105 @@ async fn run(config_path: &Path) -> Result<(), anyhow::Error> {
 
     #[cfg(not(feature = "stackdriver"))]
     builder.with(tracing_subscriber::fmt::layer()).init();
+}
+
+/// Report panics as fatal errors, with the audit progress at the time of the panic.
+///
+/// The default hook still runs afterwards, so the panic message and backtrace
+/// are printed to stderr as usual.
+fn install_panic_hook(status: status::StatusHandle) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("non-string panic payload");
+        let location = info.location().map(ToString::to_string);
+        // The status lock may be held by the panicking thread
+        let (last_signed_size, last_signed_root) = status
+            .try_progress()
+            .map_or((None, None), |(_, signed_size, signed_root)| {
+                (Some(signed_size), signed_root.map(hex::encode))
+            });
+        report_error!(
+            format!("Panic: {message}"),
+            type = "fatal",
+            location = location,
+            last_signed_size = last_signed_size,
+            last_signed_root = last_signed_root
+        );
+        default_hook(info);
+    }));
+}
 
-    // Load configuration from YAML file
-    let config = load_config_from_file(config_path).context("Failed to load config")?;
+async fn run(config: ClientConfig, allow_resync: bool) -> Result<(), anyhow::Error> {
+    let status_listen_addr = config.status_listen_addr;
+    let pull_listen_addr = config.pull_listen_addr;
+    let key_check = match &config.public_keys_url {
+        Some(url) => Some((
+            transport::http_client(&config)?,
+            url.clone(),
+            config.clone(),
+        )),
+        None => None,
+    };
+    let custody = config
+        .storage_custody_interval_seconds
+        .map(|secs| (Duration::from_secs(secs), config.clone()));
+    let mut client = KeyTransparencyClient::new(config, allow_resync).await?;
+    client.log_startup_summary();
+    install_panic_hook(client.status());
+    if let Some((http, url, config)) = key_check {
+        tokio::spawn(keycheck::watch(http, url, config, client.running_keys()));
+    }
+    if let Some((interval, config)) = custody {
+        tokio::spawn(custody::watch(
+            config,
+            interval,
+            client.committed_head(),
+            client.status(),
+        ));
+    }
+    if let Some(addr) = pull_listen_addr {
+        let latest = client.latest_head();
+        tokio::spawn(async move {
+            if let Err(e) = pull::serve(addr, latest).await {
+                report_error!(format!("Pull API server failed: {e:?}"));
+            }
+        });
+    }
+    if let Some(addr) = status_listen_addr {
+        let handle = client.status();
+        tokio::spawn(async move {
+            if let Err(e) = status::serve(addr, handle).await {
+                report_error!(format!("Status server failed: {e:?}"));
+            }
+        });
+    }
+    // Keys are loaded and the server connection is configured, so the process
+    // no longer needs the syscalls used during startup
+    #[cfg(feature = "sandbox")]
+    sandbox::restrict_syscalls()?;
 
-    let mut client = KeyTransparencyClient::new(config).await?;
     let mut backoff = Duration::from_secs(10);
+    let mut failures = 0;
     loop {
         info!("Running audit...");
         if let Err(e) = client.run_audit().await {
-            gcp_error!(format!("Error running audit: {e:?}"));
+            report_error!(format!("Error running audit: {e:?}"));
+            client.status().record_error(&e);
+            failures += 1;
+            // Distinguish server failures from network failures after repeated errors
+            if failures >= HEALTH_PROBE_AFTER_FAILURES {
+                client.report_server_health().await;
+            }
             info!("backing off for {backoff:?}");
             tokio::time::sleep(backoff).await;
             backoff = backoff.mul_f32(2.0).min(Duration::from_secs(600));
         } else {
-            gcp_error!("Unexpected audit exit");
+            report_error!("Unexpected audit exit");
         }
     }
 }
@@ -0,0 +1,318 @@
+//! An optional append-only record of the prefix tree root at every log position.
+//!
+//! Each log entry commits to the prefix tree root after its update, but the log
+//! head only keeps the current root. Recording every root lets the auditor later
+//! show which prefix root it believed a historical entry committed to, to
+//! adjudicate disputes about specific entries without replaying the log.
+//!
+//! The file holds the big-endian `u64` position of its first root, followed by
+//! the 32-byte root for each position in order. Roots are hash outputs, which
+//! general-purpose compression cannot shrink, so the encoding is kept compact by
+//! leaving positions implicit instead: each position costs exactly 32 bytes,
+//! and the root for any position is read from a fixed offset.
+//!
+//! A root recorded again after a restart from an older committed head must match
+//! the root already recorded for that position. A partial root left by a crash is
+//! truncated when the file is opened.
+
+use anyhow::{Context, anyhow};
+use signal_auditor::Hash;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+/// Size of the header holding the first position.
+const HEADER: u64 = 8;
+/// Size of a recorded root.
+const ROOT: u64 = 32;
+
+/// A prefix root that differs from the one recorded for its position.
+#[derive(Debug)]
+pub struct RootConflict {
+    pub position: u64,
+    pub recorded: Hash,
+    pub root: Hash,
+}
+
+impl std::fmt::Display for RootConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Prefix root {} at position {} differs from the recorded root {}",
+            hex::encode(self.root),
+            self.position,
+            hex::encode(self.recorded)
+        )
+    }
+}
+
+impl std::error::Error for RootConflict {}
+
+/// A writer for the prefix root record.
+pub struct PrefixRoots {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    /// Position of the first recorded root, once one has been recorded
+    first: Option<u64>,
+    /// Number of recorded roots, including buffered ones
+    count: u64,
+}
+
+impl PrefixRoots {
+    /// Open the record at `path` for appending, creating it if necessary.
+    pub fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .context(format!("Failed to open prefix roots {}", path.display()))?;
+        let (first, count) = read_header(&file)?;
+        // Drop a partial root or header left by a crash
+        let len = first.map_or(0, |_| HEADER + count * ROOT);
+        if file.metadata()?.len() != len {
+            tracing::warn!("Truncating partial record at the end of {}", path.display());
+            file.set_len(len)?;
+        }
+        tracing::info!("Recording prefix roots to {}", path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(file),
+            first,
+            count,
+        })
+    }
+
+    /// Record the prefix roots after each update of a batch starting at `start`.
+    ///
+    /// Roots are buffered until [`PrefixRoots::sync`] is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RootConflict`] if a root was already recorded for a position
+    /// and differs, or an error if the batch does not follow the recorded roots.
+    pub fn append(&mut self, start: u64, roots: &[Hash]) -> Result<(), anyhow::Error> {
+        let first = match self.first {
+            Some(first) => first,
+            None => {
+                self.writer.write_all(&start.to_be_bytes())?;
+                *self.first.insert(start)
+            }
+        };
+        for (position, root) in (start..).zip(roots) {
+            let end = first + self.count;
+            if position < first {
+                // Before recording was enabled
+                continue;
+            }
+            if position < end {
+                let recorded = self
+                    .get(position)?
+                    .ok_or(anyhow!("Prefix root at position {position} is missing"))?;
+                if recorded != *root {
+                    return Err(RootConflict {
+                        position,
+                        recorded,
+                        root: *root,
+                    }
+                    .into());
+                }
+                continue;
+            }
+            if position > end {
+                return Err(anyhow!(
+                    "Prefix roots are missing positions {end} to {}",
+                    position - 1
+                ));
+            }
+            self.writer.write_all(root)?;
+            self.count += 1;
+        }
+        Ok(())
+    }
+
+    /// Flush buffered roots and sync them to disk.
+    pub fn sync(&mut self) -> Result<(), anyhow::Error> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data().context(format!(
+            "Failed to sync prefix roots {}",
+            self.path.display()
+        ))
+    }
+
+    /// The recorded root for `position`, if any.
+    fn get(&mut self, position: u64) -> Result<Option<Hash>, anyhow::Error> {
+        self.writer.flush()?;
+        read_root(self.writer.get_ref(), self.first, self.count, position)
+    }
+}
+
+/// Look up the prefix root recorded for `position` in the record at `path`.
+pub fn lookup(path: &Path, position: u64) -> Result<Option<Hash>, anyhow::Error> {
+    let file =
+        File::open(path).context(format!("Failed to open prefix roots {}", path.display()))?;
+    let (first, count) = read_header(&file)?;
+    read_root(&file, first, count, position)
+}
+
+/// Read the first position and the number of complete roots in `file`.
+fn read_header(file: &File) -> Result<(Option<u64>, u64), anyhow::Error> {
+    let mut header = [0u8; HEADER as usize];
+    match file.read_exact_at(&mut header, 0) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok((None, 0)),
+        Err(e) => return Err(e.into()),
+    }
+    let count = (file.metadata()?.len() - HEADER) / ROOT;
+    Ok((Some(u64::from_be_bytes(header)), count))
+}
+
+fn read_root(
+    file: &File,
+    first: Option<u64>,
+    count: u64,
+    position: u64,
+) -> Result<Option<Hash>, anyhow::Error> {
+    let Some(offset) = first
+        .and_then(|first| position.checked_sub(first))
+        .filter(|offset| *offset < count)
+    else {
+        return Ok(None);
+    };
+    let mut root = Hash::default();
+    file.read_exact_at(&mut root, HEADER + offset * ROOT)?;
+    Ok(Some(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(i: u8) -> Hash {
+        [i; 32]
+    }
+
+    fn write(path: &Path, start: u64, roots: &[Hash]) {
+        let mut record = PrefixRoots::open(path).unwrap();
+        record.append(start, roots).unwrap();
+        record.sync().unwrap();
+    }
+
+    fn len(path: &Path) -> u64 {
+        std::fs::metadata(path).unwrap().len()
+    }
+
+    #[test]
+    fn test_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roots");
+        write(&path, 10, &[root(0), root(1)]);
+        write(&path, 12, &[root(2)]);
+        assert_eq!(len(&path), HEADER + 3 * ROOT);
+
+        for i in 0..3 {
+            assert_eq!(lookup(&path, 10 + i).unwrap(), Some(root(i as u8)));
+        }
+        // Before the first recorded position, and after the last
+        assert_eq!(lookup(&path, 9).unwrap(), None);
+        assert_eq!(lookup(&path, 13).unwrap(), None);
+    }
+
+    #[test]
+    fn test_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roots");
+        PrefixRoots::open(&path).unwrap();
+        assert_eq!(len(&path), 0);
+        assert_eq!(lookup(&path, 0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rerecord_matching() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roots");
+        write(&path, 0, &[root(0), root(1)]);
+
+        // A restart from an older head records some roots again
+        write(&path, 1, &[root(1), root(2)]);
+        assert_eq!(len(&path), HEADER + 3 * ROOT);
+        assert_eq!(lookup(&path, 2).unwrap(), Some(root(2)));
+
+        // Positions before the first recorded one are skipped
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roots");
+        write(&path, 5, &[root(5)]);
+        write(&path, 3, &[root(3), root(4), root(5), root(6)]);
+        assert_eq!(len(&path), HEADER + 2 * ROOT);
+        assert_eq!(lookup(&path, 4).unwrap(), None);
+        assert_eq!(lookup(&path, 6).unwrap(), Some(root(6)));
+    }
+
+    #[test]
+    fn test_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roots");
+        write(&path, 0, &[root(0), root(1)]);
+
+        let mut record = PrefixRoots::open(&path).unwrap();
+        let error = record.append(0, &[root(0), root(9)]).unwrap_err();
+        let conflict = error.downcast_ref::<RootConflict>().unwrap();
+        assert_eq!(conflict.position, 1);
+        assert_eq!(conflict.recorded, root(1));
+        assert_eq!(conflict.root, root(9));
+
+        // A buffered root is checked as well
+        record.append(2, &[root(2)]).unwrap();
+        let error = record.append(2, &[root(3)]).unwrap_err();
+        assert!(error.is::<RootConflict>());
+    }
+
+    #[test]
+    fn test_missing_positions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roots");
+        write(&path, 0, &[root(0), root(1)]);
+
+        let mut record = PrefixRoots::open(&path).unwrap();
+        let error = record.append(4, &[root(4)]).unwrap_err();
+        assert!(!error.is::<RootConflict>());
+        assert!(error.to_string().contains("missing positions 2 to 3"));
+        record.sync().unwrap();
+        assert_eq!(len(&path), HEADER + 2 * ROOT);
+    }
+
+    #[test]
+    fn test_partial_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roots");
+        write(&path, 7, &[root(7), root(8)]);
+
+        // A crash part way through writing a root
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&root(9)[..10]).unwrap();
+        drop(file);
+        assert_eq!(lookup(&path, 9).unwrap(), None);
+
+        write(&path, 9, &[root(9)]);
+        assert_eq!(len(&path), HEADER + 3 * ROOT);
+        assert_eq!(lookup(&path, 8).unwrap(), Some(root(8)));
+        assert_eq!(lookup(&path, 9).unwrap(), Some(root(9)));
+    }
+
+    #[test]
+    fn test_partial_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roots");
+        std::fs::write(&path, [0, 0, 0]).unwrap();
+        assert_eq!(lookup(&path, 0).unwrap(), None);
+
+        // The header is written again, with the first position recorded
+        write(&path, 4, &[root(4)]);
+        assert_eq!(len(&path), HEADER + ROOT);
+        assert_eq!(lookup(&path, 4).unwrap(), Some(root(4)));
+    }
+}
@@ -0,0 +1,52 @@
+//! A gRPC service from which the latest signed head can be pulled.
+//!
+//! This is an alternative to pushing heads with `set_auditor_head`, for
+//! deployments where the auditor should not hold credentials for submitting
+//! to the key transparency service.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use tonic::{Request, Response, Status};
+
+use signal_auditor::proto::auditor::SignedAuditorHead;
+use signal_auditor::proto::auditor::auditor_head_service_server::{
+    AuditorHeadService, AuditorHeadServiceServer,
+};
+
+/// A shared handle to the most recently signed head.
+#[derive(Clone, Default)]
+pub struct LatestHead(Arc<RwLock<Option<SignedAuditorHead>>>);
+
+impl LatestHead {
+    /// Replace the latest signed head.
+    pub fn set(&self, head: SignedAuditorHead) {
+        *self.0.write().unwrap() = Some(head);
+    }
+
+    /// The latest signed head, if any.
+    pub fn get(&self) -> Option<SignedAuditorHead> {
+        self.0.read().unwrap().clone()
+    }
+}
+
+#[tonic::async_trait]
+impl AuditorHeadService for LatestHead {
+    async fn get_auditor_head(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<SignedAuditorHead>, Status> {
+        self.get()
+            .map(Response::new)
+            .ok_or(Status::not_found("No head has been signed yet"))
+    }
+}
+
+/// Serve the pull API on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, latest: LatestHead) -> Result<(), anyhow::Error> {
+    tracing::info!("Serving signed heads on {addr}");
+    tonic::transport::Server::builder()
+        .add_service(AuditorHeadServiceServer::new(latest))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
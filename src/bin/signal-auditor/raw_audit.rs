@@ -0,0 +1,54 @@
+//! A client for the `Audit` RPC that leaves updates encoded.
+//!
+//! The generated client decodes each `AuditorUpdate`, silently dropping fields
+//! unknown to this version of the auditor. Decoding the response with each update
+//! as bytes instead lets the auditor detect such fields, and archive updates
+//! exactly as they were received. Repeated messages and repeated bytes share a
+//! wire encoding, so the same response bytes decode either way.
+
+use signal_auditor::proto::kt::AuditRequest;
+use tonic::client::Grpc;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::Channel;
+use tonic::{Request, Response, Status};
+
+const AUDIT_PATH: &str = "/kt.KeyTransparencyAuditorService/Audit";
+
+/// An `AuditResponse` with each update left encoded.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RawAuditResponse {
+    /// Encoded `AuditorUpdate` messages
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub updates: Vec<Vec<u8>>,
+    #[prost(bool, tag = "2")]
+    pub more: bool,
+}
+
+/// A client for the `Audit` RPC returning [`RawAuditResponse`]s.
+#[derive(Clone)]
+pub struct RawAuditClient {
+    inner: Grpc<Channel>,
+}
+
+impl RawAuditClient {
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            inner: Grpc::new(channel),
+        }
+    }
+
+    pub async fn audit(
+        &mut self,
+        request: Request<AuditRequest>,
+    ) -> Result<Response<RawAuditResponse>, Status> {
+        self.inner
+            .ready()
+            .await
+            .map_err(|e| Status::unknown(format!("Service was not ready: {e}")))?;
+        let codec = ProstCodec::<AuditRequest, RawAuditResponse>::default();
+        self.inner
+            .unary(request, PathAndQuery::from_static(AUDIT_PATH), codec)
+            .await
+    }
+}
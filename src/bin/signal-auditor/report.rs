@@ -0,0 +1,564 @@
+//! Error reporting.
+//!
+//! Every error raised with `report_error!` goes through a process-wide reporter
+//! before it reaches any sink. The reporter:
+//!
+//! - Suppresses repeats of a message within `dedup_window_seconds`. Messages
+//!   that differ only in words containing digits, such as positions, sizes and
+//!   hashes, are repeats. Once the window closes, the number of repeats that were
+//!   suppressed is reported with the last of them, so an error storm during an
+//!   outage produces one report per window instead of one per retry. Pending
+//!   counts are also reported before the process exits. Suppressed repeats are
+//!   still logged locally, marked with `repeat`, but reach no other sink.
+//! - Attaches the audit context at the time of the error: the log size and the
+//!   range of the last batch fetched.
+//! - Fans the report out to every configured sink. It is always logged, as a
+//!   Cloud Error Reporting event with the `stackdriver` feature. It is also
+//!   posted as JSON to `webhook_url`, and sent as an event to the Sentry
+//!   project in `sentry_dsn`, if those are set.
+//!
+//! Remote sinks are served by a background task from a bounded queue, so
+//! reporting never blocks the audit. Reports are dropped rather than queued
+//! once the queue is full, and failed deliveries are logged as warnings but
+//! not reported again. Only the message and audit context are sent to remote
+//! sinks; additional structured fields are only logged.
+
+use anyhow::{Context, anyhow};
+use reqwest::Url;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+use crate::build_info::BUILD_INFO;
+
+/// Reports waiting for remote delivery, beyond which reports are dropped.
+const QUEUE_SIZE: usize = 64;
+/// Timeout for a single delivery to a remote sink.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of distinct messages tracked before expired ones are pruned.
+const MAX_TRACKED: usize = 1024;
+/// Interval at which repeats suppressed in closed windows are reported.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+fn default_dedup_window() -> u64 {
+    300
+}
+
+/// Error reporting configuration.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ReportConfig {
+    /// Seconds during which repeats of an error are suppressed
+    #[serde(default = "default_dedup_window")]
+    pub dedup_window_seconds: u64,
+    /// URL that each reported error is posted to as JSON
+    pub webhook_url: Option<String>,
+    /// Sentry DSN of the project to send reported errors to
+    pub sentry_dsn: Option<String>,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        Self {
+            dedup_window_seconds: default_dedup_window(),
+            webhook_url: None,
+            sentry_dsn: None,
+        }
+    }
+}
+
+/// The audit context and suppression count attached to a report.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Report {
+    /// Size of the verified log
+    pub size: Option<u64>,
+    /// Start of the last batch fetched
+    pub batch_start: Option<u64>,
+    /// End (exclusive) of the last batch fetched
+    pub batch_end: Option<u64>,
+    /// Repeats of the message suppressed since it was last reported
+    pub suppressed: u64,
+}
+
+/// A report queued for remote sinks.
+#[derive(Debug, Serialize)]
+struct Delivery {
+    message: String,
+    #[serde(flatten)]
+    report: Report,
+    /// Seconds since the Unix epoch
+    timestamp: u64,
+    version: &'static str,
+    git_commit: &'static str,
+}
+
+struct Recent {
+    since: Instant,
+    suppressed: u64,
+    /// The last suppressed repeat
+    latest: Option<String>,
+}
+
+struct Reporter {
+    window: Duration,
+    /// Keyed by `dedup_key`
+    recent: HashMap<String, Recent>,
+    context: Report,
+    remote: Option<mpsc::Sender<Delivery>>,
+}
+
+impl Reporter {
+    fn admit(&mut self, message: &str, now: Instant) -> Option<Report> {
+        let window = self.window;
+        if self.recent.len() >= MAX_TRACKED {
+            // Suppressed repeats are kept until they are swept
+            self.recent.retain(|_, recent| {
+                recent.suppressed > 0 || now.duration_since(recent.since) < window
+            });
+        }
+        let opened = Recent {
+            since: now,
+            suppressed: 0,
+            latest: None,
+        };
+        let key = dedup_key(message);
+        let suppressed = match self.recent.get_mut(&key) {
+            Some(recent) if now.duration_since(recent.since) < window => {
+                recent.suppressed += 1;
+                recent.latest = Some(message.to_string());
+                return None;
+            }
+            Some(recent) => std::mem::replace(recent, opened).suppressed,
+            None => {
+                self.recent.insert(key, opened);
+                0
+            }
+        };
+        Some(Report {
+            suppressed,
+            ..self.context
+        })
+    }
+
+    /// Take the suppressed repeats of each message whose window has closed, or
+    /// of every message if `all`, returning the last repeat and its report.
+    fn sweep(&mut self, now: Instant, all: bool) -> Vec<(String, Report)> {
+        let window = self.window;
+        let mut reports = vec![];
+        self.recent.retain(|_, recent| {
+            if !all && now.duration_since(recent.since) < window {
+                return true;
+            }
+            if let Some(latest) = recent.latest.take() {
+                reports.push((
+                    latest,
+                    Report {
+                        suppressed: std::mem::take(&mut recent.suppressed),
+                        ..self.context
+                    },
+                ));
+            }
+            all
+        });
+        reports
+    }
+}
+
+/// The part of `message` that identifies repeats, with each word containing a
+/// digit replaced by `#`.
+fn dedup_key(message: &str) -> String {
+    let mut key = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_alphanumeric() {
+            rest.find(|c: char| !c.is_alphanumeric())
+                .unwrap_or(rest.len())
+        } else {
+            c.len_utf8()
+        };
+        let (word, tail) = rest.split_at(len);
+        if word.chars().any(|c| c.is_ascii_digit()) {
+            key.push('#');
+        } else {
+            key.push_str(word);
+        }
+        rest = tail;
+    }
+    key
+}
+
+static REPORTER: LazyLock<Mutex<Reporter>> = LazyLock::new(|| {
+    Mutex::new(Reporter {
+        window: Duration::from_secs(default_dedup_window()),
+        recent: HashMap::new(),
+        context: Report::default(),
+        remote: None,
+    })
+});
+
+/// Reports queued or being delivered to remote sinks.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+fn reporter() -> std::sync::MutexGuard<'static, Reporter> {
+    // Reporting must keep working after a panic, including from the panic hook
+    REPORTER.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Apply the configuration and start delivering reports to the remote sinks.
+///
+/// Errors reported before this are only logged, and are deduplicated with the
+/// default window. Remote sinks are reached with `client`. Must be called from
+/// within the runtime.
+pub fn install(config: &ReportConfig, client: reqwest::Client) -> Result<(), anyhow::Error> {
+    let mut sinks = vec![];
+    if let Some(url) = &config.webhook_url {
+        let url = Url::parse(url).context("Invalid error report webhook URL")?;
+        sinks.push(Sink::Webhook(url));
+    }
+    if let Some(dsn) = &config.sentry_dsn {
+        sinks.push(Sink::Sentry(SentryDsn::parse(dsn)?));
+    }
+
+    let mut reporter = reporter();
+    reporter.window = Duration::from_secs(config.dedup_window_seconds);
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            report_suppressed(false);
+        }
+    });
+    if !sinks.is_empty() {
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(deliver(client, receiver, sinks));
+        reporter.remote = Some(sender);
+    }
+    Ok(())
+}
+
+/// Record the size of the verified log.
+pub fn set_size(size: u64) {
+    reporter().context.size = Some(size);
+}
+
+/// Record the range of the batch being applied.
+pub fn set_batch(start: u64, end: u64) {
+    let mut reporter = reporter();
+    reporter.context.batch_start = Some(start);
+    reporter.context.batch_end = Some(end);
+}
+
+/// Decide whether `message` should be reported, returning the context to
+/// attach if so. Used by `report_error!`.
+pub fn admit(message: &str) -> Option<Report> {
+    reporter().admit(message, Instant::now())
+}
+
+/// Report the repeats suppressed in closed windows, or in every window if `all`.
+fn report_suppressed(all: bool) {
+    let reports = reporter().sweep(Instant::now(), all);
+    for (message, report) in reports {
+        #[cfg(feature = "stackdriver")]
+        tracing::error!(
+            "@type" = crate::GCP_ERROR_TYPE,
+            size = report.size,
+            batch_start = report.batch_start,
+            batch_end = report.batch_end,
+            suppressed = report.suppressed,
+            message = message.as_str(),
+        );
+        #[cfg(not(feature = "stackdriver"))]
+        tracing::error!(
+            size = report.size,
+            batch_start = report.batch_start,
+            batch_end = report.batch_end,
+            suppressed = report.suppressed,
+            message = message.as_str(),
+        );
+        dispatch(message, report);
+    }
+}
+
+/// Queue an admitted report for the remote sinks. Used by `report_error!`.
+pub fn dispatch(message: String, report: Report) {
+    let Some(remote) = reporter().remote.clone() else {
+        return;
+    };
+    let delivery = Delivery {
+        message,
+        report,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        version: BUILD_INFO.version,
+        git_commit: BUILD_INFO.git_commit,
+    };
+    PENDING.fetch_add(1, Ordering::SeqCst);
+    if remote.try_send(delivery).is_err() {
+        PENDING.fetch_sub(1, Ordering::SeqCst);
+        tracing::warn!(
+            type = "error-report",
+            state = "dropped",
+            "Error report queue is full"
+        );
+    }
+}
+
+/// Wait up to `timeout` for queued reports to be delivered, so that errors
+/// reported just before exiting reach the remote sinks.
+pub async fn flush(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while PENDING.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Report pending suppressed repeats, flush queued reports and exit the process
+/// with `code`.
+pub async fn exit(code: i32) -> ! {
+    report_suppressed(true);
+    flush(DELIVERY_TIMEOUT).await;
+    std::process::exit(code)
+}
+
+async fn deliver(
+    client: reqwest::Client,
+    mut receiver: mpsc::Receiver<Delivery>,
+    sinks: Vec<Sink>,
+) {
+    while let Some(delivery) = receiver.recv().await {
+        for sink in &sinks {
+            if let Err(e) = sink.send(&client, &delivery).await {
+                // Not reported, which could loop
+                tracing::warn!(
+                    type = "error-report",
+                    state = "failed",
+                    sink = sink.name(),
+                    reason = format!("{e:#}")
+                );
+            }
+        }
+        PENDING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A remote destination for reports.
+enum Sink {
+    /// A URL each report is posted to as JSON
+    Webhook(Url),
+    Sentry(SentryDsn),
+}
+
+impl Sink {
+    fn name(&self) -> &'static str {
+        match self {
+            Sink::Webhook(_) => "webhook",
+            Sink::Sentry(_) => "sentry",
+        }
+    }
+
+    async fn send(
+        &self,
+        client: &reqwest::Client,
+        delivery: &Delivery,
+    ) -> Result<(), anyhow::Error> {
+        let request = match self {
+            Sink::Webhook(url) => client.post(url.clone()).json(delivery),
+            Sink::Sentry(dsn) => client
+                .post(dsn.store_url.clone())
+                .header("X-Sentry-Auth", &dsn.auth)
+                .json(&dsn.event(delivery)),
+        };
+        request
+            .timeout(DELIVERY_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// A Sentry project, from a DSN of the form
+/// `https://<public key>@<host>[/<path>]/<project id>`.
+struct SentryDsn {
+    /// The project's event store endpoint
+    store_url: Url,
+    /// The `X-Sentry-Auth` header value
+    auth: String,
+}
+
+impl SentryDsn {
+    fn parse(dsn: &str) -> Result<Self, anyhow::Error> {
+        let url = Url::parse(dsn).context("Invalid Sentry DSN")?;
+        let key = url.username();
+        let (path, project) = url
+            .path()
+            .trim_end_matches('/')
+            .rsplit_once('/')
+            .filter(|(_, project)| !project.is_empty())
+            .ok_or(anyhow!("Sentry DSN has no project ID"))?;
+        if key.is_empty() {
+            return Err(anyhow!("Sentry DSN has no public key"));
+        }
+        let mut store_url = url.clone();
+        store_url
+            .set_username("")
+            .and_then(|()| store_url.set_password(None))
+            .map_err(|()| anyhow!("Invalid Sentry DSN"))?;
+        store_url.set_path(&format!("{path}/api/{project}/store/"));
+        Ok(Self {
+            store_url,
+            auth: format!(
+                "Sentry sentry_version=7, sentry_key={key}, sentry_client=signal-auditor/{}",
+                BUILD_INFO.version
+            ),
+        })
+    }
+
+    fn event(&self, delivery: &Delivery) -> serde_json::Value {
+        serde_json::json!({
+            "level": "error",
+            "platform": "other",
+            "logger": "signal-auditor",
+            "timestamp": delivery.timestamp,
+            "release": delivery.version,
+            "message": { "formatted": delivery.message },
+            "tags": { "git_commit": delivery.git_commit },
+            "extra": delivery.report,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reporter(window: u64) -> Reporter {
+        Reporter {
+            window: Duration::from_secs(window),
+            recent: HashMap::new(),
+            context: Report {
+                size: Some(10),
+                ..Report::default()
+            },
+            remote: None,
+        }
+    }
+
+    #[test]
+    fn test_dedup_key() {
+        assert_eq!(
+            dedup_key("Update at position 1234 failed: root 9f3a00 != ab12cd"),
+            "Update at position # failed: root # != #"
+        );
+        assert_eq!(dedup_key("Server unavailable"), "Server unavailable");
+        assert_eq!(dedup_key(""), "");
+    }
+
+    #[test]
+    fn test_admit() {
+        let mut reporter = reporter(60);
+        let start = Instant::now();
+        let report = reporter.admit("Fetch failed at 100", start).unwrap();
+        assert_eq!((report.size, report.suppressed), (Some(10), 0));
+
+        // Repeats within the window are suppressed, even at other positions
+        assert!(reporter.admit("Fetch failed at 100", start).is_none());
+        assert!(
+            reporter
+                .admit("Fetch failed at 200", start + Duration::from_secs(59))
+                .is_none()
+        );
+        // Other messages are not
+        assert!(reporter.admit("Server unavailable", start).is_some());
+
+        // The first report after the window closes carries the suppressed count
+        let report = reporter
+            .admit("Fetch failed at 300", start + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(report.suppressed, 2);
+        assert!(
+            reporter
+                .admit("Fetch failed at 300", start + Duration::from_secs(61))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_admit_without_window() {
+        let mut reporter = reporter(0);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(reporter.admit("Fetch failed", now).unwrap().suppressed, 0);
+        }
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut reporter = reporter(60);
+        let start = Instant::now();
+        reporter.admit("Fetch failed at 100", start).unwrap();
+        reporter.admit("Fetch failed at 200", start);
+        reporter.admit("Fetch failed at 300", start);
+        reporter.admit("Server unavailable", start).unwrap();
+
+        // Nothing is reported while the window is open
+        assert!(
+            reporter
+                .sweep(start + Duration::from_secs(59), false)
+                .is_empty()
+        );
+
+        // Once it closes, the last repeat is reported with the count
+        let reports = reporter.sweep(start + Duration::from_secs(60), false);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].0, "Fetch failed at 300");
+        assert_eq!(reports[0].1.suppressed, 2);
+        assert!(reporter.recent.is_empty());
+
+        // The count is not reported again
+        let report = reporter
+            .admit("Fetch failed at 400", start + Duration::from_secs(61))
+            .unwrap();
+        assert_eq!(report.suppressed, 0);
+    }
+
+    #[test]
+    fn test_sweep_all() {
+        let mut reporter = reporter(60);
+        let start = Instant::now();
+        reporter.admit("Fetch failed at 100", start).unwrap();
+        reporter.admit("Fetch failed at 200", start);
+
+        // Open windows are reported when exiting, and stay open
+        let reports = reporter.sweep(start, true);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].1.suppressed, 1);
+        assert!(reporter.sweep(start, true).is_empty());
+        assert!(reporter.admit("Fetch failed at 300", start).is_none());
+    }
+
+    #[test]
+    fn test_sentry_dsn() {
+        let dsn = SentryDsn::parse("https://abc123@o1.ingest.sentry.io/42").unwrap();
+        assert_eq!(
+            dsn.store_url.as_str(),
+            "https://o1.ingest.sentry.io/api/42/store/"
+        );
+        assert!(dsn.auth.contains("sentry_key=abc123"));
+
+        let dsn = SentryDsn::parse("https://abc123@sentry.example.com/prefix/7/").unwrap();
+        assert_eq!(
+            dsn.store_url.as_str(),
+            "https://sentry.example.com/prefix/api/7/store/"
+        );
+
+        assert!(SentryDsn::parse("not a url").is_err());
+        assert!(SentryDsn::parse("https://sentry.example.com/42").is_err());
+        assert!(SentryDsn::parse("https://abc123@sentry.example.com/").is_err());
+    }
+}
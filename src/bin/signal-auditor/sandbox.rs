@@ -0,0 +1,320 @@
+//! Process sandboxing for the `sandbox` feature.
+//!
+//! The auditor parses untrusted protobuf and TLS input from the network, so
+//! the `run` command confines itself to limit the damage an exploited parser
+//! could do. The policy has two parts:
+//!
+//! - A Landlock ruleset limiting filesystem access to the configured state
+//!   directories (read-write) and key, certificate and resolver files (read-only).
+//!   Landlock only applies to the calling thread and threads it creates later,
+//!   so it is applied before the tokio runtime is built.
+//! - A seccomp allowlist of the syscalls the auditor makes once running: file and
+//!   socket I/O, memory management, threads and their synchronization. Any other
+//!   syscall, such as `execve` or `ptrace`, fails with `EPERM`, as do `ioctl`
+//!   requests other than the few the auditor makes. The filter is synchronized
+//!   to every thread, so it is applied once startup is complete.
+//!
+//! State paths must be absolute when the sandbox is enabled, so that a relative
+//! path cannot grant write access to the whole working directory.
+//!
+//! On kernels without Landlock support the auditor refuses to start, unless
+//! `sandbox_best_effort` is set, in which case a warning is logged and only the
+//! seccomp filter applies.
+
+use anyhow::Context;
+use landlock::{
+    ABI, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus,
+    path_beneath_rules,
+};
+use seccompiler::{
+    BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+    SeccompRule,
+};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::client::ClientConfig;
+
+/// The newest Landlock ABI the ruleset is written for.
+const LANDLOCK_ABI: ABI = ABI::V3;
+
+/// System paths read by the resolver and the runtime.
+const SYSTEM_READ_PATHS: &[&str] = &["/etc", "/proc/self", "/sys/fs/cgroup"];
+
+/// Syscalls the auditor makes after startup, on every architecture.
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    // File I/O
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_pread64,
+    libc::SYS_pwrite64,
+    libc::SYS_preadv,
+    libc::SYS_pwritev,
+    libc::SYS_openat,
+    libc::SYS_close,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_lseek,
+    libc::SYS_fcntl,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_ftruncate,
+    libc::SYS_fallocate,
+    libc::SYS_flock,
+    libc::SYS_fchown,
+    libc::SYS_fchmod,
+    libc::SYS_renameat,
+    libc::SYS_renameat2,
+    libc::SYS_unlinkat,
+    libc::SYS_mkdirat,
+    libc::SYS_getdents64,
+    libc::SYS_readlinkat,
+    libc::SYS_faccessat,
+    libc::SYS_faccessat2,
+    libc::SYS_getcwd,
+    libc::SYS_dup,
+    libc::SYS_dup3,
+    libc::SYS_pipe2,
+    // Networking
+    libc::SYS_socket,
+    libc::SYS_socketpair,
+    libc::SYS_connect,
+    libc::SYS_bind,
+    libc::SYS_listen,
+    libc::SYS_accept4,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_setsockopt,
+    libc::SYS_getsockopt,
+    libc::SYS_sendto,
+    libc::SYS_recvfrom,
+    libc::SYS_sendmsg,
+    libc::SYS_recvmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_recvmmsg,
+    libc::SYS_shutdown,
+    // Event loop
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_epoll_pwait2,
+    libc::SYS_eventfd2,
+    libc::SYS_ppoll,
+    libc::SYS_pselect6,
+    // Memory
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    // Threads and synchronization
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_futex,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_prctl,
+    libc::SYS_gettid,
+    libc::SYS_getpid,
+    libc::SYS_tgkill,
+    libc::SYS_prlimit64,
+    // Signals
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_restart_syscall,
+    libc::SYS_sigaltstack,
+    // Time, randomness and identity
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    libc::SYS_getrandom,
+    libc::SYS_uname,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+];
+
+/// The `ioctl` requests the auditor makes: setting non-blocking mode and
+/// close-on-exec, and the terminal check of the log formatter. Other requests,
+/// such as `TIOCSTI`, are denied.
+const ALLOWED_IOCTLS: &[u64] = &[
+    libc::FIONBIO as u64,
+    libc::FIOCLEX as u64,
+    libc::TCGETS as u64,
+];
+
+/// Legacy syscalls that libc still uses on x86-64.
+#[cfg(target_arch = "x86_64")]
+const ALLOWED_LEGACY_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_open,
+    libc::SYS_stat,
+    libc::SYS_lstat,
+    libc::SYS_access,
+    libc::SYS_readlink,
+    libc::SYS_rename,
+    libc::SYS_unlink,
+    libc::SYS_mkdir,
+    libc::SYS_pipe,
+    libc::SYS_dup2,
+    libc::SYS_poll,
+    libc::SYS_select,
+    libc::SYS_epoll_wait,
+    libc::SYS_getrlimit,
+    libc::SYS_arch_prctl,
+];
+#[cfg(not(target_arch = "x86_64"))]
+const ALLOWED_LEGACY_SYSCALLS: &[libc::c_long] = &[];
+
+/// The directory containing the state file at `path`, which the auditor may
+/// create files in, created if necessary and with symlinks resolved.
+///
+/// Relative paths are rejected, since the working directory is often `/` in a
+/// container, and granting it would defeat the ruleset.
+fn state_dir(name: &str, path: &Path) -> Result<PathBuf, anyhow::Error> {
+    if path.is_relative() {
+        return Err(anyhow::anyhow!(
+            "`{name}` must be an absolute path when the sandbox is enabled, got {}",
+            path.display()
+        ));
+    }
+    let parent = path.parent().unwrap_or(path);
+    // Rules can only be added for paths that exist
+    std::fs::create_dir_all(parent).context(format!(
+        "Failed to create state directory {}",
+        parent.display()
+    ))?;
+    let dir = parent.canonicalize()?;
+    if dir.parent().is_none() {
+        return Err(anyhow::anyhow!(
+            "`{name}` must not be directly under the filesystem root when the sandbox is enabled"
+        ));
+    }
+    Ok(dir)
+}
+
+/// Restrict filesystem access to the paths named in the configuration.
+///
+/// Must be called before the runtime is built, so that its threads inherit the ruleset.
+pub fn restrict_paths(config: &ClientConfig) -> Result<(), anyhow::Error> {
+    let mut write_paths = [
+        ("storage_path", &config.storage_path),
+        ("archive_path", &config.archive_path),
+        ("prefix_roots_path", &config.prefix_roots_path),
+        ("history_db_path", &config.history_db_path),
+        ("signing_journal_path", &config.signing_journal_path),
+        ("halt_path", &config.halt_path),
+    ]
+    .into_iter()
+    .filter_map(|(name, path)| Some(state_dir(name, path.as_ref()?)))
+    .collect::<Result<Vec<_>, _>>()?;
+    if let Some(dir) = &config.snapshot_dir {
+        // Snapshots are written inside the directory, rather than next to it
+        write_paths.push(state_dir("snapshot_dir", &dir.join("snapshot"))?);
+    }
+
+    let mut read_paths: Vec<PathBuf> = vec![
+        config.client_cert_path.clone(),
+        config.client_key_path.clone(),
+        config.signal_public_key.clone(),
+        config.vrf_public_key.clone(),
+    ];
+    read_paths.extend(config.ca_cert_path.clone());
+    read_paths.extend(config.operator_public_key.clone());
+    read_paths.extend(config.storage_mac_key_path.clone());
+    #[cfg(not(feature = "gcloud-kms"))]
+    read_paths.push(config.auditor_signing_key.clone());
+    read_paths.extend(std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS").map(PathBuf::from));
+    read_paths.extend(SYSTEM_READ_PATHS.iter().map(PathBuf::from));
+
+    // Paths that cannot be opened are skipped by `path_beneath_rules`
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(LANDLOCK_ABI))?
+        .create()?
+        .add_rules(path_beneath_rules(
+            &read_paths,
+            AccessFs::from_read(LANDLOCK_ABI),
+        ))?
+        .add_rules(path_beneath_rules(
+            &write_paths,
+            AccessFs::from_all(LANDLOCK_ABI),
+        ))?
+        .restrict_self()
+        .context("Failed to apply Landlock ruleset")?;
+
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => {
+            tracing::info!(type = "sandbox", landlock = "enforced", "Filesystem access restricted");
+        }
+        RulesetStatus::PartiallyEnforced => {
+            tracing::warn!(
+                type = "sandbox",
+                landlock = "partial",
+                "Filesystem access partially restricted, the kernel supports an older Landlock ABI"
+            );
+        }
+        RulesetStatus::NotEnforced if config.sandbox_best_effort => {
+            tracing::warn!(
+                type = "sandbox",
+                landlock = "unsupported",
+                "Filesystem access is not restricted, the kernel does not support Landlock"
+            );
+        }
+        RulesetStatus::NotEnforced => {
+            return Err(anyhow::anyhow!(
+                "The kernel does not support Landlock, so filesystem access cannot be restricted; set `sandbox_best_effort` to run without it"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Allow only the syscalls the auditor needs on every thread of the process.
+///
+/// Other syscalls fail with `EPERM` rather than killing the process, so an
+/// unexpected denial surfaces as an ordinary error.
+pub fn restrict_syscalls() -> Result<(), anyhow::Error> {
+    let mut rules = ALLOWED_SYSCALLS
+        .iter()
+        .chain(ALLOWED_LEGACY_SYSCALLS)
+        .map(|&syscall| (syscall, vec![]))
+        .collect::<BTreeMap<_, _>>();
+    // `ioctl` is only allowed for the requests in `ALLOWED_IOCTLS`
+    let ioctls = ALLOWED_IOCTLS
+        .iter()
+        .map(|&request| {
+            SeccompRule::new(vec![SeccompCondition::new(
+                1,
+                SeccompCmpArgLen::Dword,
+                SeccompCmpOp::Eq,
+                request,
+            )?])
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    rules.insert(libc::SYS_ioctl, ioctls);
+    let allowed_syscalls = rules.len();
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Errno(libc::EPERM as u32),
+        SeccompAction::Allow,
+        std::env::consts::ARCH.try_into()?,
+    )?;
+    let program: BpfProgram = filter.try_into()?;
+    seccompiler::apply_filter_all_threads(&program).context("Failed to apply seccomp filter")?;
+    tracing::info!(
+        type = "sandbox",
+        seccomp = "enforced",
+        allowed_syscalls = allowed_syscalls,
+        "Syscalls restricted"
+    );
+    Ok(())
+}
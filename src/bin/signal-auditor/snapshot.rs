@@ -0,0 +1,95 @@
+//! Labeled snapshots of the log head, taken on a schedule.
+//!
+//! The head object is replaced on every commit, so it only holds the latest state.
+//! Snapshots copy the committed head, including the log frontier, to a separate
+//! location (ideally retention locked) once per configured period, giving coarse
+//! restore points that do not depend on the head object. Each snapshot is named
+//! by its label and the start of its period, and existing snapshots are never
+//! replaced, so restarting within a period does not take a second snapshot.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use signal_auditor::transparency::TransparencyLog;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::storage::Storage;
+
+/// A labeled snapshot taken once per period.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct SnapshotSchedule {
+    /// Label the snapshots are stored under, e.g. "monthly"
+    pub label: String,
+    /// Length of the period in seconds, counted from the Unix epoch
+    pub interval_seconds: u64,
+}
+
+impl SnapshotSchedule {
+    /// The name of the snapshot for the period containing `now`, in seconds
+    /// since the Unix epoch.
+    fn name(&self, now: u64) -> String {
+        let period_start = now - now % self.interval_seconds;
+        format!("{}/{period_start}", self.label)
+    }
+}
+
+/// The configured snapshot schedules, and the snapshots taken by this process.
+pub struct Snapshots {
+    schedules: Vec<SnapshotSchedule>,
+    /// The name of the last snapshot stored for each schedule
+    taken: Vec<Option<String>>,
+}
+
+impl Snapshots {
+    pub fn new(schedules: Vec<SnapshotSchedule>) -> Result<Self, anyhow::Error> {
+        for schedule in &schedules {
+            if schedule.label.is_empty() || schedule.label.contains(['/', '\\', '.']) {
+                return Err(anyhow::anyhow!(
+                    "Invalid snapshot label {:?}, labels must be non-empty and must not contain '/', '\\' or '.'",
+                    schedule.label
+                ));
+            }
+            if schedule.interval_seconds == 0 {
+                return Err(anyhow::anyhow!(
+                    "Snapshot interval for {:?} must be positive",
+                    schedule.label
+                ));
+            }
+        }
+        let taken = vec![None; schedules.len()];
+        Ok(Self { schedules, taken })
+    }
+
+    /// Store a snapshot of `head` for every schedule whose current period has
+    /// none yet.
+    ///
+    /// `head` must already be committed. Failures are reported and retried after
+    /// the next commit, rather than interrupting the audit.
+    pub async fn take_due(&mut self, storage: &mut impl Storage, head: &TransparencyLog) {
+        let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) else {
+            return;
+        };
+        for (schedule, taken) in self.schedules.iter().zip(&mut self.taken) {
+            let name = schedule.name(now.as_secs());
+            if taken.as_ref() == Some(&name) {
+                continue;
+            }
+            match storage.store_snapshot(&name, head).await {
+                Ok(Some(location)) => {
+                    tracing::info!(
+                        type = "snapshot",
+                        label = schedule.label,
+                        size = head.size(),
+                        location = location,
+                    );
+                }
+                // Taken before a restart within the same period
+                Ok(None) => {}
+                Err(e) => {
+                    report_error!(format!("Failed to store snapshot {name}: {e:#}"));
+                    continue;
+                }
+            }
+            *taken = Some(name);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Support for SOPS-encrypted configuration files.
+//!
+//! A configuration file with a top-level `sops` key is decrypted with the
+//! `sops` binary before it is parsed, so that configuration can be committed
+//! without plaintext values. The binary is given by absolute path with
+//! `--sops-path` rather than looked up on the `PATH`, since it sees the
+//! decrypted configuration. Key material is resolved by `sops` itself: age
+//! identities from `SOPS_AGE_KEY` or `SOPS_AGE_KEY_FILE`, and cloud KMS keys
+//! from the ambient credentials.
+
+use anyhow::{Context, anyhow};
+use std::path::Path;
+use std::process::Command;
+
+/// Whether the YAML or JSON document `contents` is SOPS-encrypted.
+pub fn is_encrypted(contents: &str) -> bool {
+    serde_yaml::from_str::<serde_yaml::Value>(contents)
+        .ok()
+        .and_then(|value| value.get("sops").cloned())
+        .is_some()
+}
+
+/// Decrypt the SOPS-encrypted file at `path`, in `format` ("yaml" or "json"),
+/// with the `sops` binary at `sops`.
+pub fn decrypt(sops: Option<&Path>, path: &Path, format: &str) -> Result<String, anyhow::Error> {
+    let sops = sops.ok_or(anyhow!(
+        "{} is SOPS-encrypted; pass --sops-path with the absolute path of the sops binary",
+        path.display()
+    ))?;
+    if !sops.is_absolute() {
+        return Err(anyhow!("sops path {} is not absolute", sops.display()));
+    }
+    let output = Command::new(sops)
+        .args(["--decrypt", "--input-type", format, "--output-type", format])
+        .arg(path)
+        .output()
+        .context(format!("Failed to run {}", sops.display()))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "sops failed to decrypt {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout).context("Decrypted configuration is not UTF-8")
+}
@@ -0,0 +1,50 @@
+//! Signed statements published by the auditor alongside its log heads.
+//!
+//! A statement is a JSON document signed by the auditor key. The signature
+//! covers [`STATEMENT_CONTEXT`] followed by the exact bytes of the `statement`
+//! field, which keeps statement signatures distinct from tree head signatures.
+//!
+//! [`STATEMENT_CONTEXT`]: signal_auditor::auditor::STATEMENT_CONTEXT
+
+use anyhow::Context;
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use signal_auditor::auditor::{Auditor, STATEMENT_CONTEXT};
+
+/// A statement together with the auditor's signature over it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedStatement {
+    /// The JSON-encoded statement
+    pub statement: String,
+    /// Hex-encoded Ed25519 signature by the auditor key
+    pub signature: String,
+}
+
+impl SignedStatement {
+    /// Verify the signature on the statement with `key`.
+    ///
+    /// Verification is strict, so signatures that are only valid under a
+    /// small-order key or with a non-canonical encoding are rejected.
+    pub fn verify(&self, key: &VerifyingKey) -> Result<(), anyhow::Error> {
+        let signature = Signature::from_slice(
+            &hex::decode(&self.signature).context("Signature is not valid hex")?,
+        )?;
+        let mut msg = STATEMENT_CONTEXT.to_vec();
+        msg.extend_from_slice(self.statement.as_bytes());
+        key.verify_strict(&msg, &signature)
+            .context("Statement signature is invalid")
+    }
+}
+
+/// Serialize `statement` as JSON and sign it with the auditor key.
+pub async fn sign_statement(
+    auditor: &Auditor,
+    statement: &impl Serialize,
+) -> Result<SignedStatement, anyhow::Error> {
+    let statement = serde_json::to_string(statement)?;
+    let signature = auditor.sign_statement(statement.as_bytes()).await?;
+    Ok(SignedStatement {
+        statement,
+        signature: hex::encode(signature),
+    })
+}
@@ -0,0 +1,394 @@
+//! A minimal embedded HTTP server for health checks and a read-only status page.
+//!
+//! The server only understands `GET` requests and closes the connection after each
+//! response. Clients get a few seconds to send the request line and read the
+//! response. It is intended for load balancer probes and for operators to glance at
+//! an instance, not for general use, so errors are shown only as a short summary
+//! without their causes.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::build_info::BUILD_INFO;
+
+/// Number of recent errors retained for display.
+const MAX_RECENT_ERRORS: usize = 10;
+/// Characters of an error summary displayed.
+const MAX_ERROR_SUMMARY: usize = 200;
+/// Time allowed for a client to send its request line and read the response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest request line accepted.
+const MAX_REQUEST_LINE: usize = 1024;
+
+#[derive(Default)]
+struct State {
+    size: u64,
+    lag: Option<u64>,
+    last_signed_root: Option<[u8; 32]>,
+    last_signed_size: u64,
+    last_signed_at: Option<u64>,
+    /// Coverage latency of the last signed head, and the maximum since startup
+    coverage_latency: Option<(Duration, Duration)>,
+    /// The result and time of the last storage custody check
+    custody: Option<(bool, u64)>,
+    /// Number of updates with fields unknown to this auditor
+    unknown_field_updates: u64,
+    recent_errors: VecDeque<(u64, String)>,
+}
+
+/// A shared handle to the auditor status.
+#[derive(Clone)]
+pub struct StatusHandle {
+    started: Instant,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for StatusHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatusHandle {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            state: Arc::default(),
+        }
+    }
+
+    /// Record the current size of the audited log.
+    pub fn set_size(&self, size: u64) {
+        self.state.lock().unwrap().size = size;
+    }
+
+    /// Record a successfully submitted head, and the lag behind the server at that time.
+    pub fn record_signed(&self, root: [u8; 32], size: u64, lag: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.size = size;
+        state.lag = Some(lag);
+        state.last_signed_root = Some(root);
+        state.last_signed_size = size;
+        state.last_signed_at = Some(unix_seconds());
+    }
+
+    /// Record the coverage latency of a newly signed head.
+    pub fn record_coverage_latency(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let max = state
+            .coverage_latency
+            .map_or(latency, |(_, max)| max.max(latency));
+        state.coverage_latency = Some((latency, max));
+    }
+
+    /// Record the result of a storage custody check.
+    pub fn record_custody(&self, ok: bool) {
+        self.state.lock().unwrap().custody = Some((ok, unix_seconds()));
+    }
+
+    /// Count an update with fields unknown to this auditor.
+    pub fn record_unknown_fields(&self) {
+        self.state.lock().unwrap().unknown_field_updates += 1;
+    }
+
+    /// The log size and the last signed size and root, if the state is not locked.
+    ///
+    /// This does not block, so it is safe to call from a panic hook.
+    pub fn try_progress(&self) -> Option<(u64, u64, Option<[u8; 32]>)> {
+        let state = self.state.try_lock().ok()?;
+        Some((state.size, state.last_signed_size, state.last_signed_root))
+    }
+
+    /// Record a summary of an error for display on the status page.
+    pub fn record_error(&self, error: &anyhow::Error) {
+        let mut state = self.state.lock().unwrap();
+        if state.recent_errors.len() == MAX_RECENT_ERRORS {
+            state.recent_errors.pop_front();
+        }
+        state
+            .recent_errors
+            .push_back((unix_seconds(), summary(error)));
+    }
+
+    /// Render the status page.
+    fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut html = String::from(
+            "<!DOCTYPE html><html><head><title>Signal Auditor</title></head><body>\
+             <h1>Signal Auditor</h1><table>",
+        );
+        let mut row = |key: &str, value: String| {
+            let _ = write!(html, "<tr><th>{key}</th><td>{}</td></tr>", escape(&value));
+        };
+        row(
+            "Build",
+            format!(
+                "{} ({}, built {}, features: {})",
+                BUILD_INFO.version,
+                BUILD_INFO.git_commit,
+                BUILD_INFO.build_timestamp,
+                BUILD_INFO.features
+            ),
+        );
+        row("Uptime", format!("{}s", self.started.elapsed().as_secs()));
+        row("Log size", state.size.to_string());
+        row(
+            "Lag",
+            state.lag.map_or("unknown".to_string(), |l| l.to_string()),
+        );
+        row(
+            "Last signed root",
+            state
+                .last_signed_root
+                .map_or("none".to_string(), hex::encode),
+        );
+        row("Last signed size", state.last_signed_size.to_string());
+        row(
+            "Last signed at",
+            state
+                .last_signed_at
+                .map_or("never".to_string(), |t| t.to_string()),
+        );
+        row(
+            "Coverage latency",
+            state
+                .coverage_latency
+                .map_or("unknown".to_string(), |(last, max)| {
+                    format!("{}s (max {}s)", last.as_secs(), max.as_secs())
+                }),
+        );
+        row(
+            "Storage custody",
+            state.custody.map_or("unchecked".to_string(), |(ok, at)| {
+                format!("{} at {at}", if ok { "ok" } else { "FAILED" })
+            }),
+        );
+        row(
+            "Updates with unknown fields",
+            state.unknown_field_updates.to_string(),
+        );
+        html.push_str("</table><h2>Recent errors</h2><ul>");
+        for (at, message) in state.recent_errors.iter().rev() {
+            let _ = write!(html, "<li>{at}: <pre>{}</pre></li>", escape(message));
+        }
+        html.push_str("</ul></body></html>");
+        html
+    }
+}
+
+/// Serve health checks on `/healthz` and the status page on `/` until the process exits.
+pub async fn serve(addr: SocketAddr, status: StatusHandle) -> Result<(), anyhow::Error> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("Serving status page on {addr}");
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let status = status.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, &status, REQUEST_TIMEOUT).await {
+                tracing::debug!("Status request failed: {e:?}");
+            }
+        });
+    }
+}
+
+/// Answer a single request, giving up after `timeout`.
+async fn serve_connection(
+    stream: TcpStream,
+    status: &StatusHandle,
+    timeout: Duration,
+) -> Result<(), anyhow::Error> {
+    tokio::time::timeout(timeout, handle(stream, status))
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out after {timeout:?}"))?
+}
+
+async fn handle(mut stream: TcpStream, status: &StatusHandle) -> Result<(), anyhow::Error> {
+    let line = read_request_line(&mut stream).await?;
+    let path = line
+        .strip_prefix("GET ")
+        .and_then(|rest| rest.split_whitespace().next());
+
+    let (code, content_type, body) = match path {
+        Some("/healthz") => ("200 OK", "text/plain", "ok".to_string()),
+        Some("/") => ("200 OK", "text/html; charset=utf-8", status.render()),
+        Some(_) => ("404 Not Found", "text/plain", "not found".to_string()),
+        None => ("405 Method Not Allowed", "text/plain", String::new()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {code}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Read the request line, which may arrive over several reads.
+async fn read_request_line(stream: &mut TcpStream) -> Result<String, anyhow::Error> {
+    let mut line = Vec::new();
+    let mut buf = [0u8; 256];
+    while !line.contains(&b'\n') {
+        if line.len() > MAX_REQUEST_LINE {
+            return Err(anyhow::anyhow!("Request line too long"));
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        line.extend_from_slice(&buf[..n]);
+    }
+    let line = String::from_utf8_lossy(&line);
+    Ok(line.lines().next().unwrap_or_default().to_string())
+}
+
+/// The outermost message of `error`, without its causes, truncated.
+fn summary(error: &anyhow::Error) -> String {
+    let message = error.to_string();
+    let message = message.lines().next().unwrap_or_default();
+    match message.char_indices().nth(MAX_ERROR_SUMMARY) {
+        Some((end, _)) => format!("{}...", &message[..end]),
+        None => message.to_string(),
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serve one connection on a local port, returning its address.
+    async fn serve_once(
+        status: StatusHandle,
+        timeout: Duration,
+    ) -> (
+        SocketAddr,
+        tokio::task::JoinHandle<Result<(), anyhow::Error>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            serve_connection(stream, &status, timeout).await
+        });
+        (addr, task)
+    }
+
+    /// Send `request` in the given pieces and return the response.
+    async fn request(status: StatusHandle, pieces: &[&str]) -> String {
+        let (addr, task) = serve_once(status, REQUEST_TIMEOUT).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        for piece in pieces {
+            stream.write_all(piece.as_bytes()).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        task.await.unwrap().unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_healthz() {
+        let response = request(StatusHandle::new(), &["GET /healthz HTTP/1.1\r\n\r\n"]).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nok"));
+    }
+
+    #[tokio::test]
+    async fn test_split_request_line() {
+        let response = request(StatusHandle::new(), &["GET /hea", "lthz HTTP/1.1\r\n\r\n"]).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_other_requests() {
+        let response = request(StatusHandle::new(), &["GET /missing HTTP/1.1\r\n\r\n"]).await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = request(StatusHandle::new(), &["POST / HTTP/1.1\r\n\r\n"]).await;
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_idle_client_times_out() {
+        let (addr, task) = serve_once(StatusHandle::new(), Duration::from_millis(100)).await;
+        let _stream = TcpStream::connect(addr).await.unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap();
+        assert!(result.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_overlong_request_line() {
+        let (addr, task) = serve_once(StatusHandle::new(), REQUEST_TIMEOUT).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let line = format!("GET /{}", "a".repeat(4 * MAX_REQUEST_LINE));
+        // The server may close the connection before everything is written
+        let _ = stream.write_all(line.as_bytes()).await;
+        assert!(task.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_status_page_shows_error_summary() {
+        let status = StatusHandle::new();
+        status.set_size(42);
+        let error = anyhow::anyhow!("token=secret").context("Fetch <failed>");
+        status.record_error(&error);
+        let response = request(status, &["GET / HTTP/1.1\r\n\r\n"]).await;
+        assert!(response.contains("<tr><th>Log size</th><td>42</td></tr>"));
+        assert!(response.contains("Fetch &lt;failed&gt;"));
+        assert!(!response.contains("secret"));
+    }
+
+    #[test]
+    fn test_summary() {
+        let error = anyhow::anyhow!("cause").context("first line\nsecond line");
+        assert_eq!(summary(&error), "first line");
+
+        let long = anyhow::anyhow!("é".repeat(MAX_ERROR_SUMMARY + 1));
+        let summary = summary(&long);
+        assert_eq!(summary.chars().count(), MAX_ERROR_SUMMARY + 3);
+        assert!(summary.ends_with("..."));
+    }
+
+    #[test]
+    fn test_recent_errors_are_bounded() {
+        let status = StatusHandle::new();
+        for i in 0..MAX_RECENT_ERRORS + 5 {
+            status.record_error(&anyhow::anyhow!("error {i}"));
+        }
+        let state = status.state.lock().unwrap();
+        assert_eq!(state.recent_errors.len(), MAX_RECENT_ERRORS);
+        assert_eq!(
+            state.recent_errors.back().unwrap().1,
+            format!("error {}", MAX_RECENT_ERRORS + 4)
+        );
+    }
+}
@@ -1,27 +1,168 @@
 //! A trait for storage backends.
 //!
-//! Currently we do not actually use generic storage impls
-//! but instead use feature flags to select a single storage backend
+//! The backend is selected at runtime by `storage.kind`, so a single build can
+//! serve every deployment target. Backends with heavy dependencies are still
+//! only compiled in with their feature.
 //!
-//! TODO - sign stored data to ensure integrity
+//! With `storage_mac_key_path` set, stored heads and snapshots end with an
+//! HMAC-SHA256 tag, and heads without a valid tag are rejected when read.
 
-use crate::client::ClientConfig;
-use serde::{Deserialize, Serialize};
+use crate::client::{ClientConfig, StorageKind};
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
 use signal_auditor::transparency::TransparencyLog;
 
-const VERSION: u8 = 1;
+/// Heads authenticated with a [`HeadMac`] are a [`VERSION`] head with this
+/// version byte, followed by the tag over everything before it.
+const MAC_VERSION: u8 = 4;
+/// Stored heads are the version byte, the big-endian `u64` length of the canonical
+/// log encoding, the encoding itself, and the JSON-encoded update statistics.
+const VERSION: u8 = 3;
+/// The version byte followed by the canonical log encoding, without statistics.
+const CANONICAL_VERSION: u8 = 2;
+/// Heads wrapped in a CBOR [`StoredHead`], still accepted for migration.
+const LEGACY_VERSION: u8 = 1;
+
+pub mod filestore;
+use filestore::FileBackend;
 
 #[cfg(feature = "storage-gcp")]
 mod gcp;
 #[cfg(feature = "storage-gcp")]
-pub use gcp::GcpBackend as Backend;
+use gcp::GcpBackend;
+
+/// The storage backend selected by the configuration.
+pub enum Backend {
+    File(FileBackend),
+    #[cfg(feature = "storage-gcp")]
+    Gcs(GcpBackend),
+}
+
+/// Forward a call to whichever backend is selected.
+macro_rules! dispatch {
+    ($self:ident, $backend:ident => $call:expr) => {
+        match $self {
+            Backend::File($backend) => $call,
+            #[cfg(feature = "storage-gcp")]
+            Backend::Gcs($backend) => $call,
+        }
+    };
+}
+
+impl Storage for Backend {
+    async fn init_from_config(config: &ClientConfig) -> Result<Self, anyhow::Error> {
+        match config.storage_kind()? {
+            StorageKind::File => Ok(Backend::File(FileBackend::init_from_config(config).await?)),
+            #[cfg(feature = "storage-gcp")]
+            StorageKind::Gcs => Ok(Backend::Gcs(GcpBackend::init_from_config(config).await?)),
+            #[cfg(not(feature = "storage-gcp"))]
+            StorageKind::Gcs => Err(anyhow::anyhow!(
+                "Storage kind `gcs` is not available in this build, enable the `storage-gcp` feature"
+            )),
+        }
+    }
+
+    fn location(&self) -> String {
+        dispatch!(self, backend => backend.location())
+    }
+
+    async fn commit_head(&mut self, head: &TransparencyLog) -> Result<(), anyhow::Error> {
+        dispatch!(self, backend => backend.commit_head(head).await)
+    }
+
+    async fn get_head(&mut self) -> Result<Option<TransparencyLog>, anyhow::Error> {
+        dispatch!(self, backend => backend.get_head().await)
+    }
+
+    async fn quarantine_head(&mut self) -> Result<String, anyhow::Error> {
+        dispatch!(self, backend => backend.quarantine_head().await)
+    }
+
+    async fn publish_artifact(
+        &mut self,
+        name: &str,
+        data: Vec<u8>,
+    ) -> Result<String, anyhow::Error> {
+        dispatch!(self, backend => backend.publish_artifact(name, data).await)
+    }
+
+    async fn store_snapshot(
+        &mut self,
+        name: &str,
+        head: &TransparencyLog,
+    ) -> Result<Option<String>, anyhow::Error> {
+        dispatch!(self, backend => backend.store_snapshot(name, head).await)
+    }
+
+    async fn latest_snapshot(
+        &mut self,
+        max_size: u64,
+    ) -> Result<Option<(String, TransparencyLog)>, anyhow::Error> {
+        dispatch!(self, backend => backend.latest_snapshot(max_size).await)
+    }
+}
+
+impl Backend {
+    /// The kind of the selected backend
+    pub fn kind(&self) -> StorageKind {
+        match self {
+            Backend::File(_) => StorageKind::File,
+            #[cfg(feature = "storage-gcp")]
+            Backend::Gcs(_) => StorageKind::Gcs,
+        }
+    }
+}
+
+/// The key that stored heads are authenticated with.
+#[derive(Clone)]
+pub struct HeadMac(Hmac<Sha256>);
 
-#[cfg(not(feature = "storage-gcp"))]
-mod filestore;
-#[cfg(not(feature = "storage-gcp"))]
-pub use filestore::FileBackend as Backend;
+impl HeadMac {
+    /// Shortest accepted key, in bytes
+    const MIN_KEY_LEN: usize = 32;
+    /// Length of a tag, in bytes
+    const TAG_LEN: usize = 32;
 
-#[derive(Debug, Serialize, Deserialize)]
+    pub fn new(key: &[u8]) -> Result<Self, anyhow::Error> {
+        if key.len() < Self::MIN_KEY_LEN {
+            return Err(anyhow::anyhow!(
+                "Storage MAC key must be at least {} bytes",
+                Self::MIN_KEY_LEN
+            ));
+        }
+        let mac =
+            Hmac::new_from_slice(key).map_err(|_| anyhow::anyhow!("Invalid storage MAC key"))?;
+        Ok(Self(mac))
+    }
+
+    /// Read the key at `storage_mac_key_path`, if it is set.
+    pub fn from_config(config: &ClientConfig) -> Result<Option<Self>, anyhow::Error> {
+        let Some(path) = &config.storage_mac_key_path else {
+            return Ok(None);
+        };
+        let key = std::fs::read(path)
+            .context(format!("Failed to read storage MAC key {}", path.display()))?;
+        Self::new(&key).map(Some)
+    }
+
+    fn tag(&self, data: &[u8]) -> Vec<u8> {
+        let mut mac = self.0.clone();
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn verify(&self, data: &[u8], tag: &[u8]) -> Result<(), anyhow::Error> {
+        let mut mac = self.0.clone();
+        mac.update(data);
+        mac.verify_slice(tag)
+            .map_err(|_| anyhow::anyhow!("Log head MAC is invalid"))
+    }
+}
+
+/// The legacy (version 1) stored head format.
+#[derive(Debug, Deserialize)]
 struct StoredHead {
     version: u8,
     #[serde(with = "serde_bytes")]
@@ -33,30 +174,278 @@ pub trait Storage: Sized {
     /// Initialize the storage from a config
     async fn init_from_config(config: &ClientConfig) -> Result<Self, anyhow::Error>;
 
+    /// A description of where the log head is stored
+    fn location(&self) -> String;
+
     /// Commit a log head to storage
     async fn commit_head(&mut self, head: &TransparencyLog) -> Result<(), anyhow::Error>;
 
     /// Get the log head from storage, if it exists
     /// Returns None if the storage is not initialized
     async fn get_head(&mut self) -> Result<Option<TransparencyLog>, anyhow::Error>;
+
+    /// Move the stored log head aside so that it is preserved for investigation
+    /// and no longer loaded by `get_head`.
+    /// Returns a description of where the head was moved to.
+    async fn quarantine_head(&mut self) -> Result<String, anyhow::Error>;
+
+    /// Publish an auxiliary artifact, such as a signed statement, alongside the log head.
+    /// Returns a description of where the artifact was written.
+    async fn publish_artifact(
+        &mut self,
+        name: &str,
+        data: Vec<u8>,
+    ) -> Result<String, anyhow::Error>;
+
+    /// Store a copy of `head` as the snapshot `name`, separately from the log head.
+    /// Existing snapshots are never replaced.
+    /// Returns where the snapshot was written, or `None` if it already existed.
+    async fn store_snapshot(
+        &mut self,
+        name: &str,
+        head: &TransparencyLog,
+    ) -> Result<Option<String>, anyhow::Error>;
+
+    /// The largest snapshot with a log size of at most `max_size`, and where it
+    /// is stored. Snapshots that cannot be read are skipped.
+    async fn latest_snapshot(
+        &mut self,
+        max_size: u64,
+    ) -> Result<Option<(String, TransparencyLog)>, anyhow::Error>;
+
+    /// Read back the stored log head and check that it matches `expected`.
+    ///
+    /// Used after `commit_head` to confirm that the head about to be signed
+    /// has actually been persisted.
+    async fn verify_committed(&mut self, expected: &TransparencyLog) -> Result<(), CommitMismatch> {
+        let stored = self
+            .get_head()
+            .await
+            .map_err(CommitMismatch::Unreadable)?
+            .ok_or(CommitMismatch::Missing)?;
+        if stored.size() != expected.size() {
+            return Err(CommitMismatch::Size {
+                stored: stored.size(),
+                expected: expected.size(),
+            });
+        }
+        if stored.log_root().ok() != expected.log_root().ok() {
+            return Err(CommitMismatch::Root);
+        }
+        Ok(())
+    }
 }
 
-/// Serialize a log head to a byte vector, and include a MAC
-fn serialize_head(head: &TransparencyLog) -> Result<Vec<u8>, anyhow::Error> {
-    let serialized = serde_cbor::ser::to_vec_packed(head)?;
-    let stored_head = StoredHead {
-        log_cache: serialized,
-        version: VERSION,
-    };
-    Ok(serde_cbor::ser::to_vec_packed(&stored_head)?)
+/// The stored log head does not match the in-memory log after a commit.
+#[derive(Debug)]
+pub enum CommitMismatch {
+    /// The stored head could not be read back.
+    Unreadable(anyhow::Error),
+    /// No head was found in storage.
+    Missing,
+    /// The stored head has a different size.
+    Size { stored: u64, expected: u64 },
+    /// The stored head has the same size but a different log root.
+    Root,
 }
 
-/// Deserialize a log head from a byte vector, and verify the MAC
-fn deserialize_head(head: &[u8]) -> Result<TransparencyLog, anyhow::Error> {
-    let stored_head: StoredHead = serde_cbor::from_slice(head)?;
-    if stored_head.version != VERSION {
-        return Err(anyhow::anyhow!("Invalid version"));
+impl std::fmt::Display for CommitMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommitMismatch::Unreadable(e) => write!(f, "Committed log head is unreadable: {e:#}"),
+            CommitMismatch::Missing => write!(f, "Committed log head is missing"),
+            CommitMismatch::Size { stored, expected } => write!(
+                f,
+                "Committed log head has size {stored}, expected {expected}"
+            ),
+            CommitMismatch::Root => write!(f, "Committed log head has a different root"),
+        }
+    }
+}
+
+impl std::error::Error for CommitMismatch {}
+
+/// Serialize a log head to a byte vector, and include a MAC if `mac` is set
+///
+/// The log encoding is canonical, so equal logs are stored as identical bytes.
+/// Statistics follow the log, since they are not part of its verified state.
+fn serialize_head(head: &TransparencyLog, mac: Option<&HeadMac>) -> Result<Vec<u8>, anyhow::Error> {
+    let log = head.to_canonical_bytes();
+    let mut stored = vec![if mac.is_some() { MAC_VERSION } else { VERSION }];
+    stored.extend_from_slice(&(log.len() as u64).to_be_bytes());
+    stored.extend_from_slice(&log);
+    stored.extend_from_slice(&serde_json::to_vec(head.stats())?);
+    if let Some(mac) = mac {
+        let tag = mac.tag(&stored);
+        stored.extend_from_slice(&tag);
+    }
+    Ok(stored)
+}
+
+/// A stored log head was found but failed validation.
+///
+/// This is distinguished from transport errors so that callers
+/// can decide whether it is safe to discard the stored head.
+#[derive(Debug)]
+pub struct InvalidHead(anyhow::Error);
+
+impl std::fmt::Display for InvalidHead {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Stored log head is invalid: {:#}", self.0)
     }
-    let log: TransparencyLog = serde_cbor::from_slice(&stored_head.log_cache)?;
+}
+
+impl std::error::Error for InvalidHead {}
+
+/// Deserialize a log head from a byte vector, and verify the MAC
+///
+/// If `mac` is set, heads without a valid MAC are rejected. Otherwise, the MAC
+/// of an authenticated head is not checked.
+///
+/// Errors are wrapped in [`InvalidHead`].
+fn deserialize_head(head: &[u8], mac: Option<&HeadMac>) -> Result<TransparencyLog, anyhow::Error> {
+    let validate = || match head.split_first() {
+        Some((&MAC_VERSION, _)) => {
+            let (data, tag) = head
+                .split_at_checked(head.len().saturating_sub(HeadMac::TAG_LEN))
+                .filter(|(data, _)| !data.is_empty())
+                .ok_or(anyhow::anyhow!("Truncated log head"))?;
+            match mac {
+                Some(mac) => mac.verify(data, tag)?,
+                None => tracing::warn!(
+                    "Stored log head is authenticated, but `storage_mac_key_path` is not set"
+                ),
+            }
+            deserialize_versioned(&data[1..])
+        }
+        _ if mac.is_some() => Err(anyhow::anyhow!(
+            "Stored log head has no MAC, but `storage_mac_key_path` is set"
+        )),
+        Some((&VERSION, rest)) => deserialize_versioned(rest),
+        Some((&CANONICAL_VERSION, log)) => Ok(TransparencyLog::from_canonical_bytes(log)?),
+        // Legacy heads are a CBOR map, which never starts with a version byte
+        _ => {
+            let stored_head: StoredHead = serde_cbor::from_slice(head)?;
+            if stored_head.version != LEGACY_VERSION {
+                return Err(anyhow::anyhow!("Invalid version"));
+            }
+            let log: TransparencyLog = serde_cbor::from_slice(&stored_head.log_cache)?;
+            log.verify_consistency()?;
+            tracing::info!("Loaded legacy log head, it will be rewritten on the next commit");
+            Ok(log)
+        }
+    };
+    validate().map_err(|e| InvalidHead(e).into())
+}
+
+/// Deserialize a [`VERSION`] head after its version byte.
+fn deserialize_versioned(rest: &[u8]) -> Result<TransparencyLog, anyhow::Error> {
+    let (len, rest) = rest
+        .split_first_chunk::<8>()
+        .ok_or(anyhow::anyhow!("Truncated log head"))?;
+    let (log, stats) = rest
+        .split_at_checked(u64::from_be_bytes(*len) as usize)
+        .ok_or(anyhow::anyhow!("Truncated log head"))?;
+    let mut log = TransparencyLog::from_canonical_bytes(log)?;
+    log.set_stats(serde_json::from_slice(stats).context("Malformed update statistics")?);
     Ok(log)
 }
+
+/// A log of size one, for tests of storage and its users.
+#[cfg(test)]
+pub(crate) fn test_log() -> TransparencyLog {
+    use signal_auditor::proto::transparency::auditor_proof::{NewTree, Proof};
+    use signal_auditor::proto::transparency::{AuditorProof, AuditorUpdate};
+
+    let mut log = TransparencyLog::new();
+    log.apply_update(AuditorUpdate {
+        real: true,
+        index: vec![0; 32],
+        seed: vec![0; 16],
+        commitment: vec![0; 32],
+        proof: Some(AuditorProof {
+            proof: Some(Proof::NewTree(NewTree {})),
+        }),
+    })
+    .unwrap();
+    log
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac(key: u8) -> HeadMac {
+        HeadMac::new(&[key; 32]).unwrap()
+    }
+
+    fn is_invalid(result: Result<TransparencyLog, anyhow::Error>) -> bool {
+        result.is_err_and(|e| e.is::<InvalidHead>())
+    }
+
+    #[test]
+    fn test_mac_round_trip() {
+        let head = test_log();
+        let stored = serialize_head(&head, Some(&mac(1))).unwrap();
+        assert_eq!(stored[0], MAC_VERSION);
+        let read = deserialize_head(&stored, Some(&mac(1))).unwrap();
+        assert_eq!(read.to_canonical_bytes(), head.to_canonical_bytes());
+
+        // Authenticated heads can still be read without the key
+        let read = deserialize_head(&stored, None).unwrap();
+        assert_eq!(read.to_canonical_bytes(), head.to_canonical_bytes());
+    }
+
+    #[test]
+    fn test_mac_rejects_tampering() {
+        let stored = serialize_head(&test_log(), Some(&mac(1))).unwrap();
+        for i in [1, 10, stored.len() - 1] {
+            let mut tampered = stored.clone();
+            tampered[i] ^= 1;
+            assert!(is_invalid(deserialize_head(&tampered, Some(&mac(1)))));
+        }
+        assert!(is_invalid(deserialize_head(&stored, Some(&mac(2)))));
+        assert!(is_invalid(deserialize_head(
+            &stored[..HeadMac::TAG_LEN],
+            Some(&mac(1))
+        )));
+    }
+
+    #[test]
+    fn test_mac_rejects_unauthenticated() {
+        let stored = serialize_head(&test_log(), None).unwrap();
+        assert_eq!(stored[0], VERSION);
+        assert!(deserialize_head(&stored, None).is_ok());
+        assert!(is_invalid(deserialize_head(&stored, Some(&mac(1)))));
+
+        let mut canonical = vec![CANONICAL_VERSION];
+        canonical.extend_from_slice(&test_log().to_canonical_bytes());
+        assert!(deserialize_head(&canonical, None).is_ok());
+        assert!(is_invalid(deserialize_head(&canonical, Some(&mac(1)))));
+    }
+
+    #[test]
+    fn test_mac_key_length() {
+        assert!(HeadMac::new(&[1; HeadMac::MIN_KEY_LEN - 1]).is_err());
+        assert!(HeadMac::new(&[1; HeadMac::MIN_KEY_LEN]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_file_backend_mac() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("head");
+        let head = test_log();
+
+        let mut storage = FileBackend::new(&path, None, Some(mac(1))).unwrap();
+        storage.commit_head(&head).await.unwrap();
+        assert!(storage.verify_committed(&head).await.is_ok());
+
+        let mut unkeyed = FileBackend::new(&path, None, None).unwrap();
+        assert!(unkeyed.verify_committed(&head).await.is_ok());
+        unkeyed.commit_head(&head).await.unwrap();
+        assert!(matches!(
+            storage.verify_committed(&head).await,
+            Err(CommitMismatch::Unreadable(_))
+        ));
+    }
+}
@@ -5,23 +5,37 @@
 //! No special care is taken to ensure that the file is not corrupted
 
 use crate::client::ClientConfig;
-use crate::storage::{Storage, deserialize_head, serialize_head};
+use crate::storage::{HeadMac, Storage, deserialize_head, serialize_head};
 use signal_auditor::transparency::TransparencyLog;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub struct FileBackend {
     path: PathBuf,
+    /// Directory snapshots are written to
+    snapshot_dir: PathBuf,
+    mac: Option<HeadMac>,
 }
 
 impl FileBackend {
-    pub fn new(path: &Path) -> Result<Self, anyhow::Error> {
+    /// Use the head file at `path`, with snapshots in `snapshot_dir`, or
+    /// `snapshots` next to the head file by default, authenticated with `mac`.
+    pub fn new(
+        path: &Path,
+        snapshot_dir: Option<&Path>,
+        mac: Option<HeadMac>,
+    ) -> Result<Self, anyhow::Error> {
         // Create the directory if it doesn't exist
         std::fs::create_dir_all(path.parent().unwrap())?;
         tracing::info!("Using file storage: {}", path.display());
         Ok(Self {
             path: path.to_path_buf(),
+            snapshot_dir: snapshot_dir
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| path.with_file_name("snapshots")),
+            mac,
         })
     }
 }
@@ -33,11 +47,17 @@ impl Storage for FileBackend {
                 .storage_path
                 .as_ref()
                 .ok_or(anyhow::anyhow!("Storage path not set"))?,
+            config.snapshot_dir.as_deref(),
+            HeadMac::from_config(config)?,
         )
     }
 
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
     async fn commit_head(&mut self, head: &TransparencyLog) -> Result<(), anyhow::Error> {
-        let serialized = serialize_head(head)?;
+        let serialized = serialize_head(head, self.mac.as_ref())?;
 
         let mut file = File::create(&self.path)?;
         file.write_all(&serialized)?;
@@ -54,7 +74,86 @@ impl Storage for FileBackend {
         let mut file = File::open(&self.path)?;
         let mut file_data = Vec::new();
         file.read_to_end(&mut file_data)?;
-        let log_head = deserialize_head(&file_data)?;
-        Ok(Some(log_head)) // TODO - return error if the log is invalid
+        let log_head = deserialize_head(&file_data, self.mac.as_ref())?;
+        Ok(Some(log_head))
+    }
+
+    // Writes the artifact to `name` in the same directory as the head file
+    async fn publish_artifact(
+        &mut self,
+        name: &str,
+        data: Vec<u8>,
+    ) -> Result<String, anyhow::Error> {
+        let path = self.path.with_file_name(name);
+        let mut file = File::create(&path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        Ok(path.display().to_string())
+    }
+
+    // Writes the snapshot to `{snapshot_dir}/{name}`, failing if it exists
+    async fn store_snapshot(
+        &mut self,
+        name: &str,
+        head: &TransparencyLog,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let path = self.snapshot_dir.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        file.write_all(&serialize_head(head, self.mac.as_ref())?)?;
+        file.sync_all()?;
+        Ok(Some(path.display().to_string()))
+    }
+
+    // Reads every `{snapshot_dir}/{label}/{period}` snapshot
+    async fn latest_snapshot(
+        &mut self,
+        max_size: u64,
+    ) -> Result<Option<(String, TransparencyLog)>, anyhow::Error> {
+        let labels = match std::fs::read_dir(&self.snapshot_dir) {
+            Ok(labels) => labels,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut latest: Option<(String, TransparencyLog)> = None;
+        for label in labels {
+            let label = label?;
+            if !label.file_type()?.is_dir() {
+                continue;
+            }
+            for snapshot in std::fs::read_dir(label.path())? {
+                let path = snapshot?.path();
+                let head = match std::fs::read(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|data| deserialize_head(&data, self.mac.as_ref()))
+                {
+                    Ok(head) => head,
+                    Err(e) => {
+                        tracing::warn!("Skipping unreadable snapshot {}: {e:#}", path.display());
+                        continue;
+                    }
+                };
+                let size = head.size();
+                if size <= max_size && latest.as_ref().is_none_or(|(_, best)| best.size() < size) {
+                    latest = Some((path.display().to_string(), head));
+                }
+            }
+        }
+        Ok(latest)
+    }
+
+    // Renames the head file to `{path}.quarantined-{unix_seconds}`
+    async fn quarantine_head(&mut self) -> Result<String, anyhow::Error> {
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut quarantined = self.path.clone().into_os_string();
+        quarantined.push(format!(".quarantined-{ts}"));
+        std::fs::rename(&self.path, &quarantined)?;
+        Ok(PathBuf::from(quarantined).display().to_string())
     }
 }
@@ -6,38 +6,215 @@
 //!
 //! In order for this technique to be effective, the bucket name must be included in
 //! the image measurement used to gate the auditor signing key
+//!
+//! The head may also be replicated to buckets in other regions. Commits must be
+//! accepted by a quorum of buckets, and reads return the latest head that a quorum
+//! of buckets agree on, so a single bucket being rolled back or lost cannot
+//! change the head the auditor starts from. Buckets without a head never count
+//! towards a quorum: the log is only treated as new if every bucket is empty,
+//! and reads fail otherwise.
+//!
+//! Snapshots are written to `gcp_snapshot_bucket`, which should have a longer
+//! retention lock than the head buckets, or under `snapshots/` in the primary bucket.
 
 use crate::client::ClientConfig;
-use crate::storage::{Storage, deserialize_head, serialize_head};
+use crate::storage::{HeadMac, InvalidHead, Storage, deserialize_head, serialize_head};
 use google_cloud_storage::client::{Client, ClientConfig as GcpClientConfig};
 use google_cloud_storage::http::Error;
 use google_cloud_storage::http::error::ErrorResponse;
+use google_cloud_storage::http::objects::copy::CopyObjectRequest;
 use google_cloud_storage::http::objects::download::Range;
 use google_cloud_storage::http::objects::get::GetObjectRequest;
+use google_cloud_storage::http::objects::list::ListObjectsRequest;
 use google_cloud_storage::http::objects::upload::{Media, UploadObjectRequest, UploadType};
+use signal_auditor::Hash;
 use signal_auditor::transparency::TransparencyLog;
 
 const HEAD_OBJECT: &str = "log_head";
+/// Prefix of snapshot objects
+const SNAPSHOT_PREFIX: &str = "snapshots/";
 
-/// A storage backend using a GCP bucket
-pub struct GcpBackend {
-    bucket: String,
-    client: Client,
+/// A single bucket holding a replica of the head
+struct Bucket {
+    name: String,
     // Used to detect contention on the head object
     last_generation: Option<i64>,
+    // Set when a commit fails, after which `last_generation` may be stale
+    needs_resync: bool,
+}
+
+impl Bucket {
+    async fn commit_head(
+        &mut self,
+        client: &Client,
+        mac: Option<&HeadMac>,
+        head: &TransparencyLog,
+        data: Vec<u8>,
+    ) -> Result<(), anyhow::Error> {
+        if self.needs_resync {
+            self.resync(client, mac, head).await?;
+        }
+        let upload_type = UploadType::Simple(Media::new(HEAD_OBJECT.to_string()));
+        let response = client
+            .upload_object(
+                &UploadObjectRequest {
+                    bucket: self.name.clone(),
+                    if_generation_match: self.last_generation,
+                    ..Default::default()
+                },
+                data,
+                &upload_type,
+            )
+            .await;
+        match response {
+            Ok(response) => {
+                self.last_generation = Some(response.generation);
+                self.needs_resync = false;
+                Ok(())
+            }
+            Err(e) => {
+                self.needs_resync = true;
+                Err(e.into())
+            }
+        }
+    }
+
+    // Adopts the current generation of a replica that missed commits, as long
+    // as the head it holds is behind `head`, or is `head` itself if the failed
+    // upload was applied. A replica holding any other head was written by
+    // someone else, and stays out of the quorum.
+    async fn resync(
+        &mut self,
+        client: &Client,
+        mac: Option<&HeadMac>,
+        head: &TransparencyLog,
+    ) -> Result<(), anyhow::Error> {
+        let current = self.get_head(client, mac).await?;
+        let Some(current) = current else {
+            // The object must still not exist when the head is committed
+            self.last_generation = Some(0);
+            return Ok(());
+        };
+        let behind = current.size() < head.size()
+            || (current.size() == head.size() && current.log_root()? == head.log_root()?);
+        if !behind {
+            return Err(anyhow::anyhow!(
+                "Bucket {} holds a log head at size {}, which is not behind the head being committed at size {}",
+                self.name,
+                current.size(),
+                head.size()
+            ));
+        }
+        tracing::info!(
+            "Resynchronized log head replica in bucket {} at size {}",
+            self.name,
+            current.size()
+        );
+        Ok(())
+    }
+
+    async fn get_head(
+        &mut self,
+        client: &Client,
+        mac: Option<&HeadMac>,
+    ) -> Result<Option<TransparencyLog>, anyhow::Error> {
+        let head_file = client
+            .get_object(&GetObjectRequest {
+                bucket: self.name.clone(),
+                object: HEAD_OBJECT.to_string(),
+                ..Default::default()
+            })
+            .await;
+
+        if let Err(Error::Response(ErrorResponse { code: 404, .. })) = head_file {
+            return Ok(None);
+        }
+
+        let head_file = head_file?;
+        self.last_generation = Some(head_file.generation);
+
+        let head_file_data = client
+            .download_object(
+                &GetObjectRequest {
+                    bucket: self.name.clone(),
+                    object: HEAD_OBJECT.to_string(),
+                    generation: self.last_generation,
+                    ..Default::default()
+                },
+                &Range::default(),
+            )
+            .await?;
+        let head = deserialize_head(&head_file_data, mac)?;
+
+        Ok(Some(head))
+    }
+}
+
+/// A storage backend using one or more GCP buckets
+pub struct GcpBackend {
+    client: Client,
+    /// The primary bucket first, followed by any replicas
+    buckets: Vec<Bucket>,
+    /// Number of buckets that must accept a commit or agree on a read
+    quorum: usize,
+    /// Bucket that snapshots are written to
+    snapshot_bucket: String,
+    mac: Option<HeadMac>,
 }
 
 impl GcpBackend {
-    pub async fn new(bucket: &str) -> Result<Self, anyhow::Error> {
+    /// Use the head in `buckets`, the primary first, with snapshots in
+    /// `snapshot_bucket` or by default the primary bucket, authenticated with `mac`.
+    pub async fn new(
+        buckets: &[String],
+        quorum: usize,
+        snapshot_bucket: Option<&str>,
+        mac: Option<HeadMac>,
+    ) -> Result<Self, anyhow::Error> {
+        if quorum == 0 || quorum > buckets.len() {
+            return Err(anyhow::anyhow!(
+                "Quorum of {quorum} is not satisfiable with {} buckets",
+                buckets.len()
+            ));
+        }
+
         let config = GcpClientConfig::default().with_auth().await?;
         let client = Client::new(config);
 
         Ok(Self {
-            bucket: bucket.to_string(),
             client,
-            last_generation: None,
+            buckets: buckets
+                .iter()
+                .map(|name| Bucket {
+                    name: name.clone(),
+                    last_generation: None,
+                    needs_resync: false,
+                })
+                .collect(),
+            quorum,
+            snapshot_bucket: snapshot_bucket.unwrap_or(&buckets[0]).to_string(),
+            mac,
         })
     }
+
+    // Fails with the last error unless a quorum of buckets hold `what`
+    fn check_quorum(
+        &self,
+        locations: &[String],
+        last_error: Option<Error>,
+        what: &str,
+    ) -> Result<(), anyhow::Error> {
+        match last_error {
+            Some(e) if locations.len() < self.quorum => {
+                Err(anyhow::Error::new(e).context(format!(
+                    "Wrote {what} to {} buckets, quorum is {}",
+                    locations.len(),
+                    self.quorum
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 impl Storage for GcpBackend {
@@ -46,67 +223,288 @@ impl Storage for GcpBackend {
             .gcp_bucket
             .as_ref()
             .ok_or(anyhow::anyhow!("GCP bucket not set"))?;
-        tracing::info!("Using GCP storage bucket {bucket}");
-        Self::new(bucket)
+        let mut buckets = vec![bucket.clone()];
+        buckets.extend(config.gcp_replica_buckets.iter().cloned());
+        // A majority by default, so that two disjoint quorums cannot exist
+        let quorum = config.gcp_quorum.unwrap_or(buckets.len() / 2 + 1);
+        tracing::info!(
+            "Using GCP storage buckets {} with quorum {quorum}",
+            buckets.join(", ")
+        );
+        let mac = HeadMac::from_config(config)?;
+        Self::new(&buckets, quorum, config.gcp_snapshot_bucket.as_deref(), mac)
             .await
             .map_err(|e| anyhow::anyhow!("Failed to initialize GCP storage: {}", e))
     }
 
-    // Commits head to a file `head_{size}_{log_root_hash}`
-    // then updates `head` to point to the new file
+    fn location(&self) -> String {
+        let buckets = self
+            .buckets
+            .iter()
+            .map(|bucket| format!("gs://{}/{HEAD_OBJECT}", bucket.name))
+            .collect::<Vec<_>>();
+        format!("{} (quorum {})", buckets.join(", "), self.quorum)
+    }
+
+    // Commits the head to every bucket, and succeeds if a quorum accepted it
     async fn commit_head(&mut self, head: &TransparencyLog) -> Result<(), anyhow::Error> {
-        let serialized = serialize_head(head)?;
+        let serialized = serialize_head(head, self.mac.as_ref())?;
 
-        let upload_type = UploadType::Simple(Media::new(HEAD_OBJECT.to_string()));
-        let response = self
-            .client
-            .upload_object(
-                &UploadObjectRequest {
-                    bucket: self.bucket.clone(),
-                    if_generation_match: self.last_generation,
-                    ..Default::default()
-                },
-                serialized,
-                &upload_type,
-            )
-            .await?;
-        self.last_generation = Some(response.generation);
-        Ok(())
+        let mut accepted = 0;
+        let mut last_error = None;
+        for bucket in &mut self.buckets {
+            match bucket
+                .commit_head(&self.client, self.mac.as_ref(), head, serialized.clone())
+                .await
+            {
+                Ok(()) => accepted += 1,
+                Err(e) => {
+                    tracing::warn!("Failed to commit log head to bucket {}: {e:#}", bucket.name);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) if accepted < self.quorum => Err(e.context(format!(
+                "Log head committed to {accepted} buckets, quorum is {}",
+                self.quorum
+            ))),
+            _ => Ok(()),
+        }
     }
 
-    // Gets head from most recent object by lexicographic order
+    // Reads the head from every bucket, and returns the latest head with quorum agreement.
+    // The log is only new if every bucket reports that the head is missing
     async fn get_head(&mut self) -> Result<Option<TransparencyLog>, anyhow::Error> {
-        let head_file = self
-            .client
-            .get_object(&GetObjectRequest {
-                bucket: self.bucket.clone(),
-                object: HEAD_OBJECT.to_string(),
-                ..Default::default()
-            })
-            .await;
+        // Heads are grouped by (size, root)
+        let mut groups: Vec<((u64, Hash), usize, TransparencyLog)> = Vec::new();
+        let mut missing = 0;
+        let mut invalid = 0;
+        let mut unreachable = 0;
+        let replicated = self.buckets.len() > 1;
+        for bucket in &mut self.buckets {
+            let head = match bucket.get_head(&self.client, self.mac.as_ref()).await {
+                Ok(Some(head)) => head,
+                Ok(None) => {
+                    missing += 1;
+                    continue;
+                }
+                Err(e) => {
+                    // A single invalid or unreachable replica only counts against the quorum
+                    if !replicated {
+                        return Err(e);
+                    }
+                    tracing::warn!("Failed to read log head from bucket {}: {e:#}", bucket.name);
+                    if e.is::<InvalidHead>() {
+                        invalid += 1;
+                    } else {
+                        unreachable += 1;
+                    }
+                    continue;
+                }
+            };
+            let key = (head.size(), head.log_root().unwrap_or_default());
+            match groups.iter_mut().find(|(k, _, _)| *k == key) {
+                Some((_, count, _)) => *count += 1,
+                None => groups.push((key, 1, head)),
+            }
+        }
 
-        if let Err(Error::Response(ErrorResponse { code: 404, .. })) = head_file {
+        if missing == self.buckets.len() {
             tracing::info!("No log head found, creating new log");
             return Ok(None);
         }
 
-        let head_file = head_file?;
-        self.last_generation = Some(head_file.generation);
+        if groups.len() > 1 || missing > 0 {
+            tracing::warn!(
+                "Log head replicas disagree: {:?}, missing from {missing} buckets",
+                groups
+                    .iter()
+                    .map(|((size, root), count, _)| ((size, hex::encode(root)), *count))
+                    .collect::<Vec<_>>()
+            );
+        }
 
-        let head_file_data = self
+        let agreed = groups
+            .iter()
+            .map(|(_, count, _)| *count)
+            .max()
+            .unwrap_or_default();
+        if let Some((_, _, head)) = groups
+            .into_iter()
+            .filter(|(_, count, _)| *count >= self.quorum)
+            .max_by_key(|((size, _), _, _)| *size)
+        {
+            return Ok(Some(head));
+        }
+
+        let error = anyhow::anyhow!(
+            "No log head is agreed on by a quorum of {} buckets \
+             ({missing} missing, {invalid} invalid, {unreachable} unreachable)",
+            self.quorum
+        );
+        // Blame the invalid replicas if the quorum fails even when every
+        // unreachable replica is assumed to agree
+        if invalid > 0 && agreed + unreachable < self.quorum {
+            return Err(InvalidHead(error).into());
+        }
+        Err(error)
+    }
+
+    // Uploads the artifact as object `name` in every bucket, and succeeds if a
+    // quorum accepted it
+    async fn publish_artifact(
+        &mut self,
+        name: &str,
+        data: Vec<u8>,
+    ) -> Result<String, anyhow::Error> {
+        let mut locations = Vec::new();
+        let mut last_error = None;
+        for bucket in &self.buckets {
+            let upload_type = UploadType::Simple(Media::new(name.to_string()));
+            let uploaded = self
+                .client
+                .upload_object(
+                    &UploadObjectRequest {
+                        bucket: bucket.name.clone(),
+                        ..Default::default()
+                    },
+                    data.clone(),
+                    &upload_type,
+                )
+                .await;
+            match uploaded {
+                Ok(_) => locations.push(format!("gs://{}/{name}", bucket.name)),
+                Err(e) => {
+                    tracing::warn!("Failed to publish {name} to bucket {}: {e:#}", bucket.name);
+                    last_error = Some(e);
+                }
+            }
+        }
+        self.check_quorum(&locations, last_error, name)?;
+        Ok(locations.join(", "))
+    }
+
+    // Uploads the snapshot as `snapshots/{name}` in the snapshot bucket,
+    // only if no object of that name exists
+    async fn store_snapshot(
+        &mut self,
+        name: &str,
+        head: &TransparencyLog,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let object = format!("{SNAPSHOT_PREFIX}{name}");
+        let upload_type = UploadType::Simple(Media::new(object.clone()));
+        let uploaded = self
             .client
-            .download_object(
-                &GetObjectRequest {
-                    bucket: self.bucket.clone(),
-                    object: HEAD_OBJECT.to_string(),
-                    generation: self.last_generation,
+            .upload_object(
+                &UploadObjectRequest {
+                    bucket: self.snapshot_bucket.clone(),
+                    if_generation_match: Some(0),
                     ..Default::default()
                 },
-                &Range::default(),
+                serialize_head(head, self.mac.as_ref())?,
+                &upload_type,
             )
-            .await?;
-        let head = deserialize_head(&head_file_data)?;
+            .await;
+        match uploaded {
+            Ok(_) => Ok(Some(format!("gs://{}/{object}", self.snapshot_bucket))),
+            Err(Error::Response(ErrorResponse { code: 412, .. })) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
 
-        Ok(Some(head))
+    // Reads every object under `snapshots/` in the snapshot bucket
+    async fn latest_snapshot(
+        &mut self,
+        max_size: u64,
+    ) -> Result<Option<(String, TransparencyLog)>, anyhow::Error> {
+        let mut latest: Option<(String, TransparencyLog)> = None;
+        let mut page_token = None;
+        loop {
+            let listed = self
+                .client
+                .list_objects(&ListObjectsRequest {
+                    bucket: self.snapshot_bucket.clone(),
+                    prefix: Some(SNAPSHOT_PREFIX.to_string()),
+                    page_token: page_token.take(),
+                    ..Default::default()
+                })
+                .await?;
+            for object in listed.items.unwrap_or_default() {
+                let location = format!("gs://{}/{}", self.snapshot_bucket, object.name);
+                let head = self
+                    .client
+                    .download_object(
+                        &GetObjectRequest {
+                            bucket: self.snapshot_bucket.clone(),
+                            object: object.name,
+                            generation: Some(object.generation),
+                            ..Default::default()
+                        },
+                        &Range::default(),
+                    )
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|data| deserialize_head(&data, self.mac.as_ref()));
+                let head = match head {
+                    Ok(head) => head,
+                    Err(e) => {
+                        tracing::warn!("Skipping unreadable snapshot {location}: {e:#}");
+                        continue;
+                    }
+                };
+                let size = head.size();
+                if size <= max_size && latest.as_ref().is_none_or(|(_, best)| best.size() < size) {
+                    latest = Some((location, head));
+                }
+            }
+            match listed.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => return Ok(latest),
+            }
+        }
+    }
+
+    // Copies the current generation of the head in each bucket to
+    // `log_head.quarantined.{generation}`.
+    // The head object itself is left in place, since the bucket is retention locked;
+    // it is replaced by the next call to `commit_head`.
+    async fn quarantine_head(&mut self) -> Result<String, anyhow::Error> {
+        let mut locations = Vec::new();
+        let mut last_error = None;
+        for bucket in &self.buckets {
+            let Some(generation) = bucket.last_generation.filter(|generation| *generation > 0)
+            else {
+                continue;
+            };
+            let destination = format!("{HEAD_OBJECT}.quarantined.{generation}");
+            let copied = self
+                .client
+                .copy_object(&CopyObjectRequest {
+                    source_bucket: bucket.name.clone(),
+                    source_object: HEAD_OBJECT.to_string(),
+                    source_generation: Some(generation),
+                    destination_bucket: bucket.name.clone(),
+                    destination_object: destination.clone(),
+                    ..Default::default()
+                })
+                .await;
+            match copied {
+                Ok(_) => locations.push(format!("gs://{}/{destination}", bucket.name)),
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to quarantine log head in bucket {}: {e:#}",
+                        bucket.name
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+        if locations.is_empty() && last_error.is_none() {
+            return Err(anyhow::anyhow!("No log head to quarantine"));
+        }
+        self.check_quorum(&locations, last_error, "the quarantined log head")?;
+        Ok(locations.join(", "))
     }
 }
@@ -0,0 +1,250 @@
+//! Connections to the Key Transparency service.
+//!
+//! Endpoints are either `https://` URIs, connected to with mTLS using the
+//! configured client identity, or `unix://` paths to a local socket. Unix sockets
+//! are intended for deployments where a sidecar proxy handles mTLS and authentication,
+//! so plaintext HTTP/2 is used over the socket.
+//!
+//! For TCP endpoints, the address family can be restricted or preferred with
+//! `ip_preference`, for networks where one family is broken and would otherwise
+//! use up the connect timeout on every attempt.
+//!
+//! TCP endpoints may instead be reached through a SOCKS5 proxy such as Tor, so
+//! that the log operator does not learn the auditor's network location. The
+//! endpoint host is resolved by the proxy, which allows `.onion` endpoints and
+//! avoids leaking DNS queries, and `ip_preference` does not apply. The proxy
+//! also carries the auditor's other HTTP requests, through [`http_client`].
+
+use anyhow::Context;
+use hyper_util::rt::TokioIo;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::{TcpStream, UnixStream};
+use tokio_socks::tcp::Socks5Stream;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity, Uri};
+use tower::service_fn;
+
+use crate::client::{ClientConfig, IpPreference};
+
+const UNIX_SCHEME: &str = "unix://";
+
+/// An endpoint for the Key Transparency service.
+#[derive(Clone)]
+pub struct ServerEndpoint {
+    endpoint: Endpoint,
+    /// Set if the endpoint is a `unix://` socket
+    unix_socket: Option<PathBuf>,
+    ip_preference: IpPreference,
+    /// Address of a SOCKS5 proxy to connect through
+    socks5_proxy: Option<String>,
+    connect_timeout: Duration,
+}
+
+impl ServerEndpoint {
+    /// Create an endpoint for `uri`, using the TLS and timeout settings from `config`.
+    ///
+    /// The channel timeout is an upper bound on every request, so it is the longest
+    /// of the configured timeouts; requests set their own, shorter timeouts.
+    pub fn new(uri: &str, config: &ClientConfig) -> Result<Self, anyhow::Error> {
+        let timeout = config
+            .request_timeout()
+            .max(config.audit_timeout())
+            .max(config.submit_timeout());
+        let connect_timeout = config.connect_timeout();
+
+        if let Some(path) = uri.strip_prefix(UNIX_SCHEME) {
+            if config.socks5_proxy.is_some() {
+                return Err(anyhow::anyhow!(
+                    "socks5_proxy cannot be used with a unix:// endpoint"
+                ));
+            }
+            // The URI is not used to connect, but must be well formed
+            let endpoint = Endpoint::from_static("http://localhost")
+                .timeout(timeout)
+                .connect_timeout(connect_timeout);
+            return Ok(Self {
+                endpoint,
+                unix_socket: Some(PathBuf::from(path)),
+                ip_preference: config.ip_preference,
+                socks5_proxy: None,
+                connect_timeout,
+            });
+        }
+
+        let endpoint = Endpoint::from_shared(uri.to_string())
+            .context("Failed to create endpoint")?
+            .tls_config(tls_config(config)?)
+            .context("Failed to create TLS config")?
+            .timeout(timeout);
+        // With a preference, the timeout applies to each address rather than the whole connect
+        let endpoint = match config.ip_preference {
+            IpPreference::Any => endpoint.connect_timeout(connect_timeout),
+            _ => endpoint,
+        };
+        Ok(Self {
+            endpoint,
+            unix_socket: None,
+            ip_preference: config.ip_preference,
+            socks5_proxy: config.socks5_proxy.clone(),
+            connect_timeout,
+        })
+    }
+
+    /// Open a new channel to the endpoint.
+    pub async fn connect(&self) -> Result<Channel, tonic::transport::Error> {
+        if let Some(proxy) = &self.socks5_proxy {
+            let (proxy, timeout) = (proxy.clone(), self.connect_timeout);
+            return self
+                .endpoint
+                .connect_with_connector(service_fn(move |uri: Uri| {
+                    let proxy = proxy.clone();
+                    async move {
+                        Ok::<_, std::io::Error>(TokioIo::new(
+                            connect_socks5(&proxy, &uri, timeout).await?,
+                        ))
+                    }
+                }))
+                .await;
+        }
+        match &self.unix_socket {
+            None if self.ip_preference == IpPreference::Any => self.endpoint.connect().await,
+            None => {
+                let (preference, timeout) = (self.ip_preference, self.connect_timeout);
+                self.endpoint
+                    .connect_with_connector(service_fn(move |uri: Uri| async move {
+                        Ok::<_, std::io::Error>(TokioIo::new(
+                            connect_tcp(&uri, preference, timeout).await?,
+                        ))
+                    }))
+                    .await
+            }
+            Some(path) => {
+                let path = path.clone();
+                self.endpoint
+                    .connect_with_connector(service_fn(move |_: Uri| {
+                        let path = path.clone();
+                        async move {
+                            Ok::<_, std::io::Error>(TokioIo::new(UnixStream::connect(path).await?))
+                        }
+                    }))
+                    .await
+            }
+        }
+    }
+}
+
+/// Resolve the host in `uri` and connect to its addresses in order of `preference`,
+/// allowing each address up to `timeout`.
+async fn connect_tcp(
+    uri: &Uri,
+    preference: IpPreference,
+    timeout: Duration,
+) -> Result<TcpStream, std::io::Error> {
+    let (host, port) = host_port(uri)?;
+    let mut addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await?
+        .filter(|addr| match preference {
+            IpPreference::Ipv4Only => addr.is_ipv4(),
+            IpPreference::Ipv6Only => addr.is_ipv6(),
+            _ => true,
+        })
+        .collect();
+    // Stable, so the resolver order is kept within each family
+    match preference {
+        IpPreference::PreferIpv4 => addrs.sort_by_key(|addr| addr.is_ipv6()),
+        IpPreference::PreferIpv6 => addrs.sort_by_key(|addr| addr.is_ipv4()),
+        _ => {}
+    }
+
+    let mut last_error = std::io::Error::other(format!("No usable addresses for {host}"));
+    for addr in addrs {
+        match tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => {
+                stream.set_nodelay(true)?;
+                return Ok(stream);
+            }
+            Ok(Err(e)) => last_error = e,
+            Err(_) => {
+                last_error = std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("Connecting to {addr} timed out"),
+                )
+            }
+        }
+        tracing::debug!("Failed to connect to {addr}: {last_error}");
+    }
+    Err(last_error)
+}
+
+/// Connect to the host in `uri` through the SOCKS5 proxy at `proxy`,
+/// allowing up to `timeout` for the proxy to establish the connection.
+async fn connect_socks5(
+    proxy: &str,
+    uri: &Uri,
+    timeout: Duration,
+) -> Result<TcpStream, std::io::Error> {
+    let (host, port) = host_port(uri)?;
+    // Pass the host name rather than resolving it, so the proxy resolves it
+    let stream = tokio::time::timeout(timeout, Socks5Stream::connect(proxy, (host, port)))
+        .await
+        .map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("Connecting to {host} through {proxy} timed out"),
+            )
+        })?
+        .map_err(|e| std::io::Error::other(format!("SOCKS5 proxy {proxy}: {e}")))?
+        .into_inner();
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// The host and port in `uri`, with the port defaulting from the scheme.
+fn host_port(uri: &Uri) -> Result<(&str, u16), std::io::Error> {
+    let host = uri
+        .host()
+        .ok_or(std::io::Error::other("Endpoint has no host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = uri
+        .port_u16()
+        .unwrap_or(if uri.scheme_str() == Some("http") {
+            80
+        } else {
+            443
+        });
+    Ok((host, port))
+}
+
+/// An HTTP client for requests other than those to the Key Transparency
+/// service, such as fetching the published keys and delivering error reports.
+///
+/// Requests go through `socks5_proxy` if it is set, with host names resolved
+/// by the proxy.
+pub fn http_client(config: &ClientConfig) -> Result<reqwest::Client, anyhow::Error> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = &config.socks5_proxy {
+        builder = builder.proxy(
+            reqwest::Proxy::all(format!("socks5h://{proxy}")).context("Invalid socks5_proxy")?,
+        );
+    }
+    builder.build().context("Failed to create HTTP client")
+}
+
+/// Build the mTLS configuration from the client identity and optional CA.
+fn tls_config(config: &ClientConfig) -> Result<ClientTlsConfig, anyhow::Error> {
+    let identity = Identity::from_pem(
+        std::fs::read(&config.client_cert_path).context("Failed to read client cert")?,
+        std::fs::read(&config.client_key_path).context("Failed to read client key")?,
+    );
+
+    let mut tls_config = ClientTlsConfig::new().identity(identity);
+    if let Some(ca_cert_path) = &config.ca_cert_path {
+        let ca_certificate = Certificate::from_pem(std::fs::read(ca_cert_path)?);
+        tls_config = tls_config.ca_certificate(ca_certificate);
+    } else {
+        tls_config = tls_config.with_enabled_roots();
+    }
+    Ok(tls_config)
+}
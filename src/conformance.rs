@@ -0,0 +1,271 @@
+//! Conformance recordings.
+//!
+//! A recording pins a sequence of `Audit` responses exactly as a log served them,
+//! typically the staging server, starting from the beginning of the log, together
+//! with the golden head after each response: the log size, the log root and a
+//! signature. Replaying a recording with [`Recording::check`] confirms that a
+//! build decodes, verifies and signs exactly like the build that made it.
+//!
+//! Golden heads are signed with the fixed [`conformance_key`] at the time each
+//! response was recorded, rather than with the auditor's key at the current time,
+//! so that they can be reproduced by any build. Ed25519 signatures are
+//! deterministic, so a change to the signed head encoding changes them.
+
+use ed25519_dalek::{SignatureError, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+use crate::auditor::{DeploymentMode, PublicConfig};
+use crate::transcript::{EncodedUpdate, ReplayError, TranscriptHead, apply_encoded};
+use crate::transparency::{LogError, TransparencyLog};
+
+/// Domain separation string the conformance key is derived from.
+pub const CONFORMANCE_CONTEXT: &[u8] = b"signal-auditor-conformance-v1\n";
+
+/// The key golden heads are signed with, whose seed is the SHA-256 digest of
+/// [`CONFORMANCE_CONTEXT`].
+///
+/// This key is public, and its signatures carry no weight outside of
+/// conformance checks.
+pub fn conformance_key() -> SigningKey {
+    SigningKey::from_bytes(&Sha256::digest(CONFORMANCE_CONTEXT).into())
+}
+
+/// A pinned sequence of log responses and the heads they lead to.
+///
+/// Byte fields are hex-encoded when serialized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recording {
+    /// Where and when the responses were recorded
+    pub description: String,
+    /// The deployment mode heads are signed for
+    pub mode: u8,
+    /// The log operator's signing public key
+    #[serde(with = "hex::serde")]
+    pub sig_key: [u8; 32],
+    /// The log operator's VRF public key
+    #[serde(with = "hex::serde")]
+    pub vrf_key: [u8; 32],
+    /// Responses in the order they were served, from the start of the log
+    pub responses: Vec<RecordedResponse>,
+}
+
+/// A response as served by the log, and the golden head after applying it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    /// Encoded `AuditorUpdate`s, exactly as served by the log
+    pub updates: Vec<EncodedUpdate>,
+    /// The head after the updates, signed with the [`conformance_key`]
+    pub head: TranscriptHead,
+}
+
+/// The reason a recording could not be made or does not conform.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConformanceError {
+    /// The deployment mode is not known to this version
+    UnknownDeploymentMode(u8),
+    /// The log operator's signing key is not a valid Ed25519 key
+    InvalidSigKey(SignatureError),
+    /// The log operator's VRF key is not a valid Ed25519 key
+    InvalidVrfKey(SignatureError),
+    /// An update could not be applied
+    Replay(ReplayError),
+    /// The log after a response has no root
+    Log(LogError),
+    /// A recorded response could not be replayed
+    Response {
+        index: usize,
+        error: Box<ConformanceError>,
+    },
+    /// A recorded response leads to a different head than the golden head
+    HeadMismatch {
+        index: usize,
+        head: Box<TranscriptHead>,
+        expected: Box<TranscriptHead>,
+    },
+}
+
+impl fmt::Display for ConformanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConformanceError::UnknownDeploymentMode(mode) => {
+                write!(f, "Unknown deployment mode {mode}")
+            }
+            ConformanceError::InvalidSigKey(_) => write!(f, "Invalid log signing key"),
+            ConformanceError::InvalidVrfKey(_) => write!(f, "Invalid log VRF key"),
+            ConformanceError::Replay(error) => write!(f, "{error}"),
+            ConformanceError::Log(error) => write!(f, "{error}"),
+            ConformanceError::Response { index, .. } => write!(f, "Response {index} failed"),
+            ConformanceError::HeadMismatch {
+                index,
+                head,
+                expected,
+            } => write!(
+                f,
+                "Response {index} leads to size {}, root {} and signature {}, but the recording expects size {}, root {} and signature {}",
+                head.tree_size,
+                hex::encode(head.root),
+                hex::encode(&head.signature),
+                expected.tree_size,
+                hex::encode(expected.root),
+                hex::encode(&expected.signature)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConformanceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConformanceError::InvalidSigKey(error) | ConformanceError::InvalidVrfKey(error) => {
+                Some(error)
+            }
+            ConformanceError::Replay(error) => error.source(),
+            ConformanceError::Log(error) => error.source(),
+            ConformanceError::Response { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl Recording {
+    /// Replay the recording on `threads` threads, checking that every response
+    /// leads to its golden head. Returns the log after the last response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an update is malformed or fails verification, or if
+    /// a head differs from the golden head.
+    pub fn check(&self, threads: usize) -> Result<TransparencyLog, ConformanceError> {
+        let config = public_config(
+            DeploymentMode::try_from(self.mode).map_err(ConformanceError::UnknownDeploymentMode)?,
+            VerifyingKey::from_bytes(&self.sig_key).map_err(ConformanceError::InvalidSigKey)?,
+            VerifyingKey::from_bytes(&self.vrf_key).map_err(ConformanceError::InvalidVrfKey)?,
+        );
+        let key = conformance_key();
+        let mut log = TransparencyLog::new();
+        for (index, response) in self.responses.iter().enumerate() {
+            let head = step(
+                &config,
+                &key,
+                &mut log,
+                &response.updates,
+                response.head.timestamp,
+                threads,
+            )
+            .map_err(|error| ConformanceError::Response {
+                index,
+                error: Box::new(error),
+            })?;
+            if head != response.head {
+                return Err(ConformanceError::HeadMismatch {
+                    index,
+                    head: Box::new(head),
+                    expected: Box::new(response.head.clone()),
+                });
+            }
+        }
+        Ok(log)
+    }
+}
+
+/// Builds a [`Recording`] from responses as they are served.
+pub struct Recorder {
+    recording: Recording,
+    config: PublicConfig,
+    key: SigningKey,
+    log: TransparencyLog,
+}
+
+impl Recorder {
+    /// Start a recording from the beginning of a log with the given keys, with
+    /// heads signed for `mode`.
+    pub fn new(
+        description: String,
+        mode: DeploymentMode,
+        sig_key: VerifyingKey,
+        vrf_key: VerifyingKey,
+    ) -> Self {
+        Self {
+            recording: Recording {
+                description,
+                mode: mode.into(),
+                sig_key: sig_key.to_bytes(),
+                vrf_key: vrf_key.to_bytes(),
+                responses: vec![],
+            },
+            config: public_config(mode, sig_key, vrf_key),
+            key: conformance_key(),
+            log: TransparencyLog::new(),
+        }
+    }
+
+    /// Record a response received at `timestamp`, in milliseconds since the
+    /// Unix epoch, returning its golden head.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an update is malformed or fails verification, in
+    /// which case nothing is recorded.
+    pub fn push(
+        &mut self,
+        updates: Vec<Vec<u8>>,
+        timestamp: i64,
+    ) -> Result<&TranscriptHead, ConformanceError> {
+        let updates: Vec<_> = updates.into_iter().map(EncodedUpdate).collect();
+        let head = step(
+            &self.config,
+            &self.key,
+            &mut self.log,
+            &updates,
+            timestamp,
+            1,
+        )?;
+        self.recording
+            .responses
+            .push(RecordedResponse { updates, head });
+        Ok(&self.recording.responses.last().unwrap().head)
+    }
+
+    /// The completed recording.
+    pub fn finish(self) -> Recording {
+        self.recording
+    }
+}
+
+fn public_config(
+    mode: DeploymentMode,
+    sig_key: VerifyingKey,
+    vrf_key: VerifyingKey,
+) -> PublicConfig {
+    PublicConfig {
+        mode,
+        sig_key,
+        vrf_key,
+        auditor_key: conformance_key().verifying_key(),
+    }
+}
+
+/// Apply a response to `log` and sign the resulting head at `timestamp`.
+///
+/// The log is left unchanged if the response fails verification.
+fn step(
+    config: &PublicConfig,
+    key: &SigningKey,
+    log: &mut TransparencyLog,
+    updates: &[EncodedUpdate],
+    timestamp: i64,
+    threads: usize,
+) -> Result<TranscriptHead, ConformanceError> {
+    *log = apply_encoded(log, updates, threads).map_err(ConformanceError::Replay)?;
+    let root = log.log_root().map_err(ConformanceError::Log)?;
+    let signature = key.sign(&config.encode_at_time(root, log.size(), timestamp));
+    Ok(TranscriptHead {
+        tree_size: log.size(),
+        timestamp,
+        root,
+        signature: signature.to_vec(),
+    })
+}
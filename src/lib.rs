@@ -2,9 +2,12 @@
 //! [Signal Key Transparency Log.](https://github.com/signalapp/key-transparency-server)
 
 pub mod auditor;
+pub mod conformance;
 pub mod log;
 pub mod prefix;
+pub mod transcript;
 pub mod transparency;
+pub mod wire;
 /// Protocol buffer definitions for transparency log network messages.
 pub mod proto {
     pub mod transparency {
@@ -13,19 +16,18 @@ pub mod proto {
     pub mod kt {
         include!(concat!(env!("OUT_DIR"), "/kt.rs"));
     }
+    /// The service hosted by the auditor for pulling signed heads.
+    pub mod auditor {
+        include!(concat!(env!("OUT_DIR"), "/auditor.rs"));
+    }
+    /// The standard gRPC health checking protocol.
+    pub mod health {
+        include!(concat!(env!("OUT_DIR"), "/grpc.health.v1.rs"));
+    }
 }
 
-type Hash = [u8; 32];
-/// Convert a vector of bytes into a hash.
-///
-/// # Errors
-///
-/// Returns an error if the input is not 32 bytes.
-fn try_into_hash(x: Vec<u8>) -> Result<Hash, anyhow::Error> {
-    let arr: [u8; 32] = x.try_into().map_err(|_| anyhow::anyhow!("Invalid hash"))?;
-    Ok(arr)
-}
-
+/// A SHA-256 digest, used for tree nodes and roots.
+pub type Hash = [u8; 32];
 type Index = [u8; 32];
 type Seed = [u8; 16];
 
@@ -67,5 +69,6 @@ mod tests {
 
         assert!(log.is_initialized());
         assert_eq!(log.log_root().unwrap(), expected_log_root);
+        log.verify_consistency().unwrap();
     }
 }
@@ -37,6 +37,7 @@
 //! ```
 
 use crate::Hash;
+use crate::transparency::LogError;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
@@ -107,6 +108,56 @@ impl LogTreeCache {
         self.roots.push(new_node);
     }
 
+    /// The cached subtrees as (size, root) pairs, from left to right.
+    pub fn nodes(&self) -> impl Iterator<Item = (u64, &Hash)> {
+        self.roots.iter().map(|x| (x.size, &x.root))
+    }
+
+    /// Build a cache from (size, root) pairs, from left to right.
+    ///
+    /// The result is not validated; see [`LogTreeCache::check_frontier`].
+    pub fn from_nodes(nodes: impl IntoIterator<Item = (u64, Hash)>) -> Self {
+        Self {
+            roots: nodes
+                .into_iter()
+                .map(|(size, root)| LogNode { root, size })
+                .collect(),
+        }
+    }
+
+    /// The number of leaves in the log, as recorded by the cached subtree sizes.
+    pub fn size(&self) -> u64 {
+        self.roots.iter().map(|x| x.size).sum()
+    }
+
+    /// Check that the cached roots describe a valid left-balanced tree.
+    ///
+    /// Every cached subtree must be complete (a power of two in size) and the
+    /// sizes must be strictly decreasing from left to right.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first malformed subtree.
+    pub fn check_frontier(&self) -> Result<(), LogError> {
+        let mut prev: Option<u64> = None;
+        for (subtree, node) in self.roots.iter().enumerate() {
+            if !node.size.is_power_of_two() {
+                return Err(LogError::IncompleteSubtree {
+                    subtree,
+                    size: node.size,
+                });
+            }
+            if prev.is_some_and(|p| p <= node.size) {
+                return Err(LogError::UnorderedSubtree {
+                    subtree,
+                    size: node.size,
+                });
+            }
+            prev = Some(node.size);
+        }
+        Ok(())
+    }
+
     /// Compute the root of the log tree.
     pub fn root(&self) -> Option<Hash> {
         let mut roots = self.roots.clone();
@@ -161,4 +212,24 @@ mod tests {
 
         assert_eq!(log.root().unwrap(), expected_root);
     }
+
+    #[test]
+    fn test_check_frontier() {
+        let mut log = LogTreeCache::new();
+        for i in 0..7u8 {
+            log.insert(&[i; 32]);
+            log.check_frontier().unwrap();
+            assert_eq!(log.size(), i as u64 + 1);
+        }
+
+        // Two subtrees of equal size should have been merged.
+        let mut bad = log.clone();
+        bad.roots[1].size = bad.roots[0].size;
+        assert!(bad.check_frontier().is_err());
+
+        // Subtrees must be complete.
+        let mut bad = log.clone();
+        bad.roots[2].size = 3;
+        assert!(bad.check_frontier().is_err());
+    }
 }
@@ -22,8 +22,8 @@
 
 use crate::proto::transparency::AuditorUpdate;
 use crate::proto::transparency::auditor_proof::{DifferentKey, Proof, SameKey};
-use crate::{Hash, Index, Seed, try_into_hash};
-use anyhow::{Result, anyhow};
+use crate::transparency::{UpdateError, UpdateField};
+use crate::{Hash, Index, Seed};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
@@ -64,29 +64,133 @@ pub(crate) enum PrefixTreeUpdate {
     },
 }
 
+/// The roots before and after an update, computed from the update alone.
+///
+/// Computing these is the expensive part of applying an update, and does not
+/// depend on the tree, so the roots of many updates can be computed in parallel
+/// and then checked against each other with [`PrefixTreeCache::chain`].
+pub(crate) struct SpeculativeRoots {
+    /// The root the update's proof is for, or `None` for a new tree
+    old: Option<Hash>,
+    /// The root after the update
+    new: Hash,
+}
+
+impl PrefixTreeUpdate {
+    /// Compute the roots of the update, assuming it is applied to a tree of size `size`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the update is malformed.
+    pub(crate) fn speculate(&self, size: u64) -> Result<SpeculativeRoots, UpdateError> {
+        match self {
+            PrefixTreeUpdate::NewTree { index, seed } => {
+                let proof = PrefixProof::real(
+                    &PrefixLeaf {
+                        index: *index,
+                        counter: 0,
+                        position: 0,
+                    },
+                    &[],
+                    seed,
+                )?;
+                Ok(SpeculativeRoots {
+                    old: None,
+                    new: proof.compute_root(),
+                })
+            }
+            PrefixTreeUpdate::SameKey {
+                index,
+                copath,
+                seed,
+                counter,
+                position,
+            } => {
+                // The lookup at counter, position gives the old root.
+                let old = PrefixProof::real(
+                    &PrefixLeaf {
+                        index: *index,
+                        counter: *counter,
+                        position: *position,
+                    },
+                    copath,
+                    seed,
+                )?;
+                let new = PrefixProof::real(
+                    &PrefixLeaf {
+                        index: *index,
+                        counter: counter.checked_add(1).ok_or(UpdateError::CounterOverflow)?,
+                        // Tracks the _first_ time the index was inserted.
+                        position: *position,
+                    },
+                    copath,
+                    seed,
+                )?;
+                Ok(SpeculativeRoots {
+                    old: Some(old.compute_root()),
+                    new: new.compute_root(),
+                })
+            }
+            PrefixTreeUpdate::DifferentKey {
+                real,
+                index,
+                seed,
+                old_seed,
+                copath,
+            } => {
+                // DifferentKey updates always replace a fake node.
+                // The proof is a non-inclusion proof, terminating at the first stand-in hash.
+                let old = PrefixProof::fake(index, copath, old_seed)?;
+                let new = if *real {
+                    PrefixProof::real(
+                        &PrefixLeaf {
+                            index: *index,
+                            counter: 0,
+                            position: size,
+                        },
+                        copath,
+                        seed,
+                    )?
+                } else {
+                    PrefixProof::fake(index, copath, seed)?
+                };
+                Ok(SpeculativeRoots {
+                    old: Some(old.compute_root()),
+                    new: new.compute_root(),
+                })
+            }
+        }
+    }
+}
+
 // Convert an auditor update off the wire into a prefix tree update.
-impl TryFrom<AuditorUpdate> for PrefixTreeUpdate {
-    type Error = anyhow::Error;
-    fn try_from(update: AuditorUpdate) -> Result<Self, Self::Error> {
+//
+// Only the fixed-size fields are copied, so the update can stay shared between threads.
+impl TryFrom<&AuditorUpdate> for PrefixTreeUpdate {
+    type Error = UpdateError;
+    fn try_from(update: &AuditorUpdate) -> Result<Self, Self::Error> {
         let proof = update
             .proof
-            .and_then(|x| x.proof)
-            .ok_or(anyhow!("Missing proof"))?;
+            .as_ref()
+            .and_then(|x| x.proof.as_ref())
+            .ok_or(UpdateError::MissingProof)?;
         match proof {
             Proof::NewTree(_) => {
                 // New trees always start with one real leaf.
                 if !update.real {
-                    return Err(anyhow!("Fake update"));
+                    return Err(UpdateError::FakeUpdate);
                 }
                 Ok(PrefixTreeUpdate::NewTree {
                     index: update
                         .index
+                        .as_slice()
                         .try_into()
-                        .map_err(|_| anyhow!("Invalid index"))?,
+                        .map_err(|_| UpdateError::InvalidField(UpdateField::Index))?,
                     seed: update
                         .seed
+                        .as_slice()
                         .try_into()
-                        .map_err(|_| anyhow!("Invalid seed"))?,
+                        .map_err(|_| UpdateError::InvalidField(UpdateField::Seed))?,
                 })
             }
             Proof::DifferentKey(DifferentKey { copath, old_seed }) => {
@@ -94,18 +198,22 @@ impl TryFrom<AuditorUpdate> for PrefixTreeUpdate {
                     real: update.real,
                     index: update
                         .index
+                        .as_slice()
                         .try_into()
-                        .map_err(|_| anyhow!("Invalid index"))?,
+                        .map_err(|_| UpdateError::InvalidField(UpdateField::Index))?,
                     seed: update
                         .seed
+                        .as_slice()
                         .try_into()
-                        .map_err(|_| anyhow!("Invalid seed"))?,
+                        .map_err(|_| UpdateError::InvalidField(UpdateField::Seed))?,
                     old_seed: old_seed
+                        .as_slice()
                         .try_into()
-                        .map_err(|_| anyhow!("Invalid old seed"))?,
+                        .map_err(|_| UpdateError::InvalidField(UpdateField::OldSeed))?,
                     copath: copath
-                        .into_iter()
-                        .map(try_into_hash)
+                        .iter()
+                        .map(Vec::as_slice)
+                        .map(copath_node)
                         .collect::<Result<Vec<_>, _>>()?,
                 })
             }
@@ -116,24 +224,27 @@ impl TryFrom<AuditorUpdate> for PrefixTreeUpdate {
             }) => {
                 // Real leaves cannot be replaced with fake nodes.
                 if !update.real {
-                    return Err(anyhow!("Fake update"));
+                    return Err(UpdateError::FakeUpdate);
                 }
 
                 Ok(PrefixTreeUpdate::SameKey {
                     index: update
                         .index
+                        .as_slice()
                         .try_into()
-                        .map_err(|_| anyhow!("Invalid index"))?,
+                        .map_err(|_| UpdateError::InvalidField(UpdateField::Index))?,
                     copath: copath
-                        .into_iter()
-                        .map(try_into_hash)
+                        .iter()
+                        .map(Vec::as_slice)
+                        .map(copath_node)
                         .collect::<Result<Vec<_>, _>>()?,
                     seed: update
                         .seed
+                        .as_slice()
                         .try_into()
-                        .map_err(|_| anyhow!("Invalid seed"))?,
-                    counter,
-                    position,
+                        .map_err(|_| UpdateError::InvalidField(UpdateField::Seed))?,
+                    counter: *counter,
+                    position: *position,
                 })
             }
         }
@@ -155,106 +266,32 @@ impl PrefixTreeCache {
 
     /// Apply an update to the prefix tree
     ///
-    /// Returns the new head of the tree and the new position.
-    ///
     /// # Errors
     ///
     /// Returns an error if the update is malformed or inconsistent with the current state.
-    pub(crate) fn apply_update(&mut self, update: PrefixTreeUpdate) -> Result<(), anyhow::Error> {
-        let proof = match update {
-            PrefixTreeUpdate::NewTree { index, seed } => {
-                if self.is_initialized() {
-                    return Err(anyhow!("Tree already initialized"));
-                }
-
-                PrefixProof::real(
-                    &PrefixLeaf {
-                        index,
-                        counter: 0,
-                        position: 0,
-                    },
-                    &[],
-                    &seed,
-                )
-            }
-            PrefixTreeUpdate::SameKey {
-                index,
-                copath,
-                seed,
-                counter,
-                position,
-            } => {
-                if !self.is_initialized() {
-                    return Err(anyhow!("Tree not initialized"));
-                }
-
-                // Check that lookup at counter, position is the same as the old root.
-                let proof = PrefixProof::real(
-                    &PrefixLeaf {
-                        index,
-                        counter,
-                        position,
-                    },
-                    &copath,
-                    &seed,
-                )?;
-
-                // Check the proof is consistent with the current root.
-                if proof.compute_root() != self.head {
-                    return Err(anyhow!("Old root mismatch"));
-                }
-
-                // Update the cache
-                PrefixProof::real(
-                    &PrefixLeaf {
-                        index,
-                        counter: counter.checked_add(1).ok_or(anyhow!("Counter overflow"))?,
-                        // Tracks the _first_ time the index was inserted.
-                        position,
-                    },
-                    &copath,
-                    &seed,
-                )
-            }
-            PrefixTreeUpdate::DifferentKey {
-                real,
-                index,
-                seed,
-                old_seed,
-                copath,
-            } => {
-                if !self.is_initialized() {
-                    return Err(anyhow!("Tree not initialized"));
-                }
-
-                // DifferentKey updates always replace a fake node.
-                // The proof is a non-inclusion proof, terminating at the first stand-in hash.
-                let proof = PrefixProof::fake(&index, &copath, &old_seed)?;
-
-                // Check the proof is consistent with the current root.
-                if proof.compute_root() != self.head {
-                    return Err(anyhow!("Old root mismatch"));
-                }
-
-                if real {
-                    PrefixProof::real(
-                        &PrefixLeaf {
-                            index,
-                            counter: 0,
-                            position: self.size,
-                        },
-                        &copath,
-                        &seed,
-                    )
-                } else {
-                    PrefixProof::fake(&index, &copath, &seed)
-                }
-            }
-        };
+    #[cfg(test)]
+    pub(crate) fn apply_update(&mut self, update: PrefixTreeUpdate) -> Result<(), UpdateError> {
+        let roots = update.speculate(self.size)?;
+        self.chain(&roots)
+    }
 
-        self.head = proof?.compute_root();
+    /// Advance the tree to the root of a speculated update, after checking that the
+    /// update's proof is consistent with the current root.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, leaving the tree unchanged, if the update does not apply
+    /// to the current state.
+    pub(crate) fn chain(&mut self, roots: &SpeculativeRoots) -> Result<(), UpdateError> {
+        match roots.old {
+            None if self.is_initialized() => return Err(UpdateError::AlreadyInitialized),
+            Some(_) if !self.is_initialized() => return Err(UpdateError::NotInitialized),
+            // Check the proof is consistent with the current root.
+            Some(old) if old != self.head => return Err(UpdateError::RootMismatch),
+            _ => {}
+        }
+        self.head = roots.new;
         self.size += 1;
-
         Ok(())
     }
 
@@ -267,6 +304,11 @@ impl PrefixTreeCache {
     }
 }
 
+fn copath_node(node: &[u8]) -> Result<Hash, UpdateError> {
+    node.try_into()
+        .map_err(|_| UpdateError::InvalidField(UpdateField::CopathNode))
+}
+
 struct PrefixLeaf {
     index: Index,
     position: u64, // The index of the first log entry in which this leaf appeared.
@@ -310,10 +352,12 @@ impl PrefixProof {
     /// Constructs a proof for a fake insertion.
     /// The insertion replaces a stand-in hash along the direct
     /// path to `index` at height `copath.len()`.
-    fn fake(index: &Index, copath: &[Hash], seed: &Seed) -> Result<Self, anyhow::Error> {
-        let level: u8 = (copath.len() - 1)
-            .try_into()
-            .or(Err(anyhow!("Copath too long")))?;
+    fn fake(index: &Index, copath: &[Hash], seed: &Seed) -> Result<Self, UpdateError> {
+        let level: u8 = copath
+            .len()
+            .checked_sub(1)
+            .and_then(|level| level.try_into().ok())
+            .ok_or(UpdateError::InvalidCopathLength(copath.len()))?;
 
         let value = stand_in_hash(seed, level);
 
@@ -327,9 +371,9 @@ impl PrefixProof {
     /// Constructs a proof for a new leaf insertion.
     /// The copath is generated pseudorandomly at the time of insertion.
     /// using the `seed` parameter.
-    fn real(leaf: &PrefixLeaf, copath: &[Hash], seed: &Seed) -> Result<Self, anyhow::Error> {
+    fn real(leaf: &PrefixLeaf, copath: &[Hash], seed: &Seed) -> Result<Self, UpdateError> {
         if copath.len() > 256 {
-            return Err(anyhow!("Copath too long"));
+            return Err(UpdateError::InvalidCopathLength(copath.len()));
         }
 
         // TODO - use iterators to avoid copying
@@ -420,7 +464,7 @@ mod tests {
         let copath =
             vec![hex!("33819dcecb822883dd9e134325f28ba79d114fe69bb33a09d9755c6507fe22e7").to_vec()];
 
-        let update = AuditorUpdate {
+        let update = PrefixTreeUpdate::try_from(&AuditorUpdate {
             real: true,
             index,
             seed,
@@ -431,8 +475,7 @@ mod tests {
                     old_seed: old_seed.to_vec(),
                 })),
             }),
-        }
-        .try_into()
+        })
         .unwrap();
 
         let mut cache = PrefixTreeCache {
@@ -466,7 +509,7 @@ mod tests {
             hex!("a7d0256b66a95ad4a8f9efed2ee9f060cc50c32336223063c30483dda33f0408").to_vec(),
         ];
 
-        let update = AuditorUpdate {
+        let update = PrefixTreeUpdate::try_from(&AuditorUpdate {
             real: false,
             index,
             seed: seed(2).into(),
@@ -477,8 +520,7 @@ mod tests {
                     old_seed: seed(1).into(),
                 })),
             }),
-        }
-        .try_into()
+        })
         .unwrap();
 
         let mut cache = PrefixTreeCache {
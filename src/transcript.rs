@@ -0,0 +1,373 @@
+//! Signed audit transcripts.
+//!
+//! A transcript is a self-contained record of the auditor verifying a range of
+//! the log: the log state the range starts from, the updates in the range exactly
+//! as the log served them, and the auditor's signed head for the resulting state.
+//! The auditor signs a digest of the whole bundle as a statement, so that a
+//! transcript can be handed to a third party and verified offline with
+//! [`Transcript::verify`], without access to the log or the auditor.
+
+use ed25519_dalek::{SignatureError, VerifyingKey};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+use crate::Hash;
+use crate::auditor::{Auditor, DeploymentMode, PublicConfig, SigningError, VerifyError};
+use crate::proto::transparency::{AuditorTreeHead, AuditorUpdate};
+use crate::transparency::{LogError, TransparencyLog, UpdateError};
+
+/// Domain separation prefix for transcript digests.
+pub const TRANSCRIPT_CONTEXT: &[u8] = b"signal-auditor-transcript-v1\n";
+
+/// A range of updates, with the state it starts from and the auditor's signed
+/// head for the state it ends at.
+///
+/// Byte fields are hex-encoded when serialized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transcript {
+    /// The deployment mode the head was signed for
+    pub mode: u8,
+    /// The log operator's signing public key
+    #[serde(with = "hex::serde")]
+    pub sig_key: [u8; 32],
+    /// The log operator's VRF public key
+    #[serde(with = "hex::serde")]
+    pub vrf_key: [u8; 32],
+    /// The auditor's signing public key
+    #[serde(with = "hex::serde")]
+    pub auditor_key: [u8; 32],
+    /// Log position of the first update
+    pub start: u64,
+    /// Canonical encoding of the log before the first update
+    #[serde(with = "hex::serde")]
+    pub checkpoint: Vec<u8>,
+    /// Encoded `AuditorUpdate`s, exactly as served by the log
+    pub updates: Vec<EncodedUpdate>,
+    /// The auditor's signed head for the log after the last update
+    pub head: TranscriptHead,
+    /// The auditor's statement signature over [`Transcript::digest`]
+    #[serde(with = "hex::serde")]
+    pub signature: Vec<u8>,
+}
+
+/// An encoded `AuditorUpdate`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EncodedUpdate(#[serde(with = "hex::serde")] pub Vec<u8>);
+
+/// A signed tree head together with the log root it covers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscriptHead {
+    pub tree_size: u64,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: i64,
+    #[serde(with = "hex::serde")]
+    pub root: Hash,
+    #[serde(with = "hex::serde")]
+    pub signature: Vec<u8>,
+}
+
+/// An encoded update that could not be applied to the log.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ReplayError {
+    /// The update is not a valid `AuditorUpdate` encoding
+    MalformedUpdate {
+        position: u64,
+        error: prost::DecodeError,
+    },
+    /// The update failed verification
+    RejectedUpdate { position: u64, error: UpdateError },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::MalformedUpdate { position, .. } => {
+                write!(f, "Malformed update at position {position}")
+            }
+            ReplayError::RejectedUpdate { position, .. } => {
+                write!(f, "Update at position {position} failed verification")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReplayError::MalformedUpdate { error, .. } => Some(error),
+            ReplayError::RejectedUpdate { error, .. } => Some(error),
+        }
+    }
+}
+
+/// The reason a transcript could not be signed or verified.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TranscriptError {
+    /// The transcript has no updates
+    Empty,
+    /// The transcript is signed by a different auditor key
+    UnexpectedAuditorKey([u8; 32]),
+    /// The deployment mode is not known to this version
+    UnknownDeploymentMode(u8),
+    /// The log operator's signing key is not a valid Ed25519 key
+    InvalidSigKey(SignatureError),
+    /// The log operator's VRF key is not a valid Ed25519 key
+    InvalidVrfKey(SignatureError),
+    /// The auditor's signature over the transcript does not verify
+    InvalidSignature(VerifyError),
+    /// The checkpoint is not a valid canonical log encoding
+    MalformedCheckpoint(LogError),
+    /// The checkpoint size differs from the start of the transcript
+    CheckpointSize { checkpoint: u64, start: u64 },
+    /// An update could not be applied
+    Replay(ReplayError),
+    /// The log after the updates has no root
+    Log(LogError),
+    /// The updates lead to a different head than the signed one
+    HeadMismatch {
+        size: u64,
+        root: Hash,
+        head_size: u64,
+        head_root: Hash,
+    },
+    /// The auditor's signature on the head does not verify
+    InvalidHead(VerifyError),
+    /// The auditor key failed to sign
+    Signing(SigningError),
+}
+
+impl fmt::Display for TranscriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscriptError::Empty => write!(f, "Transcript has no updates"),
+            TranscriptError::UnexpectedAuditorKey(key) => write!(
+                f,
+                "Transcript is signed by auditor key {}, not the expected key",
+                hex::encode(key)
+            ),
+            TranscriptError::UnknownDeploymentMode(mode) => {
+                write!(f, "Unknown deployment mode {mode}")
+            }
+            TranscriptError::InvalidSigKey(_) => write!(f, "Invalid log signing key"),
+            TranscriptError::InvalidVrfKey(_) => write!(f, "Invalid log VRF key"),
+            TranscriptError::InvalidSignature(_) => write!(f, "Transcript signature is invalid"),
+            TranscriptError::MalformedCheckpoint(_) => write!(f, "Malformed checkpoint"),
+            TranscriptError::CheckpointSize { checkpoint, start } => write!(
+                f,
+                "Checkpoint has size {checkpoint}, but the transcript starts at {start}"
+            ),
+            TranscriptError::Replay(error) => write!(f, "{error}"),
+            TranscriptError::Log(error) => write!(f, "{error}"),
+            TranscriptError::HeadMismatch {
+                size,
+                root,
+                head_size,
+                head_root,
+            } => write!(
+                f,
+                "Updates lead to root {} at size {size}, but the head is for root {} at size {head_size}",
+                hex::encode(root),
+                hex::encode(head_root)
+            ),
+            TranscriptError::InvalidHead(error) => write!(f, "{error}"),
+            TranscriptError::Signing(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for TranscriptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TranscriptError::InvalidSigKey(error) | TranscriptError::InvalidVrfKey(error) => {
+                Some(error)
+            }
+            TranscriptError::InvalidSignature(error) => Some(error),
+            TranscriptError::MalformedCheckpoint(error) => Some(error),
+            TranscriptError::Replay(error) => error.source(),
+            TranscriptError::Log(error) => error.source(),
+            TranscriptError::InvalidHead(error) => error.source(),
+            TranscriptError::Signing(error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<ReplayError> for TranscriptError {
+    fn from(error: ReplayError) -> Self {
+        TranscriptError::Replay(error)
+    }
+}
+
+impl From<SigningError> for TranscriptError {
+    fn from(error: SigningError) -> Self {
+        TranscriptError::Signing(error)
+    }
+}
+
+impl Transcript {
+    /// Verify `updates` from `checkpoint`, then sign the resulting head and the
+    /// transcript with the auditor key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an update is malformed or fails verification, or if
+    /// signing fails.
+    pub async fn sign(
+        auditor: &Auditor,
+        checkpoint: &TransparencyLog,
+        updates: Vec<Vec<u8>>,
+    ) -> Result<Self, TranscriptError> {
+        let updates: Vec<_> = updates.into_iter().map(EncodedUpdate).collect();
+        let log = replay(checkpoint, &updates)?;
+        let root = log.log_root().map_err(TranscriptError::Log)?;
+        let head = auditor.sign_head(root, log.size()).await?;
+
+        let config = &auditor.config;
+        let mut transcript = Self {
+            mode: config.mode.into(),
+            sig_key: config.sig_key.to_bytes(),
+            vrf_key: config.vrf_key.to_bytes(),
+            auditor_key: config.auditor_key.to_bytes(),
+            start: checkpoint.size(),
+            checkpoint: checkpoint.to_canonical_bytes(),
+            updates,
+            head: TranscriptHead {
+                tree_size: head.tree_size,
+                timestamp: head.timestamp,
+                root,
+                signature: head.signature,
+            },
+            signature: vec![],
+        };
+        transcript.signature = auditor.sign_statement(&transcript.digest()).await?;
+        Ok(transcript)
+    }
+
+    /// The digest signed by the auditor, covering every field but the signature.
+    ///
+    /// This is SHA-256 over [`TRANSCRIPT_CONTEXT`] followed by a fixed-width,
+    /// big-endian encoding of the fields in order, with each variable-length
+    /// field prefixed by its length as a `u64`.
+    pub fn digest(&self) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update(TRANSCRIPT_CONTEXT);
+        hasher.update([self.mode]);
+        hasher.update(self.sig_key);
+        hasher.update(self.vrf_key);
+        hasher.update(self.auditor_key);
+        hasher.update(self.start.to_be_bytes());
+        hash_bytes(&mut hasher, &self.checkpoint);
+        hasher.update((self.updates.len() as u64).to_be_bytes());
+        for update in &self.updates {
+            hash_bytes(&mut hasher, &update.0);
+        }
+        hasher.update(self.head.tree_size.to_be_bytes());
+        hasher.update(self.head.timestamp.to_be_bytes());
+        hasher.update(self.head.root);
+        hash_bytes(&mut hasher, &self.head.signature);
+        hasher.finalize().into()
+    }
+
+    /// Verify the transcript against a trusted auditor key, returning the log
+    /// after the last update.
+    ///
+    /// This checks the auditor's signature over the transcript, replays the
+    /// updates from the checkpoint, checks that they lead to the signed head,
+    /// and checks the auditor's signature on the head.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transcript was signed by a different key, or if
+    /// any of the checks fail.
+    pub fn verify(&self, auditor_key: &VerifyingKey) -> Result<TransparencyLog, TranscriptError> {
+        if self.auditor_key != auditor_key.to_bytes() {
+            return Err(TranscriptError::UnexpectedAuditorKey(self.auditor_key));
+        }
+        let config = PublicConfig {
+            mode: DeploymentMode::try_from(self.mode)
+                .map_err(TranscriptError::UnknownDeploymentMode)?,
+            sig_key: VerifyingKey::from_bytes(&self.sig_key)
+                .map_err(TranscriptError::InvalidSigKey)?,
+            vrf_key: VerifyingKey::from_bytes(&self.vrf_key)
+                .map_err(TranscriptError::InvalidVrfKey)?,
+            auditor_key: *auditor_key,
+        };
+        config
+            .verify_statement(&self.digest(), &self.signature)
+            .map_err(TranscriptError::InvalidSignature)?;
+
+        let checkpoint = TransparencyLog::from_canonical_bytes(&self.checkpoint)
+            .map_err(TranscriptError::MalformedCheckpoint)?;
+        if checkpoint.size() != self.start {
+            return Err(TranscriptError::CheckpointSize {
+                checkpoint: checkpoint.size(),
+                start: self.start,
+            });
+        }
+        let log = replay(&checkpoint, &self.updates)?;
+        let root = log.log_root().map_err(TranscriptError::Log)?;
+        if log.size() != self.head.tree_size || root != self.head.root {
+            return Err(TranscriptError::HeadMismatch {
+                size: log.size(),
+                root,
+                head_size: self.head.tree_size,
+                head_root: self.head.root,
+            });
+        }
+        config
+            .verify_head(
+                root,
+                &AuditorTreeHead {
+                    tree_size: self.head.tree_size,
+                    timestamp: self.head.timestamp,
+                    signature: self.head.signature.clone(),
+                },
+            )
+            .map_err(TranscriptError::InvalidHead)?;
+        Ok(log)
+    }
+}
+
+/// Hash a variable-length field, prefixed by its length.
+fn hash_bytes(hasher: &mut Sha256, bytes: &[u8]) {
+    hasher.update((bytes.len() as u64).to_be_bytes());
+    hasher.update(bytes);
+}
+
+/// Apply encoded updates to a copy of `checkpoint`.
+fn replay(
+    checkpoint: &TransparencyLog,
+    updates: &[EncodedUpdate],
+) -> Result<TransparencyLog, TranscriptError> {
+    if updates.is_empty() {
+        return Err(TranscriptError::Empty);
+    }
+    Ok(apply_encoded(checkpoint, updates, 1)?)
+}
+
+/// Decode `updates` and verify them against `log` on `threads` threads,
+/// returning the log with every update applied.
+pub(crate) fn apply_encoded(
+    log: &TransparencyLog,
+    updates: &[EncodedUpdate],
+    threads: usize,
+) -> Result<TransparencyLog, ReplayError> {
+    let start = log.size();
+    let decoded = (start..)
+        .zip(updates)
+        .map(|(position, update)| {
+            AuditorUpdate::decode(update.0.as_slice())
+                .map_err(|error| ReplayError::MalformedUpdate { position, error })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    log.verify_batch_parallel(&decoded, threads)
+        .map_err(|e| ReplayError::RejectedUpdate {
+            position: start + e.index as u64,
+            error: e.error,
+        })
+}
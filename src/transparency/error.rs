@@ -0,0 +1,144 @@
+//! Errors from applying and verifying updates to the transparency log, and from
+//! checking and decoding its state.
+
+use std::fmt;
+
+/// A field of an `AuditorUpdate` that must have a fixed length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateField {
+    Index,
+    Seed,
+    OldSeed,
+    Commitment,
+    CopathNode,
+}
+
+impl fmt::Display for UpdateField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UpdateField::Index => "index",
+            UpdateField::Seed => "seed",
+            UpdateField::OldSeed => "old seed",
+            UpdateField::Commitment => "commitment",
+            UpdateField::CopathNode => "copath node",
+        })
+    }
+}
+
+/// The reason an update was rejected by [`TransparencyLog::apply_update`].
+///
+/// A rejected update leaves the log unchanged.
+///
+/// [`TransparencyLog::apply_update`]: super::TransparencyLog::apply_update
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UpdateError {
+    /// The update has no proof, or a proof variant this version does not know
+    MissingProof,
+    /// A field has the wrong length
+    InvalidField(UpdateField),
+    /// The copath is empty or longer than the tree height
+    InvalidCopathLength(usize),
+    /// A fake update carries a proof that only real updates may use
+    FakeUpdate,
+    /// A new tree proof was given for an initialized tree
+    AlreadyInitialized,
+    /// An update proof was given before the tree was initialized
+    NotInitialized,
+    /// The proof does not lead to the current prefix tree root
+    RootMismatch,
+    /// A key's version counter would overflow
+    CounterOverflow,
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::MissingProof => write!(f, "Missing proof"),
+            UpdateError::InvalidField(field) => write!(f, "Invalid {field}"),
+            UpdateError::InvalidCopathLength(len) => write!(f, "Invalid copath length {len}"),
+            UpdateError::FakeUpdate => write!(f, "Fake update"),
+            UpdateError::AlreadyInitialized => write!(f, "Tree already initialized"),
+            UpdateError::NotInitialized => write!(f, "Tree not initialized"),
+            UpdateError::RootMismatch => write!(f, "Old root mismatch"),
+            UpdateError::CounterOverflow => write!(f, "Counter overflow"),
+        }
+    }
+}
+
+impl std::error::Error for UpdateError {}
+
+/// The first update rejected by [`TransparencyLog::verify_batch`].
+///
+/// [`TransparencyLog::verify_batch`]: super::TransparencyLog::verify_batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchError {
+    /// Offset of the rejected update in the batch
+    pub index: usize,
+    pub error: UpdateError,
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Update {} of the batch was rejected", self.index)
+    }
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The reason a log state is unusable, from [`TransparencyLog::log_root`],
+/// [`TransparencyLog::verify_consistency`] or [`TransparencyLog::from_canonical_bytes`].
+///
+/// [`TransparencyLog::log_root`]: super::TransparencyLog::log_root
+/// [`TransparencyLog::verify_consistency`]: super::TransparencyLog::verify_consistency
+/// [`TransparencyLog::from_canonical_bytes`]: super::TransparencyLog::from_canonical_bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LogError {
+    /// No update has been applied to the log
+    NotInitialized,
+    /// The log tree has no leaves
+    EmptyTree,
+    /// A cached log subtree does not have a power of two size
+    IncompleteSubtree { subtree: usize, size: u64 },
+    /// A cached log subtree is not smaller than its left neighbour
+    UnorderedSubtree { subtree: usize, size: u64 },
+    /// The log tree and prefix tree disagree on the size of the log
+    SizeMismatch { log: u64, prefix: u64 },
+    /// The canonical encoding ends early
+    Truncated,
+    /// The canonical encoding has bytes after the log
+    TrailingBytes,
+    /// The canonical encoding of an empty log has a non-zero prefix head
+    NonZeroEmptyHead,
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogError::NotInitialized => write!(f, "Log is not initialized"),
+            LogError::EmptyTree => write!(f, "Log tree is empty"),
+            LogError::IncompleteSubtree { subtree, size } => write!(
+                f,
+                "Subtree {subtree} has size {size}, which is not a power of two"
+            ),
+            LogError::UnorderedSubtree { subtree, size } => write!(
+                f,
+                "Subtree {subtree} has size {size}, which is not smaller than its left neighbour"
+            ),
+            LogError::SizeMismatch { log, prefix } => write!(
+                f,
+                "Log tree has {log} leaves but prefix tree has size {prefix}"
+            ),
+            LogError::Truncated => write!(f, "Truncated log encoding"),
+            LogError::TrailingBytes => write!(f, "Trailing bytes after log encoding"),
+            LogError::NonZeroEmptyHead => write!(f, "Empty log has a non-zero prefix head"),
+        }
+    }
+}
+
+impl std::error::Error for LogError {}
@@ -6,20 +6,30 @@
 //! that tracks key versions, and `commitment` is the
 //! commitment to the public key.
 
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::mem;
 
 use crate::log::LogTreeCache;
-use crate::prefix::PrefixTreeCache;
+use crate::prefix::{PrefixTreeCache, PrefixTreeUpdate, SpeculativeRoots};
+use crate::proto::transparency::AuditorUpdate;
 
-use crate::{Hash, try_into_hash};
+use crate::Hash;
+
+mod error;
+pub use error::{BatchError, LogError, UpdateError, UpdateField};
+
+mod stats;
+use stats::UpdateKind;
+pub use stats::UpdateStats;
 
 // TODO - this is serializing byte vecs as arrays of ints, which is not optimal
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransparencyLog {
     log_cache: LogTreeCache,
     prefix_cache: PrefixTreeCache,
+    #[serde(default)]
+    stats: UpdateStats,
 }
 
 impl Default for TransparencyLog {
@@ -33,6 +43,7 @@ impl TransparencyLog {
         Self {
             log_cache: LogTreeCache::new(),
             prefix_cache: PrefixTreeCache::new(),
+            stats: UpdateStats::default(),
         }
     }
 
@@ -44,37 +55,525 @@ impl TransparencyLog {
         self.size() > 0
     }
 
-    pub fn apply_update(
-        &mut self,
-        mut update: crate::proto::transparency::AuditorUpdate,
-    ) -> Result<(), anyhow::Error> {
-        // Take the commitment out of the update, this is not used by the prefix tree.
-        let commitment = try_into_hash(mem::take(&mut update.commitment))?;
+    /// Verify an update against the current state and apply it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the update is malformed or inconsistent with the
+    /// current state, in which case the log is unchanged.
+    pub fn apply_update(&mut self, update: AuditorUpdate) -> Result<(), UpdateError> {
+        let update = SpeculativeUpdate::new(&update, self.size())?;
+        self.chain(update)
+    }
+
+    /// Verify a batch of updates without modifying the log.
+    ///
+    /// Returns the log with every update applied, so that callers can advance
+    /// to it only once the whole batch is known to be valid.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first rejected update's offset in the batch and the reason.
+    pub fn verify_batch(&self, updates: &[AuditorUpdate]) -> Result<Self, BatchError> {
+        self.verify_batch_parallel(updates, 1)
+    }
+
+    /// Verify a batch of updates without modifying the log, split into up to
+    /// `threads` slices.
+    ///
+    /// The prefix tree roots before and after each update only depend on the
+    /// update itself, so they are computed for slices of the batch in parallel
+    /// on the current rayon thread pool, whose size bounds the parallelism.
+    /// A final sequential pass checks that each update's old root is the previous
+    /// update's new root, and appends the results to the log tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first rejected update's offset in the batch and the reason,
+    /// as for [`TransparencyLog::verify_batch`].
+    pub fn verify_batch_parallel(
+        &self,
+        updates: &[AuditorUpdate],
+        threads: usize,
+    ) -> Result<Self, BatchError> {
+        self.verify_batch_observed(updates, threads, |_| {})
+    }
+
+    /// Verify a batch of updates as [`TransparencyLog::verify_batch_parallel`]
+    /// does, calling `on_update` with the log after each update is applied.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first rejected update's offset in the batch and the reason.
+    /// `on_update` has been called for each update before it.
+    pub fn verify_batch_observed(
+        &self,
+        updates: &[AuditorUpdate],
+        threads: usize,
+        mut on_update: impl FnMut(&Self),
+    ) -> Result<Self, BatchError> {
+        let start = self.size();
+        let slice_len = updates.len().div_ceil(threads.max(1)).max(1);
+        let speculated: Vec<_> = if slice_len >= updates.len() {
+            speculate_slice(updates, start)
+        } else {
+            // A panic in a slice is resumed on this thread
+            let slices: Vec<_> = updates
+                .par_chunks(slice_len)
+                .enumerate()
+                .map(|(i, slice)| speculate_slice(slice, start + (i * slice_len) as u64))
+                .collect();
+            slices.into_iter().flatten().collect()
+        };
+
+        let mut log = self.clone();
+        for (index, update) in speculated.into_iter().enumerate() {
+            update
+                .and_then(|update| log.chain(update))
+                .map_err(|error| BatchError { index, error })?;
+            on_update(&log);
+        }
+        Ok(log)
+    }
 
-        // Consumes the update to avoid copying copaths
-        self.prefix_cache.apply_update(update.try_into()?)?;
+    /// Apply a speculated update if it follows from the current prefix tree root.
+    fn chain(&mut self, update: SpeculativeUpdate) -> Result<(), UpdateError> {
+        self.prefix_cache.chain(&update.roots)?;
         let prefix_root = self
             .prefix_cache
             .root()
-            .ok_or(anyhow::anyhow!("Prefix tree not initialized"))?;
-        let leaf = log_leaf(prefix_root, commitment);
+            .ok_or(UpdateError::NotInitialized)?;
+        let leaf = log_leaf(prefix_root, update.commitment);
         self.log_cache.insert(&leaf);
+        if let Some(kind) = update.kind {
+            self.stats.record(kind);
+        }
+        Ok(())
+    }
+
+    /// Check that the log tree and prefix tree caches agree with each other.
+    ///
+    /// This is intended for validating state loaded from storage, where the
+    /// cached frontier is not otherwise checked before use.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log frontier is malformed, if the number of
+    /// log leaves differs from the prefix tree size, or if the log root
+    /// cannot be computed.
+    pub fn verify_consistency(&self) -> Result<(), LogError> {
+        self.log_cache.check_frontier()?;
+        if self.log_cache.size() != self.prefix_cache.size {
+            return Err(LogError::SizeMismatch {
+                log: self.log_cache.size(),
+                prefix: self.prefix_cache.size,
+            });
+        }
+        if self.is_initialized() {
+            self.log_root()?;
+        }
         Ok(())
     }
 
-    pub fn log_root(&self) -> Result<Hash, anyhow::Error> {
+    /// Encode the log in its canonical binary form.
+    ///
+    /// Update statistics are not included.
+    ///
+    /// The encoding is fixed-width and big-endian, so equal logs always
+    /// encode to identical bytes:
+    ///
+    /// ```text
+    /// u64      prefix tree size
+    /// [u8; 32] prefix tree head
+    /// u8       number of log subtrees n
+    /// n * (u64 subtree size, [u8; 32] subtree root), from left to right
+    /// ```
+    pub fn to_canonical_bytes(&self) -> Vec<u8> {
+        let nodes: Vec<_> = self.log_cache.nodes().collect();
+        let mut out = Vec::with_capacity(41 + 40 * nodes.len());
+        out.extend_from_slice(&self.prefix_cache.size.to_be_bytes());
+        out.extend_from_slice(&self.prefix_cache.head);
+        // A log with fewer than 2^64 leaves has at most 64 subtrees
+        out.push(nodes.len() as u8);
+        for (size, root) in nodes {
+            out.extend_from_slice(&size.to_be_bytes());
+            out.extend_from_slice(root);
+        }
+        out
+    }
+
+    /// Decode a log from its canonical binary form.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input is truncated, has trailing bytes, is not
+    /// the canonical encoding of its log, or fails [`TransparencyLog::verify_consistency`].
+    pub fn from_canonical_bytes(mut buf: &[u8]) -> Result<Self, LogError> {
+        let size = u64::from_be_bytes(take(&mut buf)?);
+        let head: Hash = take(&mut buf)?;
+        let [count] = take(&mut buf)?;
+        let mut nodes = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let node_size = u64::from_be_bytes(take(&mut buf)?);
+            let root: Hash = take(&mut buf)?;
+            nodes.push((node_size, root));
+        }
+        if !buf.is_empty() {
+            return Err(LogError::TrailingBytes);
+        }
+        // An empty prefix tree has no meaningful head, so only zero is canonical
+        if size == 0 && head != Hash::default() {
+            return Err(LogError::NonZeroEmptyHead);
+        }
+
+        let log = Self {
+            log_cache: LogTreeCache::from_nodes(nodes),
+            prefix_cache: PrefixTreeCache { head, size },
+            stats: UpdateStats::default(),
+        };
+        log.verify_consistency()?;
+        Ok(log)
+    }
+
+    /// Statistics about the updates applied to this log.
+    ///
+    /// Statistics are only as complete as the history of this value: they are not
+    /// part of the canonical encoding, so they must be restored separately.
+    pub fn stats(&self) -> &UpdateStats {
+        &self.stats
+    }
+
+    /// Restore statistics persisted alongside the canonical encoding.
+    pub fn set_stats(&mut self, stats: UpdateStats) {
+        self.stats = stats;
+    }
+
+    /// The root of the prefix tree, if the log is initialized.
+    ///
+    /// This is the prefix root committed to by the most recent log entry.
+    pub fn prefix_root(&self) -> Option<Hash> {
+        self.prefix_cache.root()
+    }
+
+    /// The root of the log tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log is not initialized.
+    pub fn log_root(&self) -> Result<Hash, LogError> {
         if !self.is_initialized() {
-            return Err(anyhow::anyhow!("Log is not initialized"));
+            return Err(LogError::NotInitialized);
         }
-        self.log_cache
-            .root()
-            .ok_or(anyhow::anyhow!("Log tree is empty"))
+        self.log_cache.root().ok_or(LogError::EmptyTree)
     }
 }
 
+/// An update with its prefix tree roots computed, ready to be chained onto a log.
+struct SpeculativeUpdate {
+    kind: Option<UpdateKind>,
+    commitment: Hash,
+    roots: SpeculativeRoots,
+}
+
+impl SpeculativeUpdate {
+    /// Compute the roots of `update`, assuming it is applied to a log of size `size`.
+    fn new(update: &AuditorUpdate, size: u64) -> Result<Self, UpdateError> {
+        let kind = UpdateKind::of(update);
+
+        // The commitment is not used by the prefix tree.
+        let commitment: Hash = update
+            .commitment
+            .as_slice()
+            .try_into()
+            .map_err(|_| UpdateError::InvalidField(UpdateField::Commitment))?;
+
+        let roots = PrefixTreeUpdate::try_from(update)?.speculate(size)?;
+        Ok(Self {
+            kind,
+            commitment,
+            roots,
+        })
+    }
+}
+
+/// Speculate the updates of a slice starting at log position `start`.
+fn speculate_slice(
+    updates: &[AuditorUpdate],
+    start: u64,
+) -> Vec<Result<SpeculativeUpdate, UpdateError>> {
+    (start..)
+        .zip(updates)
+        .map(|(size, update)| SpeculativeUpdate::new(update, size))
+        .collect()
+}
+
+/// Split `N` bytes off the front of `buf`.
+fn take<const N: usize>(buf: &mut &[u8]) -> Result<[u8; N], LogError> {
+    let (head, rest) = buf.split_first_chunk::<N>().ok_or(LogError::Truncated)?;
+    *buf = rest;
+    Ok(*head)
+}
+
 fn log_leaf(prefix_root: Hash, commitment: Hash) -> Hash {
     let mut hasher = Sha256::new();
     hasher.update(prefix_root);
     hasher.update(commitment);
     hasher.finalize().into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::Aes128;
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    use hex_literal::hex;
+
+    use crate::proto::transparency::auditor_proof::{DifferentKey, NewTree, Proof, SameKey};
+    use crate::proto::transparency::{AuditorProof, AuditorUpdate};
+
+    /// The canonical encoding of the log built by `golden_log`.
+    const GOLDEN: &str = include_str!("../../tests/golden/canonical_head.hex");
+
+    fn seed(position: u64) -> Vec<u8> {
+        let mut buffer = [0u8; 16];
+        buffer[8..].copy_from_slice(&position.to_be_bytes());
+        let aes = Aes128::new(&[0u8; 16].into());
+        let mut block = buffer.into();
+        aes.encrypt_block(&mut block);
+        block.to_vec()
+    }
+
+    fn update(real: bool, index_byte: u8, position: u64, proof: Proof) -> AuditorUpdate {
+        let mut index = vec![0u8; 32];
+        index[0] = index_byte;
+        AuditorUpdate {
+            real,
+            index,
+            seed: seed(position),
+            commitment: vec![0u8; 32],
+            proof: Some(AuditorProof { proof: Some(proof) }),
+        }
+    }
+
+    /// A new tree, a real insertion and a fake insertion,
+    /// matching the prefix tree unit tests.
+    fn golden_updates() -> Vec<AuditorUpdate> {
+        vec![
+            update(true, 0x00, 0, Proof::NewTree(NewTree {})),
+            update(
+                true,
+                0x80,
+                1,
+                Proof::DifferentKey(DifferentKey {
+                    copath: vec![
+                        hex!("33819dcecb822883dd9e134325f28ba79d114fe69bb33a09d9755c6507fe22e7")
+                            .to_vec(),
+                    ],
+                    old_seed: seed(0),
+                }),
+            ),
+            update(
+                false,
+                0xc0,
+                2,
+                Proof::DifferentKey(DifferentKey {
+                    copath: vec![
+                        hex!("33819dcecb822883dd9e134325f28ba79d114fe69bb33a09d9755c6507fe22e7")
+                            .to_vec(),
+                        hex!("a7d0256b66a95ad4a8f9efed2ee9f060cc50c32336223063c30483dda33f0408")
+                            .to_vec(),
+                    ],
+                    old_seed: seed(1),
+                }),
+            ),
+        ]
+    }
+
+    /// The log built from `golden_updates`.
+    fn golden_log() -> TransparencyLog {
+        let mut log = TransparencyLog::new();
+        for update in golden_updates() {
+            log.apply_update(update).unwrap();
+        }
+        log
+    }
+
+    #[test]
+    fn test_canonical_golden() {
+        let golden = hex::decode(GOLDEN.trim()).unwrap();
+        let log = golden_log();
+        assert_eq!(log.to_canonical_bytes(), golden);
+
+        let decoded = TransparencyLog::from_canonical_bytes(&golden).unwrap();
+        assert_eq!(decoded.to_canonical_bytes(), golden);
+        assert_eq!(
+            decoded.log_root().unwrap(),
+            hex!("24187367e08f3607d8a112fcfb33e789f6a65cb5ba1aa47b11553deb39306cff")
+        );
+    }
+
+    #[test]
+    fn test_rejected_update() {
+        let mut log = golden_log();
+        let golden = log.to_canonical_bytes();
+
+        let result = log.apply_update(update(true, 0x00, 3, Proof::NewTree(NewTree {})));
+        assert_eq!(result, Err(UpdateError::AlreadyInitialized));
+
+        let mut short_commitment = update(true, 0x00, 3, Proof::NewTree(NewTree {}));
+        short_commitment.commitment.pop();
+        assert_eq!(
+            log.apply_update(short_commitment),
+            Err(UpdateError::InvalidField(UpdateField::Commitment))
+        );
+
+        let fake_same_key = update(
+            false,
+            0x00,
+            3,
+            Proof::SameKey(SameKey {
+                copath: vec![],
+                counter: 0,
+                position: 0,
+            }),
+        );
+        assert_eq!(
+            log.apply_update(fake_same_key),
+            Err(UpdateError::FakeUpdate)
+        );
+
+        let empty_copath = update(
+            false,
+            0x40,
+            3,
+            Proof::DifferentKey(DifferentKey {
+                copath: vec![],
+                old_seed: seed(2),
+            }),
+        );
+        assert_eq!(
+            log.apply_update(empty_copath),
+            Err(UpdateError::InvalidCopathLength(0))
+        );
+
+        assert_eq!(log.to_canonical_bytes(), golden);
+    }
+
+    #[test]
+    fn test_verify_batch() {
+        let golden = hex::decode(GOLDEN.trim()).unwrap();
+        let empty = TransparencyLog::new();
+        let verified = empty.verify_batch(&golden_updates()).unwrap();
+        assert_eq!(verified.to_canonical_bytes(), golden);
+        assert!(!empty.is_initialized());
+
+        // A failure part way through leaves the log unchanged
+        let mut updates = golden_updates();
+        updates[2].commitment.pop();
+        let partial = empty.verify_batch(&updates[..1]).unwrap();
+        let error = partial.verify_batch(&updates[1..]).unwrap_err();
+        assert_eq!(error.index, 1);
+        assert_eq!(
+            error.error,
+            UpdateError::InvalidField(UpdateField::Commitment)
+        );
+        assert_eq!(partial.size(), 1);
+    }
+
+    #[test]
+    fn test_verify_batch_parallel() {
+        let golden = hex::decode(GOLDEN.trim()).unwrap();
+        let empty = TransparencyLog::new();
+        for threads in 0..5 {
+            let verified = empty
+                .verify_batch_parallel(&golden_updates(), threads)
+                .unwrap();
+            assert_eq!(verified.to_canonical_bytes(), golden);
+            assert_eq!(verified.stats(), golden_log().stats());
+        }
+
+        // Speculated roots that do not chain are rejected in order
+        let mut updates = golden_updates();
+        updates.swap(1, 2);
+        let error = empty.verify_batch_parallel(&updates, 3).unwrap_err();
+        assert_eq!(error.index, 1);
+        assert_eq!(error.error, UpdateError::RootMismatch);
+
+        let mut updates = golden_updates();
+        updates[2].commitment.pop();
+        let error = empty.verify_batch_parallel(&updates, 3).unwrap_err();
+        assert_eq!(error.index, 2);
+        assert_eq!(
+            error.error,
+            UpdateError::InvalidField(UpdateField::Commitment)
+        );
+    }
+
+    #[test]
+    fn test_verify_batch_observed() {
+        let mut expected = vec![];
+        let mut log = TransparencyLog::new();
+        assert_eq!(log.prefix_root(), None);
+        for update in golden_updates() {
+            log.apply_update(update).unwrap();
+            expected.push(log.prefix_root().unwrap());
+        }
+
+        let mut observed = vec![];
+        TransparencyLog::new()
+            .verify_batch_observed(&golden_updates(), 2, |log| {
+                observed.extend(log.prefix_root());
+            })
+            .unwrap();
+        assert_eq!(observed, expected);
+    }
+
+    #[test]
+    fn test_update_stats() {
+        let stats = golden_log().stats().clone();
+        assert_eq!(stats.new_tree, 1);
+        assert_eq!(stats.different_key_real, 1);
+        assert_eq!(stats.different_key_fake, 1);
+        assert_eq!(stats.same_key, 0);
+        assert_eq!(
+            stats.copath_lengths.into_iter().collect::<Vec<_>>(),
+            [(0, 1), (1, 1), (2, 1)]
+        );
+    }
+
+    #[test]
+    fn test_canonical_rejects_malformed() {
+        let golden = hex::decode(GOLDEN.trim()).unwrap();
+
+        let mut trailing = golden.clone();
+        trailing.push(0);
+        assert_eq!(
+            TransparencyLog::from_canonical_bytes(&trailing).unwrap_err(),
+            LogError::TrailingBytes
+        );
+        assert_eq!(
+            TransparencyLog::from_canonical_bytes(&golden[..golden.len() - 1]).unwrap_err(),
+            LogError::Truncated
+        );
+
+        // Subtree sizes that disagree with the prefix tree size
+        let mut wrong_size = golden.clone();
+        wrong_size[7] = 4;
+        assert_eq!(
+            TransparencyLog::from_canonical_bytes(&wrong_size).unwrap_err(),
+            LogError::SizeMismatch { log: 3, prefix: 4 }
+        );
+
+        let mut empty = TransparencyLog::new().to_canonical_bytes();
+        assert!(TransparencyLog::from_canonical_bytes(&empty).is_ok());
+        assert_eq!(
+            TransparencyLog::new().log_root(),
+            Err(LogError::NotInitialized)
+        );
+        empty[8] = 1;
+        assert_eq!(
+            TransparencyLog::from_canonical_bytes(&empty).unwrap_err(),
+            LogError::NonZeroEmptyHead
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Statistics about the updates applied to a transparency log.
+//!
+//! These are not verified state and are not part of the canonical encoding,
+//! but their distributions are useful for performance tuning and for
+//! noticing unusual server behaviour.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::proto::transparency::AuditorUpdate;
+use crate::proto::transparency::auditor_proof::Proof;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateStats {
+    /// Number of updates with each supplied copath length
+    pub copath_lengths: BTreeMap<usize, u64>,
+    pub new_tree: u64,
+    /// `DifferentKey` updates inserting a real leaf
+    pub different_key_real: u64,
+    /// `DifferentKey` updates inserting a fake leaf
+    pub different_key_fake: u64,
+    pub same_key: u64,
+    /// The largest counter seen in a `SameKey` update
+    pub max_counter: u32,
+}
+
+/// The shape of an update, extracted before the update is consumed.
+pub(crate) enum UpdateKind {
+    NewTree,
+    DifferentKey { real: bool, copath_length: usize },
+    SameKey { copath_length: usize, counter: u32 },
+}
+
+impl UpdateKind {
+    pub(crate) fn of(update: &AuditorUpdate) -> Option<Self> {
+        match update.proof.as_ref()?.proof.as_ref()? {
+            Proof::NewTree(_) => Some(Self::NewTree),
+            Proof::DifferentKey(key) => Some(Self::DifferentKey {
+                real: update.real,
+                copath_length: key.copath.len(),
+            }),
+            Proof::SameKey(key) => Some(Self::SameKey {
+                copath_length: key.copath.len(),
+                counter: key.counter,
+            }),
+        }
+    }
+}
+
+impl UpdateStats {
+    /// Record an update that was applied successfully.
+    pub(crate) fn record(&mut self, kind: UpdateKind) {
+        let copath_length = match kind {
+            UpdateKind::NewTree => {
+                self.new_tree += 1;
+                0
+            }
+            UpdateKind::DifferentKey {
+                real,
+                copath_length,
+            } => {
+                if real {
+                    self.different_key_real += 1;
+                } else {
+                    self.different_key_fake += 1;
+                }
+                copath_length
+            }
+            UpdateKind::SameKey {
+                copath_length,
+                counter,
+            } => {
+                self.same_key += 1;
+                self.max_counter = self.max_counter.max(counter);
+                copath_length
+            }
+        };
+        *self.copath_lengths.entry(copath_length).or_default() += 1;
+    }
+
+    /// Total number of updates recorded.
+    pub fn total(&self) -> u64 {
+        self.copath_lengths.values().sum()
+    }
+}
@@ -0,0 +1,242 @@
+//! Inspection of the protobuf wire encoding of auditor updates.
+//!
+//! Decoding silently skips fields that are not in the compiled schema, so an
+//! update from a newer server may carry data that the auditor never verifies.
+//! [`unknown_fields`] walks the encoding against the schema of `AuditorUpdate`
+//! to find such fields, including proof variants added to the `oneof`.
+
+use std::fmt;
+
+/// The kind of a field in a known message.
+enum Field {
+    /// A scalar, bytes or repeated bytes field, which is not inspected further
+    Scalar,
+    /// A nested message
+    Message(&'static Schema),
+}
+
+/// The fields of a known message, by tag.
+struct Schema {
+    name: &'static str,
+    fields: &'static [(u32, Field)],
+}
+
+static NEW_TREE: Schema = Schema {
+    name: "AuditorProof.NewTree",
+    fields: &[],
+};
+
+static DIFFERENT_KEY: Schema = Schema {
+    name: "AuditorProof.DifferentKey",
+    fields: &[(1, Field::Scalar), (2, Field::Scalar)],
+};
+
+static SAME_KEY: Schema = Schema {
+    name: "AuditorProof.SameKey",
+    fields: &[(1, Field::Scalar), (2, Field::Scalar), (3, Field::Scalar)],
+};
+
+static AUDITOR_PROOF: Schema = Schema {
+    name: "AuditorProof",
+    fields: &[
+        (1, Field::Message(&NEW_TREE)),
+        (3, Field::Message(&DIFFERENT_KEY)),
+        (4, Field::Message(&SAME_KEY)),
+    ],
+};
+
+static AUDITOR_UPDATE: Schema = Schema {
+    name: "AuditorUpdate",
+    fields: &[
+        (1, Field::Scalar),
+        (2, Field::Scalar),
+        (3, Field::Scalar),
+        (4, Field::Scalar),
+        (5, Field::Message(&AUDITOR_PROOF)),
+    ],
+};
+
+/// A field that is not part of the known schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownField {
+    /// The name of the message containing the field
+    pub message: &'static str,
+    pub tag: u32,
+}
+
+impl fmt::Display for UnknownField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} field {}", self.message, self.tag)
+    }
+}
+
+/// The reason an encoding could not be inspected by [`unknown_fields`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WireError {
+    /// A varint is longer than ten bytes or ends early
+    MalformedVarint,
+    /// A field tag does not fit in 32 bits
+    TagOutOfRange,
+    /// A field uses a wire type that is not supported, such as a group
+    UnsupportedWireType(u64),
+    /// A field's value ends early
+    Truncated { message: &'static str, tag: u32 },
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::MalformedVarint => write!(f, "Malformed varint"),
+            WireError::TagOutOfRange => write!(f, "Field tag out of range"),
+            WireError::UnsupportedWireType(wire_type) => {
+                write!(f, "Unsupported wire type {wire_type}")
+            }
+            WireError::Truncated { message, tag } => {
+                write!(f, "Truncated field {tag} in {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+/// Find the fields in an encoded `AuditorUpdate` that are not in the known schema.
+///
+/// # Errors
+///
+/// Returns an error if the input is not a well-formed protobuf encoding.
+/// Groups are not supported, since they do not occur in proto3.
+pub fn unknown_fields(encoded: &[u8]) -> Result<Vec<UnknownField>, WireError> {
+    let mut unknown = Vec::new();
+    scan(&AUDITOR_UPDATE, encoded, &mut unknown)?;
+    Ok(unknown)
+}
+
+fn scan(schema: &Schema, mut buf: &[u8], unknown: &mut Vec<UnknownField>) -> Result<(), WireError> {
+    while !buf.is_empty() {
+        let key = varint(&mut buf)?;
+        let tag = u32::try_from(key >> 3).map_err(|_| WireError::TagOutOfRange)?;
+        let field = schema
+            .fields
+            .iter()
+            .find(|(known, _)| *known == tag)
+            .map(|(_, field)| field);
+        if field.is_none() {
+            unknown.push(UnknownField {
+                message: schema.name,
+                tag,
+            });
+        }
+
+        let truncated = WireError::Truncated {
+            message: schema.name,
+            tag,
+        };
+        let len = match key & 0x7 {
+            0 => {
+                varint(&mut buf)?;
+                0
+            }
+            1 => 8,
+            2 => usize::try_from(varint(&mut buf)?).map_err(|_| truncated.clone())?,
+            5 => 4,
+            wire_type => return Err(WireError::UnsupportedWireType(wire_type)),
+        };
+        let (value, rest) = buf.split_at_checked(len).ok_or(truncated)?;
+        if let (Some(Field::Message(inner)), 2) = (field, key & 0x7) {
+            scan(inner, value, unknown)?;
+        }
+        buf = rest;
+    }
+    Ok(())
+}
+
+/// Decode a base 128 varint from the front of `buf`.
+fn varint(buf: &mut &[u8]) -> Result<u64, WireError> {
+    let mut value = 0u64;
+    for (i, byte) in buf.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(WireError::MalformedVarint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::transparency::auditor_proof::{NewTree, Proof, SameKey};
+    use crate::proto::transparency::{AuditorProof, AuditorUpdate};
+    use prost::Message;
+
+    fn update(proof: Proof) -> Vec<u8> {
+        AuditorUpdate {
+            real: true,
+            index: vec![1; 32],
+            seed: vec![2; 16],
+            commitment: vec![3; 32],
+            proof: Some(AuditorProof { proof: Some(proof) }),
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn test_known_fields() {
+        let encoded = update(Proof::SameKey(SameKey {
+            copath: vec![vec![4; 32]; 3],
+            counter: 7,
+            position: 9,
+        }));
+        assert!(unknown_fields(&encoded).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        // A new top-level varint field 6
+        let mut encoded = update(Proof::NewTree(NewTree {}));
+        encoded.extend_from_slice(&[6 << 3, 1]);
+        // A proof with an unrecognized variant 5, holding an empty message
+        encoded.extend_from_slice(&[(5 << 3) | 2, 2, (5 << 3) | 2, 0]);
+
+        assert_eq!(
+            unknown_fields(&encoded).unwrap(),
+            [
+                UnknownField {
+                    message: "AuditorUpdate",
+                    tag: 6
+                },
+                UnknownField {
+                    message: "AuditorProof",
+                    tag: 5
+                },
+            ]
+        );
+
+        // The decoded update ignores both
+        let decoded = AuditorUpdate::decode(encoded.as_slice()).unwrap();
+        assert_eq!(
+            decoded.proof.unwrap().proof,
+            Some(Proof::NewTree(NewTree {}))
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        let encoded = update(Proof::NewTree(NewTree {}));
+        assert_eq!(
+            unknown_fields(&encoded[..encoded.len() - 1]),
+            Err(WireError::Truncated {
+                message: "AuditorUpdate",
+                tag: 5
+            })
+        );
+        assert_eq!(unknown_fields(&[0x80]), Err(WireError::MalformedVarint));
+        assert_eq!(
+            unknown_fields(&[(1 << 3) | 3]),
+            Err(WireError::UnsupportedWireType(3))
+        );
+    }
+}
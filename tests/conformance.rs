@@ -0,0 +1,143 @@
+use lazy_static::lazy_static;
+
+mod test_vectors {
+    include!(concat!(env!("OUT_DIR"), "/test_vectors.rs"));
+}
+
+use ed25519_dalek::{VerifyingKey, pkcs8::DecodePublicKey};
+use prost::Message;
+use signal_auditor::auditor::DeploymentMode;
+use signal_auditor::conformance::{Recorder, Recording};
+use test_vectors::TestVectors;
+
+/// Directory of pinned recordings, each made with `conformance record`.
+const RECORDINGS: &str = "tests/conformance";
+
+lazy_static! {
+    static ref VECTORS: TestVectors = {
+        let pb = std::fs::read("tests/kt_test_vectors.pb").unwrap();
+        TestVectors::decode(pb.as_slice()).unwrap()
+    };
+}
+
+/// Record the successful test vectors in responses of `batch` updates.
+fn record_vectors(batch: usize) -> Recording {
+    let signature = VECTORS.signature.clone().unwrap();
+    let mut recorder = Recorder::new(
+        "kt test vectors".to_string(),
+        DeploymentMode::ThirdPartyAuditing,
+        VerifyingKey::from_public_key_der(signature.sig_pub_key.as_slice()).unwrap(),
+        VerifyingKey::from_public_key_der(signature.vrf_pub_key.as_slice()).unwrap(),
+    );
+    let vectors = VECTORS.should_succeed.clone().unwrap().updates;
+    for (i, chunk) in vectors.chunks(batch).enumerate() {
+        let encoded = chunk
+            .iter()
+            .map(|vector| vector.update.as_ref().unwrap().encode_to_vec())
+            .collect();
+        let head = recorder
+            .push(encoded, 1_700_000_000_000 + i as i64)
+            .unwrap();
+        assert_eq!(head.root.to_vec(), chunk.last().unwrap().log_root);
+    }
+    recorder.finish()
+}
+
+#[test]
+fn test_pinned_recordings() {
+    let mut paths: Vec<_> = std::fs::read_dir(RECORDINGS)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "No recordings in {RECORDINGS}");
+    for path in paths {
+        let recording: Recording = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        for threads in [1, 4] {
+            if let Err(e) = recording.check(threads) {
+                panic!("{} failed with {threads} threads: {e:#}", path.display());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_record_and_check() {
+    let recording = record_vectors(7);
+    let log = recording.check(1).unwrap();
+    assert_eq!(
+        log.size(),
+        VECTORS.should_succeed.as_ref().unwrap().updates.len() as u64
+    );
+    assert_eq!(
+        recording.check(4).unwrap().log_root().unwrap(),
+        log.log_root().unwrap()
+    );
+
+    // Recordings survive serialization
+    let json = serde_json::to_string(&recording).unwrap();
+    let decoded: Recording = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, recording);
+
+    // Recording is deterministic
+    assert_eq!(record_vectors(7), recording);
+}
+
+#[test]
+fn test_check_mismatch() {
+    let recording = record_vectors(7);
+    let last = recording.responses.len() - 1;
+
+    let mut root = recording.clone();
+    root.responses[last].head.root[0] ^= 1;
+    assert!(root.check(1).is_err());
+
+    let mut signature = recording.clone();
+    signature.responses[0].head.signature[0] ^= 1;
+    assert!(signature.check(1).is_err());
+
+    let mut timestamp = recording.clone();
+    timestamp.responses[0].head.timestamp += 1;
+    assert!(timestamp.check(1).is_err());
+
+    let mut size = recording.clone();
+    size.responses[last].head.tree_size += 1;
+    assert!(size.check(1).is_err());
+
+    let mut update = recording.clone();
+    update.responses[0].updates.swap(0, 1);
+    assert!(update.check(1).is_err());
+
+    let mut missing = recording;
+    missing.responses.remove(0);
+    assert!(missing.check(1).is_err());
+}
+
+/// Golden heads are signed exactly as the auditor signs heads.
+#[cfg(not(feature = "gcloud-kms"))]
+#[test]
+fn test_golden_signatures() {
+    use signal_auditor::auditor::{Auditor, PublicConfig};
+    use signal_auditor::conformance::conformance_key;
+
+    let recording = record_vectors(7);
+    let key = conformance_key();
+    let auditor = Auditor {
+        config: PublicConfig {
+            mode: DeploymentMode::ThirdPartyAuditing,
+            sig_key: VerifyingKey::from_bytes(&recording.sig_key).unwrap(),
+            vrf_key: VerifyingKey::from_bytes(&recording.vrf_key).unwrap(),
+            auditor_key: key.verifying_key(),
+        },
+        key,
+    };
+    for response in &recording.responses {
+        let head = &response.head;
+        let signed = auditor.sign_at_time(head.root, head.tree_size, head.timestamp);
+        assert_eq!(signed.signature, head.signature);
+    }
+}
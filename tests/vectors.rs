@@ -30,6 +30,7 @@ fn test_should_succeed() {
         log.apply_update(update).unwrap();
         assert_eq!(log.log_root().unwrap().to_vec(), expected_root);
     }
+    log.verify_consistency().unwrap();
 }
 
 #[test]
@@ -78,4 +79,94 @@ mod signing {
         let sig = auditor.sign_at_time(head, vector.tree_size, vector.timestamp);
         assert_eq!(sig.signature, vector.signature);
     }
+
+    #[tokio::test]
+    async fn test_statement_domain_separation() {
+        use ed25519_dalek::{Signature, Verifier};
+        use signal_auditor::auditor::STATEMENT_CONTEXT;
+
+        let vector = VECTORS.signature.clone().unwrap();
+        let key = SigningKey::from_pkcs8_der(vector.auditor_priv_key.as_slice()).unwrap();
+        let config = PublicConfig {
+            mode: (vector.deployment_mode as u8).try_into().unwrap(),
+            sig_key: VerifyingKey::from_public_key_der(vector.sig_pub_key.as_slice()).unwrap(),
+            vrf_key: VerifyingKey::from_public_key_der(vector.vrf_pub_key.as_slice()).unwrap(),
+            auditor_key: key.verifying_key(),
+        };
+        let auditor = Auditor { config, key };
+
+        let statement = b"{}";
+        let sig = auditor.sign_statement(statement).await.unwrap();
+        let sig = Signature::from_slice(&sig).unwrap();
+
+        let mut msg = STATEMENT_CONTEXT.to_vec();
+        msg.extend_from_slice(statement);
+        assert!(auditor.config.auditor_key.verify(&msg, &sig).is_ok());
+        assert!(auditor.config.auditor_key.verify(statement, &sig).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_transcript() {
+        use signal_auditor::auditor::VerifyError;
+        use signal_auditor::transcript::{Transcript, TranscriptError};
+
+        let vector = VECTORS.signature.clone().unwrap();
+        let key = SigningKey::from_pkcs8_der(vector.auditor_priv_key.as_slice()).unwrap();
+        let config = PublicConfig {
+            mode: (vector.deployment_mode as u8).try_into().unwrap(),
+            sig_key: VerifyingKey::from_public_key_der(vector.sig_pub_key.as_slice()).unwrap(),
+            vrf_key: VerifyingKey::from_public_key_der(vector.vrf_pub_key.as_slice()).unwrap(),
+            auditor_key: key.verifying_key(),
+        };
+        let auditor = Auditor { config, key };
+
+        // Export the second half of the log, starting from the first half
+        let vectors = VECTORS.should_succeed.clone().unwrap().updates;
+        let (before, range) = vectors.split_at(vectors.len() / 2);
+        let mut checkpoint = TransparencyLog::new();
+        for vector in before {
+            checkpoint
+                .apply_update(vector.update.clone().unwrap())
+                .unwrap();
+        }
+        let encoded = range
+            .iter()
+            .map(|vector| vector.update.as_ref().unwrap().encode_to_vec())
+            .collect();
+        let transcript = Transcript::sign(&auditor, &checkpoint, encoded)
+            .await
+            .unwrap();
+
+        // Transcripts survive serialization
+        let json = serde_json::to_string(&transcript).unwrap();
+        let transcript: Transcript = serde_json::from_str(&json).unwrap();
+        let log = transcript.verify(&auditor.config.auditor_key).unwrap();
+        assert_eq!(log.size(), vectors.len() as u64);
+        assert_eq!(
+            log.log_root().unwrap().to_vec(),
+            range.last().unwrap().log_root
+        );
+
+        let other_key = SigningKey::from_bytes(&[7u8; 32]).verifying_key();
+        assert!(matches!(
+            transcript.verify(&other_key),
+            Err(TranscriptError::UnexpectedAuditorKey(_))
+        ));
+
+        let mut tampered = transcript.clone();
+        tampered.updates[0].0[3] ^= 1;
+        assert!(matches!(
+            tampered.verify(&auditor.config.auditor_key),
+            Err(TranscriptError::InvalidSignature(
+                VerifyError::InvalidStatementSignature
+            ))
+        ));
+
+        let mut tampered = transcript.clone();
+        tampered.head.timestamp += 1;
+        assert!(matches!(
+            tampered.verify(&auditor.config.auditor_key),
+            Err(TranscriptError::InvalidSignature(_))
+        ));
+    }
 }